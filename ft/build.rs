@@ -0,0 +1,96 @@
+//! Turns the assets of the default metadata into files `src/metadata.rs` includes:
+//!   - `icon.uri`: `assets/icon.svg` minified into a `data:image/svg+xml` URI.
+//!   - `reference.sha256`: the sha256 of `assets/reference.json`.
+//!   - `reference.uri`: the `ipfs://` URI of `assets/reference.json`, as
+//!     `ipfs add --cid-version 1 --raw-leaves` pins it.
+use std::path::Path;
+use std::{env, fs};
+
+use sha2::{Digest, Sha256};
+
+const ICON: &str = "assets/icon.svg";
+const REFERENCE: &str = "assets/reference.json";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", ICON);
+    println!("cargo:rerun-if-changed={}", REFERENCE);
+    // The NEP-330 link of `src/standards.rs`.
+    println!("cargo:rerun-if-env-changed=NEP330_LINK");
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+
+    let svg = fs::read_to_string(ICON).expect("Can't read the icon");
+    fs::write(out_dir.join("icon.uri"), svg_data_uri(&minify_svg(&svg))).unwrap();
+
+    let reference = fs::read(REFERENCE).expect("Can't read the reference");
+    let hash = Sha256::digest(&reference);
+    fs::write(out_dir.join("reference.sha256"), hash).unwrap();
+    fs::write(out_dir.join("reference.uri"), format!("ipfs://{}", raw_cid(&hash))).unwrap();
+}
+
+/// Drops the comments and the whitespace between tags, and collapses the other runs of
+/// whitespace into a single space.
+fn minify_svg(svg: &str) -> String {
+    let mut rest = svg;
+    let mut stripped = String::with_capacity(svg.len());
+    while let Some(start) = rest.find("<!--") {
+        stripped.push_str(&rest[..start]);
+        let end = rest[start..].find("-->").expect("Unterminated comment in the icon");
+        rest = &rest[start + end + 3..];
+    }
+    stripped.push_str(rest);
+
+    let mut minified = String::with_capacity(stripped.len());
+    for word in stripped.split_whitespace() {
+        let between_tags = minified.ends_with('>') && word.starts_with('<');
+        if !minified.is_empty() && !between_tags {
+            minified.push(' ');
+        }
+        minified.push_str(word);
+    }
+    minified
+}
+
+/// Percent-encodes the characters that can't appear as is in a URI. Double quotes become single
+/// quotes, which is shorter and valid in XML.
+fn svg_data_uri(svg: &str) -> String {
+    let mut uri = String::from("data:image/svg+xml,");
+    for c in svg.chars() {
+        match c {
+            '"' => uri.push('\''),
+            '%' | '#' | '<' | '>' | '{' | '}' | '|' | '\\' | '^' | '`' => {
+                uri.push_str(&format!("%{:02X}", c as u32))
+            }
+            c if c.is_ascii() && !c.is_ascii_control() => uri.push(c),
+            c => {
+                let mut buf = [0; 4];
+                for byte in c.encode_utf8(&mut buf).bytes() {
+                    uri.push_str(&format!("%{:02X}", byte));
+                }
+            }
+        }
+    }
+    uri
+}
+
+/// CIDv1 of a single raw block, in the default base32 multibase.
+fn raw_cid(sha256: &[u8]) -> String {
+    // version 1, raw codec, sha2-256 multihash of 32 bytes
+    let mut bytes = vec![0x01, 0x55, 0x12, 0x20];
+    bytes.extend_from_slice(sha256);
+    let alphabet = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut cid = String::from("b");
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            cid.push(alphabet[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        cid.push(alphabet[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    cid
+}
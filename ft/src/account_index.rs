@@ -0,0 +1,67 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, UnorderedSet};
+use near_sdk::json_types::U128;
+use near_sdk::{env, AccountId, IntoStorageKey};
+
+/// Index of numeric record ids (escrows, streams, ...) per account, so records can be listed by
+/// the accounts involved without iterating over all of them.
+/// Every account gets its own `UnorderedSet`, stored under `prefix + sha256(account_id)`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountIndex {
+    prefix: Vec<u8>,
+    ids: LookupMap<AccountId, UnorderedSet<u64>>,
+}
+
+impl AccountIndex {
+    pub fn new<S>(prefix: S) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        let prefix = prefix.into_storage_key();
+        Self { ids: LookupMap::new(prefix.clone()), prefix }
+    }
+
+    pub fn insert(&mut self, account_id: &AccountId, id: u64) {
+        let mut ids = self.ids.get(account_id).unwrap_or_else(|| {
+            let mut set_prefix = self.prefix.clone();
+            set_prefix.push(b's');
+            set_prefix.extend(env::sha256(account_id.as_bytes()));
+            UnorderedSet::new(set_prefix)
+        });
+        ids.insert(&id);
+        self.ids.insert(account_id, &ids);
+    }
+
+    pub fn remove(&mut self, account_id: &AccountId, id: u64) {
+        if let Some(mut ids) = self.ids.get(account_id) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.ids.remove(account_id);
+            } else {
+                self.ids.insert(account_id, &ids);
+            }
+        }
+    }
+
+    pub fn len(&self, account_id: &AccountId) -> u64 {
+        self.ids.get(account_id).map(|ids| ids.len()).unwrap_or(0)
+    }
+
+    /// Returns up to `limit` ids of `account_id` starting at `from_index`, in insertion order
+    /// (removals may reorder the remaining ids).
+    pub fn page(
+        &self,
+        account_id: &AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<u64> {
+        match self.ids.get(account_id) {
+            Some(ids) => ids
+                .iter()
+                .skip(from_index.map(|index| index.0 as usize).unwrap_or(0))
+                .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+                .collect(),
+            None => vec![],
+        }
+    }
+}
@@ -0,0 +1,381 @@
+/*!
+Dutch auctions for price discovery on large allocations.
+
+The price of an auction drops linearly from `start_price` at `start_at` to `floor_price` at
+`start_at + duration`. Bidders commit NEAR with `commit_auction` while it runs. The auction clears
+as soon as the commitments buy the whole supply at the current price, or when the duration is over.
+Commitments above what the whole supply costs at the current price are refunded right away.
+
+An auction that didn't sell out by a commitment clears at the price at which the standing
+commitments buy exactly the supply, or at the floor price if that's higher. That way commitments
+made early at high prices never buy more than the supply once the price has dropped.
+
+Every bidder pays the same clearing price: `claim` mints the tokens their commitment buys at that
+price and refunds the rest. The proceeds join the sale proceeds the owner withdraws with
+`withdraw_sale_proceeds`.
+
+Prices are in yoctoNEAR per whole token, like the sale rounds.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance, Promise};
+
+use crate::events::{AuctionClaim, AuctionCommit, AuctionCreate};
+use crate::math::U256;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DutchAuction {
+    pub auction_id: U64,
+    /// yoctoNEAR per whole token.
+    pub start_price: U128,
+    pub floor_price: U128,
+    /// Timestamps in nanoseconds.
+    pub start_at: U64,
+    pub end_at: U64,
+    /// Tokens offered, in the smallest unit.
+    pub supply: U128,
+    pub total_committed: U128,
+    /// Set once the commitments bought the whole supply.
+    pub clearing_price: Option<U128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum AuctionStatus {
+    Upcoming,
+    Active,
+    Cleared,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionView {
+    #[serde(flatten)]
+    pub auction: DutchAuction,
+    pub status: AuctionStatus,
+    pub current_price: U128,
+}
+
+impl DutchAuction {
+    /// Price at `now`, falling linearly from the start price to the floor.
+    pub fn price_at(&self, now: u64) -> Balance {
+        let now = now.clamp(self.start_at.0, self.end_at.0);
+        let drop = U256::from(self.start_price.0 - self.floor_price.0)
+            * U256::from(now - self.start_at.0)
+            / U256::from(self.end_at.0 - self.start_at.0);
+        self.start_price.0 - drop.as_u128()
+    }
+
+    /// Price at which the standing commitments buy exactly the supply, rounded up, and at least
+    /// the floor price. `unit` is one whole token in the smallest unit.
+    fn filled_price(&self, unit: U256) -> Balance {
+        let supply = U256::from(self.supply.0);
+        let price = (U256::from(self.total_committed.0) * unit + supply - 1) / supply;
+        price.as_u128().max(self.floor_price.0)
+    }
+
+    fn status(&self, now: u64, unit: U256) -> AuctionStatus {
+        if now < self.start_at.0 {
+            AuctionStatus::Upcoming
+        } else if self.clearing_price.is_some()
+            || now >= self.end_at.0
+            || self.price_at(now) <= self.filled_price(unit)
+        {
+            AuctionStatus::Cleared
+        } else {
+            AuctionStatus::Active
+        }
+    }
+
+    /// Price everyone pays, once the auction has cleared.
+    fn final_price(&self, now: u64, unit: U256) -> Option<Balance> {
+        match self.status(now, unit) {
+            AuctionStatus::Cleared => Some(
+                self.clearing_price.map(|price| price.0).unwrap_or_else(|| self.filled_price(unit)),
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl Contract {
+    fn internal_unwrap_auction(&self, auction_id: U64) -> DutchAuction {
+        self.auctions.get(&auction_id.0).unwrap_or_else(|| env::panic_str("Auction not found"))
+    }
+
+    fn internal_auction_commitment(&self, auction_id: u64, account_id: &AccountId) -> Balance {
+        self.auction_commitments.get(&(auction_id, account_id.clone())).unwrap_or(0)
+    }
+
+    fn internal_auction_view(&self, auction: DutchAuction) -> AuctionView {
+        let now = env::block_timestamp();
+        let unit = self.internal_token_unit();
+        let current_price = auction.final_price(now, unit).unwrap_or_else(|| auction.price_at(now));
+        AuctionView {
+            status: auction.status(now, unit),
+            current_price: current_price.into(),
+            auction,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Creates a Dutch auction of `supply` tokens and returns its id. Only the owner can call
+    /// this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn create_auction(
+        &mut self,
+        start_price: U128,
+        floor_price: U128,
+        start_at: U64,
+        duration: U64,
+        supply: U128,
+    ) -> U64 {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(floor_price.0 > 0, "The floor price should be a positive number");
+        require!(start_price.0 >= floor_price.0, "The start price should not be below the floor");
+        require!(duration.0 > 0, "The duration should be a positive number");
+        require!(start_at.0 >= env::block_timestamp(), "The auction should start in the future");
+        require!(supply.0 > 0, "The supply should be a positive number");
+        let auction_id = self.next_auction_id;
+        self.next_auction_id += 1;
+        let auction = DutchAuction {
+            auction_id: auction_id.into(),
+            start_price,
+            floor_price,
+            start_at,
+            end_at: (start_at.0 + duration.0).into(),
+            supply,
+            total_committed: U128(0),
+            clearing_price: None,
+        };
+        self.auctions.insert(&auction_id, &auction);
+        AuctionCreate { auction: &auction }.emit();
+        auction.auction_id
+    }
+
+    /// Commits the attached NEAR to the auction. The part above what the remaining supply costs
+    /// at the current price is refunded. The bidder must be registered. Returns the commitment
+    /// that was kept.
+    #[payable]
+    pub fn commit_auction(&mut self, auction_id: U64) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let now = env::block_timestamp();
+        let mut auction = self.internal_unwrap_auction(auction_id);
+        let unit = self.internal_token_unit();
+        require!(auction.status(now, unit) == AuctionStatus::Active, "The auction is not active");
+        self.token.internal_unwrap_balance_of(&account_id);
+
+        let price = auction.price_at(now);
+        let supply_cost = (U256::from(auction.supply.0) * U256::from(price) / unit).as_u128();
+        let amount = deposit.min(supply_cost.saturating_sub(auction.total_committed.0));
+        require!(amount > 0, "The attached deposit should be a positive number");
+        auction.total_committed = (auction.total_committed.0 + amount).into();
+        if auction.total_committed.0 >= supply_cost {
+            auction.clearing_price = Some(price.into());
+        }
+        self.auctions.insert(&auction_id.0, &auction);
+        let commitment = self.internal_auction_commitment(auction_id.0, &account_id) + amount;
+        self.auction_commitments.insert(&(auction_id.0, account_id.clone()), &commitment);
+        AuctionCommit { auction_id, account_id: &account_id, amount: &U128(amount) }.emit();
+        if deposit > amount {
+            Promise::new(account_id).transfer(deposit - amount);
+        }
+        amount.into()
+    }
+
+    /// Mints the tokens the predecessor's commitment buys at the clearing price and refunds
+    /// the difference. Returns the tokens delivered.
+    pub fn claim(&mut self, auction_id: U64) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let auction = self.internal_unwrap_auction(auction_id);
+        let unit = self.internal_token_unit();
+        let price = auction
+            .final_price(env::block_timestamp(), unit)
+            .unwrap_or_else(|| env::panic_str("The auction has not cleared yet"));
+        let commitment = self
+            .auction_commitments
+            .remove(&(auction_id.0, account_id.clone()))
+            .unwrap_or_else(|| env::panic_str("Nothing to claim"));
+
+        let amount = (U256::from(commitment) * unit / U256::from(price)).as_u128();
+        // Rounded up, so the auction never undercharges.
+        let cost = ((U256::from(amount) * U256::from(price) + unit - 1) / unit).as_u128();
+        let refund = commitment - cost;
+        self.sale_proceeds += cost;
+        if amount > 0 {
+            self.internal_mint(&account_id, amount, "Dutch auction");
+        }
+        AuctionClaim {
+            auction_id,
+            account_id: &account_id,
+            amount: &U128(amount),
+            refund: &U128(refund),
+        }
+        .emit();
+        if refund > 0 {
+            Promise::new(account_id).transfer(refund);
+        }
+        amount.into()
+    }
+
+    pub fn auction(&self, auction_id: U64) -> Option<AuctionView> {
+        self.auctions.get(&auction_id.0).map(|auction| self.internal_auction_view(auction))
+    }
+
+    pub fn auctions(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<AuctionView> {
+        self.auctions
+            .values()
+            .skip(from_index.map(|index| index.0 as usize).unwrap_or(0))
+            .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+            .map(|auction| self.internal_auction_view(auction))
+            .collect()
+    }
+
+    /// Current price of the auction, or its clearing price once it has cleared.
+    pub fn auction_price(&self, auction_id: U64) -> U128 {
+        self.internal_auction_view(self.internal_unwrap_auction(auction_id)).current_price
+    }
+
+    /// NEAR committed by `account_id` to the auction and not claimed yet.
+    pub fn auction_commitment(&self, auction_id: U64, account_id: AccountId) -> U128 {
+        self.internal_auction_commitment(auction_id.0, &account_id).into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    use super::*;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    const ONE_TOKEN: Balance = 1_000_000_000_000_000_000_000_000;
+    const START: u64 = 1_000;
+    const DURATION: u64 = 1_000;
+
+    /// Auction 0 sells 100 tokens, from 10 NEAR down to 2 NEAR each.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        contract.token.internal_register_account(&accounts(3));
+        testing_env!(context.attached_deposit(1).build());
+        contract.create_auction(
+            U128(10 * ONE_NEAR),
+            U128(2 * ONE_NEAR),
+            U64(START),
+            U64(DURATION),
+            U128(100 * ONE_TOKEN),
+        );
+        (context, contract)
+    }
+
+    fn commit(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        bidder: usize,
+        deposit: Balance,
+        now: u64,
+    ) -> Balance {
+        testing_env!(context
+            .predecessor_account_id(accounts(bidder))
+            .attached_deposit(deposit)
+            .block_timestamp(now)
+            .build());
+        contract.commit_auction(U64(0)).0
+    }
+
+    fn claim(context: &mut VMContextBuilder, contract: &mut Contract, bidder: usize) -> Balance {
+        testing_env!(context.predecessor_account_id(accounts(bidder)).attached_deposit(0).build());
+        contract.claim(U64(0)).0
+    }
+
+    #[test]
+    fn test_price_declines_linearly() {
+        let (mut context, contract) = setup();
+        for (now, price) in [(0, 10), (START, 10), (START + DURATION / 4, 8), (START + DURATION, 2)]
+        {
+            testing_env!(context.block_timestamp(now).build());
+            assert_eq!(contract.auction_price(U64(0)).0, price * ONE_NEAR);
+        }
+    }
+
+    #[test]
+    fn test_sold_out_clears_at_current_price() {
+        let (mut context, mut contract) = setup();
+        assert_eq!(commit(&mut context, &mut contract, 2, 300 * ONE_NEAR, START), 300 * ONE_NEAR);
+        // Halfway, the whole supply costs 600 NEAR, so only 300 more NEAR are kept.
+        let halfway = START + DURATION / 2;
+        assert_eq!(commit(&mut context, &mut contract, 3, 500 * ONE_NEAR, halfway), 300 * ONE_NEAR);
+
+        testing_env!(context.block_timestamp(halfway + 1).build());
+        let auction = contract.auction(U64(0)).unwrap();
+        assert_eq!(auction.status, AuctionStatus::Cleared);
+        assert_eq!(auction.current_price.0, 6 * ONE_NEAR);
+        assert_eq!(auction.auction.total_committed.0, 600 * ONE_NEAR);
+
+        assert_eq!(claim(&mut context, &mut contract, 2), 50 * ONE_TOKEN);
+        assert_eq!(claim(&mut context, &mut contract, 3), 50 * ONE_TOKEN);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY + 100 * ONE_TOKEN);
+        assert_eq!(contract.sale_proceeds().0, 600 * ONE_NEAR);
+    }
+
+    #[test]
+    fn test_undersubscribed_clears_at_floor_with_refund() {
+        let (mut context, mut contract) = setup();
+        commit(&mut context, &mut contract, 2, 15 * ONE_NEAR, START);
+        testing_env!(context.block_timestamp(START + DURATION).build());
+        assert_eq!(contract.auction_price(U64(0)).0, 2 * ONE_NEAR);
+
+        // 15 NEAR buy 7.5 tokens at the floor price, nothing is left to refund.
+        assert_eq!(claim(&mut context, &mut contract, 2), 15 * ONE_TOKEN / 2);
+        assert_eq!(contract.auction_commitment(U64(0), accounts(2)).0, 0);
+    }
+
+    #[test]
+    fn test_early_commitments_never_buy_more_than_the_supply() {
+        let (mut context, mut contract) = setup();
+        // 300 NEAR at 10 NEAR per token, but 150 tokens at the floor price.
+        commit(&mut context, &mut contract, 2, 300 * ONE_NEAR, START);
+        testing_env!(context.block_timestamp(START + DURATION).build());
+        let auction = contract.auction(U64(0)).unwrap();
+        assert_eq!(auction.status, AuctionStatus::Cleared);
+        assert_eq!(auction.current_price.0, 3 * ONE_NEAR);
+
+        assert_eq!(claim(&mut context, &mut contract, 2), 100 * ONE_TOKEN);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY + 100 * ONE_TOKEN);
+        assert_eq!(contract.sale_proceeds().0, 300 * ONE_NEAR);
+    }
+
+    #[test]
+    fn test_clears_once_the_price_meets_the_commitments() {
+        let (mut context, mut contract) = setup();
+        commit(&mut context, &mut contract, 2, 400 * ONE_NEAR, START);
+        // At 4 NEAR per token the 400 NEAR buy the whole supply, before the duration is over.
+        let at_four = START + DURATION * 3 / 4;
+        testing_env!(context.block_timestamp(at_four - 1).build());
+        assert_eq!(contract.auction(U64(0)).unwrap().status, AuctionStatus::Active);
+        testing_env!(context.block_timestamp(at_four).build());
+        assert_eq!(contract.auction(U64(0)).unwrap().status, AuctionStatus::Cleared);
+        assert_eq!(contract.auction_price(U64(0)).0, 4 * ONE_NEAR);
+        assert_eq!(claim(&mut context, &mut contract, 2), 100 * ONE_TOKEN);
+    }
+
+    #[test]
+    #[should_panic(expected = "The auction has not cleared yet")]
+    fn test_claim_before_clearing() {
+        let (mut context, mut contract) = setup();
+        commit(&mut context, &mut contract, 2, ONE_NEAR, START);
+        claim(&mut context, &mut contract, 2);
+    }
+}
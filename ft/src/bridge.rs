@@ -0,0 +1,330 @@
+/*!
+Lock/unlock bridge endpoint for ESTATES on other chains.
+
+`bridge_out` locks tokens on the contract account and logs a `bridge_out` event with an
+incrementing nonce, which the bridge operators watch to mint the wrapped tokens on the
+destination chain. Coming back, `bridge_in` unlocks tokens to a receiver on the strength of an
+attestation signed by at least `threshold` of the operator ed25519 keys. Every source chain nonce
+can only be used once, and no more can come back than is locked.
+
+The operators sign the borsh serialization of a [`BridgeMessage`], which names this contract so
+that attestations can't be replayed against another deployment. `bridge_in_message` returns it.
+Anyone can relay a signed attestation.
+*/
+use ed25519_dalek::Verifier;
+use near_sdk::borsh::{self, BorshSerialize};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, CurveType, PublicKey};
+
+use crate::events::{BridgeIn, BridgeOut};
+use crate::*;
+
+const ED25519_SIGNATURE_LEN: usize = 64;
+
+/// What the operators attest to when tokens come back from `source_chain`.
+#[derive(BorshSerialize)]
+pub struct BridgeMessage {
+    pub bridge_account_id: AccountId,
+    pub source_chain: String,
+    pub source_nonce: u64,
+    pub receiver_id: AccountId,
+    pub amount: Balance,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OperatorSignature {
+    pub public_key: PublicKey,
+    pub signature: Base64VecU8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BridgeProof {
+    pub source_chain: String,
+    pub source_nonce: U64,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+    pub signatures: Vec<OperatorSignature>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BridgeOperators {
+    pub public_keys: Vec<PublicKey>,
+    pub threshold: u32,
+}
+
+/// Whether `signature` is a valid ed25519 signature of `message` by `public_key`.
+fn verify_ed25519(public_key: &PublicKey, message: &[u8], signature: &[u8]) -> bool {
+    if public_key.curve_type() != CurveType::ED25519 || signature.len() != ED25519_SIGNATURE_LEN {
+        return false;
+    }
+    // The first byte of the key data is the curve type.
+    let public_key = match ed25519_dalek::PublicKey::from_bytes(&public_key.as_bytes()[1..]) {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+    match ed25519_dalek::Signature::from_bytes(signature) {
+        Ok(signature) => public_key.verify(message, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+impl Contract {
+    fn internal_bridge_message(&self, proof: &BridgeProof) -> Vec<u8> {
+        BridgeMessage {
+            bridge_account_id: env::current_account_id(),
+            source_chain: proof.source_chain.clone(),
+            source_nonce: proof.source_nonce.0,
+            receiver_id: proof.receiver_id.clone(),
+            amount: proof.amount.0,
+        }
+        .try_to_vec()
+        .unwrap()
+    }
+
+    /// Panics unless enough distinct operators signed the proof.
+    fn internal_verify_bridge_proof(&self, proof: &BridgeProof) {
+        let message = self.internal_bridge_message(proof);
+        let mut signers: Vec<&PublicKey> = vec![];
+        for OperatorSignature { public_key, signature } in proof.signatures.iter() {
+            require!(self.bridge_operators.contains(public_key), "Unknown operator key");
+            require!(!signers.contains(&public_key), "Duplicate operator signature");
+            require!(
+                verify_ed25519(public_key, &message, &signature.0),
+                "Invalid operator signature"
+            );
+            signers.push(public_key);
+        }
+        require!(
+            self.bridge_threshold > 0 && signers.len() >= self.bridge_threshold as usize,
+            "Not enough operator signatures"
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Replaces the operator keys, `threshold` of which have to sign every `bridge_in`. Only the
+    /// owner can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_bridge_operators(&mut self, public_keys: Vec<PublicKey>, threshold: u32) {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(threshold > 0, "The threshold should be a positive number");
+        require!(
+            public_keys.iter().all(|public_key| public_key.curve_type() == CurveType::ED25519),
+            "Operator keys should be ed25519 keys"
+        );
+        self.bridge_operators.clear();
+        for public_key in public_keys.iter() {
+            self.bridge_operators.insert(public_key);
+        }
+        require!(
+            threshold as u64 <= self.bridge_operators.len(),
+            "The threshold exceeds the number of operators"
+        );
+        self.bridge_threshold = threshold;
+    }
+
+    /// Locks `amount` tokens of the predecessor to be minted to `dest_address` on `dest_chain`,
+    /// and returns the nonce of the transfer. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn bridge_out(&mut self, dest_chain: String, dest_address: String, amount: U128) -> U64 {
+        assert_one_yocto();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        require!(!dest_chain.is_empty(), "The destination chain should not be empty");
+        require!(!dest_address.is_empty(), "The destination address should not be empty");
+        let sender_id = env::predecessor_account_id();
+        let nonce = self.next_bridge_nonce;
+        self.next_bridge_nonce += 1;
+        self.internal_transfer(
+            &sender_id,
+            &env::current_account_id(),
+            amount.0,
+            Some(format!("bridge out {}", nonce)),
+        );
+        self.bridge_locked += amount.0;
+        BridgeOut {
+            nonce: nonce.into(),
+            sender_id: &sender_id,
+            dest_chain: &dest_chain,
+            dest_address: &dest_address,
+            amount: &amount,
+        }
+        .emit();
+        nonce.into()
+    }
+
+    /// Unlocks tokens to the receiver of an attestation signed by the operators. Every source
+    /// chain nonce is accepted once. Can be called by anyone.
+    pub fn bridge_in(&mut self, proof: BridgeProof) {
+        let key = (proof.source_chain.clone(), proof.source_nonce.0);
+        require!(!self.bridge_used_nonces.contains(&key), "The nonce was already used");
+        self.internal_verify_bridge_proof(&proof);
+        require!(proof.amount.0 > 0, "The amount should be a positive number");
+        require!(proof.amount.0 <= self.bridge_locked, "The amount exceeds the locked tokens");
+        self.bridge_used_nonces.insert(&key);
+        self.bridge_locked -= proof.amount.0;
+        self.internal_transfer(
+            &env::current_account_id(),
+            &proof.receiver_id,
+            proof.amount.0,
+            Some(format!("bridge in {} {}", proof.source_chain, proof.source_nonce.0)),
+        );
+        BridgeIn {
+            source_chain: &proof.source_chain,
+            source_nonce: proof.source_nonce,
+            receiver_id: &proof.receiver_id,
+            amount: &proof.amount,
+        }
+        .emit();
+    }
+
+    /// Bytes the operators sign for `bridge_in`.
+    pub fn bridge_in_message(
+        &self,
+        source_chain: String,
+        source_nonce: U64,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> Base64VecU8 {
+        let proof =
+            BridgeProof { source_chain, source_nonce, receiver_id, amount, signatures: vec![] };
+        self.internal_bridge_message(&proof).into()
+    }
+
+    pub fn bridge_operators(&self) -> BridgeOperators {
+        BridgeOperators {
+            public_keys: self.bridge_operators.to_vec(),
+            threshold: self.bridge_threshold,
+        }
+    }
+
+    /// Tokens locked by `bridge_out` that can still come back.
+    pub fn bridge_locked(&self) -> U128 {
+        self.bridge_locked.into()
+    }
+
+    /// Nonce the next `bridge_out` gets.
+    pub fn bridge_next_nonce(&self) -> U64 {
+        self.next_bridge_nonce.into()
+    }
+
+    pub fn is_bridge_nonce_used(&self, source_chain: String, source_nonce: U64) -> bool {
+        self.bridge_used_nonces.contains(&(source_chain, source_nonce.0))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::convert::TryFrom;
+
+    use ed25519_dalek::{Keypair, SecretKey, Signer};
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    const AMOUNT: Balance = 1_000;
+
+    /// Local stand-in for an operator, with a key derived from `seed`.
+    struct LocalSigner(Keypair);
+
+    impl LocalSigner {
+        fn new(seed: u8) -> Self {
+            let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+            let public = (&secret).into();
+            Self(Keypair { secret, public })
+        }
+
+        fn public_key(&self) -> PublicKey {
+            let mut data = vec![CurveType::ED25519 as u8];
+            data.extend(self.0.public.as_bytes());
+            PublicKey::try_from(data).unwrap()
+        }
+
+        fn sign(&self, message: &[u8]) -> OperatorSignature {
+            OperatorSignature {
+                public_key: self.public_key(),
+                signature: self.0.sign(message).to_bytes().to_vec().into(),
+            }
+        }
+    }
+
+    /// Three operators with a threshold of 2. accounts(1) bridged `AMOUNT` out.
+    fn setup() -> (VMContextBuilder, Contract, Vec<LocalSigner>) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(1).build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        let signers: Vec<_> = (1..4).map(LocalSigner::new).collect();
+        contract.set_bridge_operators(signers.iter().map(LocalSigner::public_key).collect(), 2);
+        let nonce = contract.bridge_out("ethereum".into(), "0xabc".into(), AMOUNT.into());
+        assert_eq!(nonce.0, 0);
+        (context, contract, signers)
+    }
+
+    fn proof(contract: &Contract, signers: &[&LocalSigner], nonce: u64) -> BridgeProof {
+        let message =
+            contract.bridge_in_message("ethereum".into(), nonce.into(), accounts(2), AMOUNT.into());
+        BridgeProof {
+            source_chain: "ethereum".into(),
+            source_nonce: nonce.into(),
+            receiver_id: accounts(2),
+            amount: AMOUNT.into(),
+            signatures: signers.iter().map(|signer| signer.sign(&message.0)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_bridge_round_trip() {
+        let (_context, mut contract, signers) = setup();
+        assert_eq!(contract.bridge_locked().0, AMOUNT);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, AMOUNT);
+        assert_eq!(contract.bridge_next_nonce().0, 1);
+
+        contract.bridge_in(proof(&contract, &[&signers[0], &signers[2]], 7));
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, AMOUNT);
+        assert_eq!(contract.bridge_locked().0, 0);
+        assert!(contract.is_bridge_nonce_used("ethereum".into(), U64(7)));
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    #[test]
+    #[should_panic(expected = "The nonce was already used")]
+    fn test_replay() {
+        let (_context, mut contract, signers) = setup();
+        contract.bridge_out("ethereum".into(), "0xabc".into(), AMOUNT.into());
+        let proof = proof(&contract, &[&signers[0], &signers[1]], 7);
+        contract.bridge_in(proof.clone());
+        contract.bridge_in(proof);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough operator signatures")]
+    fn test_below_threshold() {
+        let (_context, mut contract, signers) = setup();
+        contract.bridge_in(proof(&contract, &[&signers[1]], 7));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid operator signature")]
+    fn test_tampered_amount() {
+        let (_context, mut contract, signers) = setup();
+        let mut proof = proof(&contract, &[&signers[0], &signers[1]], 7);
+        proof.amount = U128(AMOUNT - 1);
+        contract.bridge_in(proof);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown operator key")]
+    fn test_unknown_operator() {
+        let (_context, mut contract, signers) = setup();
+        let outsider = LocalSigner::new(9);
+        contract.bridge_in(proof(&contract, &[&signers[0], &outsider], 7));
+    }
+}
@@ -0,0 +1,387 @@
+/*!
+Buyback-and-burn of ESTATES with the NEAR of the sale proceeds.
+
+The owner configures a DEX and calls `buyback`, which takes `amount_in` yoctoNEAR out of the sale
+proceeds and attaches them to a `swap_near` call on the DEX, with `min_out` as the slippage bound.
+The DEX delivers the ESTATES it bought with `ft_transfer_call` and a `{"buyback": {}}` message,
+and `on_buyback` burns exactly what was delivered that way, whatever the DEX returns. Other
+tokens on the contract account back the in-contract deals and are never touched.
+
+If the swap fails, the NEAR comes back with the refund of the failed call and `on_buyback`
+returns it to the sale proceeds. A swap that succeeds but delivers less than `min_out` broke the
+slippage bound: its NEAR is spent, what it delivered is burned, and it's logged as a failed
+buyback. Only one buyback can be in flight at a time.
+
+If `on_buyback` itself fails, e.g. because it ran out of gas, the buyback stays in flight. Once
+[`BUYBACK_TIMEOUT`] has passed, and no callback can still run, the owner settles it with
+`reset_buyback`, telling whether the swap went through: the delivered ESTATES are burned, and
+`amount_in` is either counted as spent or returned to the sale proceeds.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, near_bindgen, require, AccountId, Gas, Promise,
+    PromiseOrValue, PromiseResult,
+};
+
+use crate::events::{Buyback, BuybackFail};
+use crate::*;
+
+const GAS_FOR_SWAP: Gas = Gas(80_000_000_000_000);
+const GAS_FOR_ON_BUYBACK: Gas = Gas(15_000_000_000_000);
+
+/// Time after which a buyback still in flight can only be one whose callback failed.
+pub const BUYBACK_TIMEOUT: u64 = 24 * 3600 * 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct BuybackInFlight {
+    amount_in: Balance,
+    min_out: Balance,
+    started_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BuybackStats {
+    /// yoctoNEAR spent on successful swaps.
+    pub spent: U128,
+    /// ESTATES bought back and burned.
+    pub burned: U128,
+}
+
+// Defining cross-contract interface. This allows to create a new promise.
+#[ext_contract(ext_dex)]
+pub trait Dex {
+    /// Swaps the attached NEAR for at least `min_out` of `token_out`, sent to the predecessor
+    /// with `ft_transfer_call` and `msg`. Returns the amount sent.
+    fn swap_near(
+        &mut self,
+        token_out: AccountId,
+        min_out: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+}
+
+#[ext_contract(ext_buyback_resolver)]
+pub trait BuybackResolver {
+    fn on_buyback(&mut self, amount_in: U128, min_out: U128) -> U128;
+}
+
+impl Contract {
+    /// Books ESTATES the DEX delivered with a buyback message.
+    pub(crate) fn internal_receive_buyback(&mut self, sender_id: &AccountId, amount: Balance) {
+        require!(self.buyback_dex.as_ref() == Some(sender_id), "Only the buyback DEX can deliver");
+        let received =
+            self.buyback_received.unwrap_or_else(|| env::panic_str("No buyback is in flight"));
+        self.buyback_received = Some(received + amount);
+    }
+
+    /// Burns the ESTATES delivered to the buyback in flight and settles `amount_in`: spent if
+    /// the swap went through, back into the sale proceeds if it didn't.
+    fn internal_settle_buyback(&mut self, amount_in: U128, min_out: U128, swapped: bool) -> U128 {
+        let amount_out = self.buyback_received.take().unwrap_or(0);
+        self.buyback_in_flight = None;
+        if amount_out > 0 {
+            self.internal_burn(&env::current_account_id(), amount_out, "Buyback");
+            self.buyback_burned += amount_out;
+        }
+        if swapped {
+            self.buyback_spent += amount_in.0;
+        } else {
+            self.sale_proceeds += amount_in.0;
+        }
+        if swapped && amount_out >= min_out.0 {
+            Buyback { amount_in: &amount_in, amount_out: &U128(amount_out) }.emit();
+        } else {
+            BuybackFail { amount_in: &amount_in, min_out: &min_out, amount_out: &U128(amount_out) }
+                .emit();
+        }
+        amount_out.into()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the DEX buybacks swap on, `None` disables buybacks. Only the owner can call this
+    /// method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_buyback_dex(&mut self, dex_id: Option<AccountId>) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.buyback_dex = dex_id;
+    }
+
+    /// Swaps `amount_in` yoctoNEAR of the sale proceeds for at least `min_out` ESTATES on the
+    /// DEX, and burns them. Only the owner can call this method. Requires exactly 1 yoctoNEAR
+    /// attached.
+    #[payable]
+    pub fn buyback(&mut self, amount_in: U128, min_out: U128) -> Promise {
+        assert_one_yocto();
+        self.assert_owner();
+        let dex_id =
+            self.buyback_dex.clone().unwrap_or_else(|| env::panic_str("No DEX is configured"));
+        require!(amount_in.0 > 0, "The amount should be a positive number");
+        require!(min_out.0 > 0, "The minimum should be a positive number");
+        require!(amount_in.0 <= self.sale_proceeds, "The amount exceeds the sale proceeds");
+        require!(self.buyback_received.is_none(), "A buyback is already in flight");
+        require!(env::prepaid_gas() >= GAS_FOR_SWAP + GAS_FOR_ON_BUYBACK, "More gas is required");
+        self.sale_proceeds -= amount_in.0;
+        self.buyback_received = Some(0);
+        self.buyback_in_flight = Some(BuybackInFlight {
+            amount_in: amount_in.0,
+            min_out: min_out.0,
+            started_at: env::block_timestamp(),
+        });
+        let msg = near_sdk::serde_json::json!({ "buyback": {} }).to_string();
+        ext_dex::ext(dex_id)
+            .with_attached_deposit(amount_in.0)
+            .with_static_gas(GAS_FOR_SWAP)
+            .swap_near(env::current_account_id(), min_out, msg)
+            .then(
+                ext_buyback_resolver::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_BUYBACK)
+                    .on_buyback(amount_in, min_out),
+            )
+    }
+
+    /// Burns the ESTATES the DEX delivered and returns their amount. A failed swap puts
+    /// `amount_in` back into the sale proceeds.
+    #[private]
+    pub fn on_buyback(&mut self, amount_in: U128, min_out: U128) -> U128 {
+        let swapped = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => true,
+            PromiseResult::Failed => false,
+        };
+        if self.buyback_in_flight.is_none() {
+            // Already settled with `reset_buyback`.
+            return U128(0);
+        }
+        self.internal_settle_buyback(amount_in, min_out, swapped)
+    }
+
+    /// Settles a buyback whose `on_buyback` failed, at least [`BUYBACK_TIMEOUT`] after it
+    /// started. `swapped` tells whether the swap on the DEX went through, i.e. whether the NEAR
+    /// was spent. Returns the ESTATES burned. Only the owner can call this method. Requires
+    /// exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn reset_buyback(&mut self, swapped: bool) -> U128 {
+        assert_one_yocto();
+        self.assert_owner();
+        let in_flight = self
+            .buyback_in_flight
+            .as_ref()
+            .unwrap_or_else(|| env::panic_str("No buyback is in flight"));
+        require!(
+            env::block_timestamp() >= in_flight.started_at + BUYBACK_TIMEOUT,
+            "The callback of the buyback may still run"
+        );
+        let (amount_in, min_out) = (U128(in_flight.amount_in), U128(in_flight.min_out));
+        self.internal_settle_buyback(amount_in, min_out, swapped)
+    }
+
+    pub fn buyback_dex(&self) -> Option<AccountId> {
+        self.buyback_dex.clone()
+    }
+
+    pub fn buyback_stats(&self) -> BuybackStats {
+        BuybackStats { spent: self.buyback_spent.into(), burned: self.buyback_burned.into() }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::test_utils::{accounts, get_created_receipts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig, ONE_NEAR};
+
+    use super::*;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    const AMOUNT_OUT: Balance = 1_000;
+
+    /// The contract has 10 NEAR of sale proceeds and swaps 4 of them on accounts(4), which holds
+    /// ESTATES to sell.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(1).build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(4));
+        contract.ft_transfer(accounts(4), (10 * AMOUNT_OUT).into(), None);
+        contract.sale_proceeds = 10 * ONE_NEAR;
+        contract.set_buyback_dex(Some(accounts(4)));
+        let _ = contract.buyback(U128(4 * ONE_NEAR), U128(AMOUNT_OUT));
+        assert_eq!(get_created_receipts()[0].receiver_id, accounts(4));
+        assert_eq!(contract.sale_proceeds().0, 6 * ONE_NEAR);
+        (context, contract)
+    }
+
+    /// Moves `amount` from `sender` to the contract account with a buyback message, the way
+    /// `ft_transfer_call` does.
+    fn deliver(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        sender: usize,
+        amount: Balance,
+    ) {
+        contract.token.internal_transfer(&accounts(sender), &accounts(0), amount, None);
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        let msg = r#"{"buyback": {}}"#.to_string();
+        let _ = contract.ft_on_transfer(accounts(sender), amount.into(), msg);
+    }
+
+    fn resolve(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        result: PromiseResult,
+    ) -> Balance {
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+        contract.on_buyback(U128(4 * ONE_NEAR), U128(AMOUNT_OUT)).0
+    }
+
+    #[test]
+    fn test_buyback_burns_delivered_tokens() {
+        let (mut context, mut contract) = setup();
+        // Tokens of an escrow, which the buyback must not burn.
+        contract.token.internal_transfer(&accounts(1), &accounts(0), 5 * AMOUNT_OUT, None);
+        deliver(&mut context, &mut contract, 4, AMOUNT_OUT);
+        // The DEX claims more than it delivered.
+        let value = near_sdk::serde_json::to_vec(&U128(3 * AMOUNT_OUT)).unwrap();
+        assert_eq!(
+            resolve(&mut context, &mut contract, PromiseResult::Successful(value)),
+            AMOUNT_OUT
+        );
+
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 5 * AMOUNT_OUT);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY - AMOUNT_OUT);
+        assert!(get_logs()[0].contains(r#""event":"ft_burn""#));
+        assert!(get_logs()[1].contains(r#""event":"buyback""#));
+        let stats = contract.buyback_stats();
+        assert_eq!((stats.spent.0, stats.burned.0), (4 * ONE_NEAR, AMOUNT_OUT));
+        assert_eq!(contract.buyback_received, None);
+    }
+
+    #[test]
+    fn test_short_swap_is_not_rolled_back() {
+        let (mut context, mut contract) = setup();
+        deliver(&mut context, &mut contract, 4, AMOUNT_OUT / 2);
+        // A return value that can't be parsed doesn't matter either.
+        let value = b"nonsense".to_vec();
+        assert_eq!(
+            resolve(&mut context, &mut contract, PromiseResult::Successful(value)),
+            AMOUNT_OUT / 2
+        );
+
+        // The NEAR was spent, the tokens delivered are burned.
+        assert_eq!(contract.sale_proceeds().0, 6 * ONE_NEAR);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY - AMOUNT_OUT / 2);
+        assert!(get_logs()[1].contains(r#""event":"buyback_fail""#));
+    }
+
+    #[test]
+    fn test_failed_swap_rolls_back() {
+        let (mut context, mut contract) = setup();
+        assert_eq!(resolve(&mut context, &mut contract, PromiseResult::Failed), 0);
+        assert_eq!(contract.sale_proceeds().0, 10 * ONE_NEAR);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+        assert_eq!(contract.buyback_stats().burned.0, 0);
+        assert!(get_logs()[0].contains(r#""event":"buyback_fail""#));
+        assert_eq!(contract.buyback_received, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the buyback DEX can deliver")]
+    fn test_delivery_from_another_account() {
+        let (mut context, mut contract) = setup();
+        deliver(&mut context, &mut contract, 1, AMOUNT_OUT);
+    }
+
+    #[test]
+    #[should_panic(expected = "A buyback is already in flight")]
+    fn test_one_buyback_at_a_time() {
+        let (_context, mut contract) = setup();
+        let _ = contract.buyback(U128(ONE_NEAR), U128(AMOUNT_OUT));
+    }
+
+    /// `on_buyback` failed after the DEX delivered `AMOUNT_OUT`, the owner settles the buyback.
+    fn reset(context: &mut VMContextBuilder, contract: &mut Contract, swapped: bool) -> Balance {
+        deliver(context, contract, 4, AMOUNT_OUT);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .block_timestamp(BUYBACK_TIMEOUT)
+            .build());
+        contract.reset_buyback(swapped).0
+    }
+
+    #[test]
+    fn test_reset_after_failed_callback() {
+        let (mut context, mut contract) = setup();
+        assert_eq!(reset(&mut context, &mut contract, true), AMOUNT_OUT);
+        assert_eq!(contract.sale_proceeds().0, 6 * ONE_NEAR);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY - AMOUNT_OUT);
+        let stats = contract.buyback_stats();
+        assert_eq!((stats.spent.0, stats.burned.0), (4 * ONE_NEAR, AMOUNT_OUT));
+        assert!(get_logs()[1].contains(r#""event":"buyback""#));
+        assert_eq!(contract.buyback_received, None);
+
+        // Buybacks work again.
+        let _ = contract.buyback(U128(ONE_NEAR), U128(AMOUNT_OUT));
+        assert_eq!(contract.sale_proceeds().0, 5 * ONE_NEAR);
+    }
+
+    #[test]
+    fn test_reset_returns_proceeds_of_failed_swap() {
+        let (mut context, mut contract) = setup();
+        assert_eq!(reset(&mut context, &mut contract, false), AMOUNT_OUT);
+        assert_eq!(contract.sale_proceeds().0, 10 * ONE_NEAR);
+        assert_eq!(contract.buyback_stats().spent.0, 0);
+        assert!(get_logs()[1].contains(r#""event":"buyback_fail""#));
+    }
+
+    #[test]
+    fn test_late_callback_after_reset() {
+        let (mut context, mut contract) = setup();
+        reset(&mut context, &mut contract, true);
+        assert_eq!(resolve(&mut context, &mut contract, PromiseResult::Failed), 0);
+        assert_eq!(contract.sale_proceeds().0, 6 * ONE_NEAR);
+        assert!(get_logs().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "The callback of the buyback may still run")]
+    fn test_cannot_reset_before_timeout() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        contract.reset_buyback(false);
+    }
+
+    #[test]
+    #[should_panic(expected = "No buyback is in flight")]
+    fn test_reset_without_buyback() {
+        let (mut context, mut contract) = setup();
+        resolve(&mut context, &mut contract, PromiseResult::Failed);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .block_timestamp(BUYBACK_TIMEOUT)
+            .build());
+        contract.reset_buyback(false);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount exceeds the sale proceeds")]
+    fn test_buyback_above_proceeds() {
+        let (mut context, mut contract) = setup();
+        resolve(&mut context, &mut contract, PromiseResult::Failed);
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        let _ = contract.buyback(U128(10 * ONE_NEAR + 1), U128(AMOUNT_OUT));
+    }
+}
@@ -0,0 +1,230 @@
+/*!
+Compliance attestations (KYC) gating large transfers.
+
+The owner appoints compliance officers, who attest that an account is verified up to a level
+until an expiry, or revoke that attestation. Once the owner sets a compliance rule, every transfer
+above its threshold requires both parties to hold a valid attestation of at least its level.
+
+The rule is checked with the other transfer checks, so it covers `ft_transfer`,
+`ft_transfer_call` and the in-contract transfers alike. The contract account is exempt: tokens
+going into an in-contract deal are checked against the sender, and against the receiver when
+they come out. Refunds of `ft_transfer_call` and minting aren't transfers and aren't checked.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance};
+
+use crate::events::{AttestationRevoke, AttestationSet};
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Attestation {
+    /// Verification level, higher is stronger. Its meaning is up to the compliance officers.
+    pub level: u8,
+    /// Timestamp in nanoseconds from which the attestation isn't valid anymore.
+    pub expires_at: U64,
+    pub attested_by: AccountId,
+}
+
+impl Attestation {
+    fn is_valid(&self, now: u64, min_level: u8) -> bool {
+        now < self.expires_at.0 && self.level >= min_level
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ComplianceRule {
+    /// Transfers of more than this amount require both parties to be attested.
+    pub threshold: U128,
+    pub min_level: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AttestationStatus {
+    pub attestation: Option<Attestation>,
+    /// Whether the attestation is valid now and meets the level of the compliance rule.
+    pub valid: bool,
+}
+
+impl Contract {
+    pub(crate) fn assert_compliance_officer(&self) {
+        require!(
+            self.compliance_officers.contains(&env::predecessor_account_id()),
+            "Only a compliance officer can call this method"
+        );
+    }
+
+    fn internal_is_attested(&self, account_id: &AccountId, min_level: u8) -> bool {
+        account_id == &env::current_account_id()
+            || self
+                .attestations
+                .get(account_id)
+                .is_some_and(|attestation| attestation.is_valid(env::block_timestamp(), min_level))
+    }
+
+    /// Panics if the transfer exceeds the threshold of the compliance rule and one of the
+    /// parties isn't attested.
+    pub(crate) fn internal_check_compliance(
+        &self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) {
+        let rule = match self.compliance_rule.as_ref() {
+            Some(rule) if amount > rule.threshold.0 => rule,
+            _ => return,
+        };
+        require!(
+            self.internal_is_attested(sender_id, rule.min_level),
+            "The sender needs a valid attestation for this amount"
+        );
+        require!(
+            self.internal_is_attested(receiver_id, rule.min_level),
+            "The receiver needs a valid attestation for this amount"
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Grants or takes away the compliance role of `account_id`. Only the owner can call this
+    /// method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_compliance_officer(&mut self, account_id: AccountId, enabled: bool) {
+        assert_one_yocto();
+        self.assert_owner();
+        if enabled {
+            self.compliance_officers.insert(&account_id);
+        } else {
+            self.compliance_officers.remove(&account_id);
+        }
+    }
+
+    /// Sets the rule that gates large transfers, `None` removes it. Only the owner can call this
+    /// method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_compliance_rule(&mut self, rule: Option<ComplianceRule>) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.compliance_rule = rule;
+    }
+
+    /// Attests `account_id` at `level` until `expires_at`, replacing its previous attestation.
+    /// Only a compliance officer can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn attest(&mut self, account_id: AccountId, level: u8, expires_at: U64) {
+        assert_one_yocto();
+        self.assert_compliance_officer();
+        require!(expires_at.0 > env::block_timestamp(), "The expiry should be in the future");
+        let attestation =
+            Attestation { level, expires_at, attested_by: env::predecessor_account_id() };
+        self.attestations.insert(&account_id, &attestation);
+        AttestationSet { account_id: &account_id, attestation: &attestation }.emit();
+    }
+
+    /// Only a compliance officer can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn revoke_attestation(&mut self, account_id: AccountId) {
+        assert_one_yocto();
+        self.assert_compliance_officer();
+        require!(self.attestations.remove(&account_id).is_some(), "The account is not attested");
+        AttestationRevoke { account_id: &account_id, revoked_by: &env::predecessor_account_id() }
+            .emit();
+    }
+
+    pub fn attestation_status(&self, account_id: AccountId) -> AttestationStatus {
+        let min_level = self.compliance_rule.as_ref().map_or(0, |rule| rule.min_level);
+        let attestation = self.attestations.get(&account_id);
+        let valid = attestation
+            .as_ref()
+            .is_some_and(|attestation| attestation.is_valid(env::block_timestamp(), min_level));
+        AttestationStatus { attestation, valid }
+    }
+
+    pub fn compliance_rule(&self) -> Option<ComplianceRule> {
+        self.compliance_rule.clone()
+    }
+
+    pub fn compliance_officers(&self) -> Vec<AccountId> {
+        self.compliance_officers.to_vec()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    const THRESHOLD: Balance = 1_000;
+    const EXPIRY: u64 = 1_000_000;
+
+    /// accounts(1) owns the supply and is attested by the officer accounts(4). Transfers above
+    /// `THRESHOLD` need level 2.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(1).build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        contract.set_compliance_officer(accounts(4), true);
+        contract.set_compliance_rule(Some(ComplianceRule {
+            threshold: THRESHOLD.into(),
+            min_level: 2,
+        }));
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.attest(accounts(1), 2, EXPIRY.into());
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        (context, contract)
+    }
+
+    #[test]
+    fn test_small_transfer_needs_no_attestation() {
+        let (_context, mut contract) = setup();
+        contract.ft_transfer(accounts(2), THRESHOLD.into(), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, THRESHOLD);
+    }
+
+    #[test]
+    fn test_large_transfer_between_attested_parties() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.attest(accounts(2), 3, EXPIRY.into());
+        assert!(contract.attestation_status(accounts(2)).valid);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.ft_transfer(accounts(2), (THRESHOLD + 1).into(), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, THRESHOLD + 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "The receiver needs a valid attestation for this amount")]
+    fn test_large_transfer_to_unattested_account() {
+        let (_context, mut contract) = setup();
+        contract.ft_transfer(accounts(2), (THRESHOLD + 1).into(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The sender needs a valid attestation for this amount")]
+    fn test_expired_attestation() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.block_timestamp(EXPIRY).build());
+        assert!(!contract.attestation_status(accounts(1)).valid);
+        let _ = contract.ft_transfer_call(accounts(2), (THRESHOLD + 1).into(), None, "".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "The receiver needs a valid attestation for this amount")]
+    fn test_internal_transfer_is_checked() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.attest(accounts(2), 1, EXPIRY.into());
+        // Tokens coming out of the contract account are checked against the receiver only.
+        contract.internal_transfer(&accounts(1), &accounts(0), THRESHOLD + 1, None);
+        contract.internal_transfer(&accounts(0), &accounts(2), THRESHOLD + 1, None);
+    }
+}
@@ -0,0 +1,456 @@
+/*!
+Bonding curve that mints ESTATES against NEAR and redeems them from a NEAR reserve.
+
+The curve is configured at init and prices the tokens it has minted so far, `curve_supply`:
+  - `linear`: the price grows by `slope` per whole token minted,
+  - `exponential`: the price doubles every `doubling_supply` tokens, and grows linearly in
+    between.
+
+The reserve is always exactly `reserve_at(curve_supply)`, the integral of the price from zero,
+rounded down. `curve_buy` charges and `curve_sell` pays the difference of the reserve before and
+after, so any sequence of trades leaves the same reserve, and selling every curve token empties it
+exactly. The reserve can't become insolvent.
+
+Only the tokens minted by the curve can be sold back to it: every account can sell what it bought
+with `curve_buy` and hasn't sold yet, whatever other ESTATES it holds, so the premint and the
+other tokens never draw on the reserve. Transferring tokens doesn't move that allowance.
+
+Both methods take a slippage bound, and `curve_buy_quote`/`curve_sell_quote` tell what a trade
+would give right now. Prices are in yoctoNEAR per whole token, like the sale rounds.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, Balance, Promise};
+
+use crate::events::{CurveBuy, CurveSell};
+use crate::math::U512;
+use crate::*;
+
+/// The exponential curve stops after this many doublings.
+const MAX_DOUBLINGS: u128 = 64;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum BondingCurve {
+    /// `base_price + slope * supply`, `slope` being in yoctoNEAR per whole token per whole token.
+    Linear { base_price: U128, slope: U128 },
+    /// `base_price * 2^(supply / doubling_supply)`, interpolated linearly between doublings.
+    Exponential { base_price: U128, doubling_supply: U128 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CurveState {
+    pub curve: BondingCurve,
+    pub supply: U128,
+    pub reserve: U128,
+    pub spot_price: U128,
+    /// Reserve over the value of the supply at the spot price, in basis points.
+    pub reserve_ratio_bps: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CurveQuote {
+    pub amount: U128,
+    pub cost: U128,
+}
+
+impl BondingCurve {
+    pub fn assert_valid(&self) {
+        match self {
+            BondingCurve::Linear { base_price, .. } => {
+                require!(base_price.0 > 0, "The base price should be a positive number");
+            }
+            BondingCurve::Exponential { base_price, doubling_supply } => {
+                require!(base_price.0 > 0, "The base price should be a positive number");
+                require!(doubling_supply.0 > 0, "The doubling supply should be a positive number");
+            }
+        }
+    }
+
+    fn base_price(&self) -> Balance {
+        match self {
+            BondingCurve::Linear { base_price, .. } => base_price.0,
+            BondingCurve::Exponential { base_price, .. } => base_price.0,
+        }
+    }
+
+    /// NEAR in the reserve once `supply` tokens are minted, rounded down. `None` when it doesn't
+    /// fit in a `Balance` or the curve doesn't go that far.
+    pub fn reserve_at(&self, supply: Balance, unit: Balance) -> Option<Balance> {
+        let (s, unit) = (U512::from(supply), U512::from(unit));
+        let reserve = match self {
+            BondingCurve::Linear { base_price, slope } => {
+                (U512::from(2) * unit * U512::from(base_price.0) * s + U512::from(slope.0) * s * s)
+                    / (U512::from(2) * unit * unit)
+            }
+            BondingCurve::Exponential { base_price, doubling_supply } => {
+                let doublings = supply / doubling_supply.0;
+                if doublings >= MAX_DOUBLINGS {
+                    return None;
+                }
+                let d = U512::from(doubling_supply.0);
+                let t = U512::from(supply % doubling_supply.0);
+                let pow = U512::one() << doublings as usize;
+                U512::from(base_price.0)
+                    * (U512::from(3) * d * d * (pow - 1) + pow * (U512::from(2) * d * t + t * t))
+                    / (U512::from(2) * d * unit)
+            }
+        };
+        if reserve > U512::from(Balance::MAX) {
+            None
+        } else {
+            Some(reserve.low_u128())
+        }
+    }
+
+    /// Price of the next token at `supply`, rounded down.
+    pub fn spot_price(&self, supply: Balance, unit: Balance) -> Option<Balance> {
+        let s = U512::from(supply);
+        let price = match self {
+            BondingCurve::Linear { base_price, slope } => {
+                U512::from(base_price.0) + U512::from(slope.0) * s / U512::from(unit)
+            }
+            BondingCurve::Exponential { base_price, doubling_supply } => {
+                let doublings = supply / doubling_supply.0;
+                if doublings >= MAX_DOUBLINGS {
+                    return None;
+                }
+                let d = U512::from(doubling_supply.0);
+                let t = U512::from(supply % doubling_supply.0);
+                (U512::from(base_price.0) << doublings as usize) * (d + t) / d
+            }
+        };
+        if price > U512::from(Balance::MAX) {
+            None
+        } else {
+            Some(price.low_u128())
+        }
+    }
+
+    /// Most tokens `deposit` buys at `supply`, and what they cost.
+    pub fn buy_quote(
+        &self,
+        supply: Balance,
+        deposit: Balance,
+        unit: Balance,
+    ) -> (Balance, Balance) {
+        let reserve = self
+            .reserve_at(supply, unit)
+            .unwrap_or_else(|| env::panic_str("The curve is exhausted"));
+        let cost_of = |amount: Balance| {
+            supply
+                .checked_add(amount)
+                .and_then(|supply| self.reserve_at(supply, unit))
+                .map(|reserve_after| reserve_after - reserve)
+        };
+        // Every token costs at least the base price, give or take the rounding of the reserve.
+        let max = U512::from(deposit.saturating_add(1)) * U512::from(unit)
+            / U512::from(self.base_price());
+        let (mut low, mut high) = (0, max.min(U512::from(Balance::MAX - supply)).low_u128());
+        while low < high {
+            let middle = high - (high - low) / 2;
+            match cost_of(middle) {
+                Some(cost) if cost <= deposit => low = middle,
+                _ => high = middle - 1,
+            }
+        }
+        (low, cost_of(low).unwrap())
+    }
+}
+
+impl Contract {
+    fn internal_unwrap_curve(&self) -> BondingCurve {
+        self.bonding_curve
+            .clone()
+            .unwrap_or_else(|| env::panic_str("The bonding curve is not enabled"))
+    }
+
+    fn internal_curve_balance(&self, account_id: &AccountId) -> Balance {
+        self.curve_balances.get(account_id).unwrap_or(0)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Mints as many tokens as the attached NEAR buys on the curve, at least `min_amount_out`,
+    /// and refunds the rest. The buyer must be registered. Returns the tokens minted.
+    #[payable]
+    pub fn curve_buy(&mut self, min_amount_out: U128) -> U128 {
+        let curve = self.internal_unwrap_curve();
+        let account_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let unit = self.internal_token_unit().as_u128();
+        let (amount, cost) = curve.buy_quote(self.curve_supply, deposit, unit);
+        require!(amount > 0, "The attached deposit doesn't buy any tokens");
+        require!(amount >= min_amount_out.0, "Slippage: fewer tokens than `min_amount_out`");
+
+        self.curve_supply += amount;
+        self.curve_reserve += cost;
+        let curve_balance = self.internal_curve_balance(&account_id) + amount;
+        self.curve_balances.insert(&account_id, &curve_balance);
+        self.internal_mint(&account_id, amount, "Bonding curve buy");
+        CurveBuy { account_id: &account_id, amount: &U128(amount), cost: &U128(cost) }.emit();
+        if deposit > cost {
+            Promise::new(account_id).transfer(deposit - cost);
+        }
+        amount.into()
+    }
+
+    /// Burns `amount` tokens of the predecessor and pays them out of the reserve, at least
+    /// `min_near_out`. The predecessor can only sell what it bought on the curve.
+    /// Requires exactly 1 yoctoNEAR attached. Returns the NEAR paid.
+    #[payable]
+    pub fn curve_sell(&mut self, amount: U128, min_near_out: U128) -> U128 {
+        assert_one_yocto();
+        let curve = self.internal_unwrap_curve();
+        let account_id = env::predecessor_account_id();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        let curve_balance = self.internal_curve_balance(&account_id);
+        require!(amount.0 <= curve_balance, "The amount exceeds the tokens bought on the curve");
+        let unit = self.internal_token_unit().as_u128();
+        let reserve_after = curve.reserve_at(self.curve_supply - amount.0, unit).unwrap();
+        let payout = self.curve_reserve - reserve_after;
+        require!(payout >= min_near_out.0, "Slippage: less NEAR than `min_near_out`");
+
+        self.internal_burn(&account_id, amount.0, "Bonding curve sell");
+        self.curve_supply -= amount.0;
+        self.curve_reserve = reserve_after;
+        if curve_balance == amount.0 {
+            self.curve_balances.remove(&account_id);
+        } else {
+            self.curve_balances.insert(&account_id, &(curve_balance - amount.0));
+        }
+        CurveSell { account_id: &account_id, amount: &amount, payout: &U128(payout) }.emit();
+        if payout > 0 {
+            Promise::new(account_id).transfer(payout);
+        }
+        payout.into()
+    }
+
+    /// Tokens `deposit` yoctoNEAR would buy on the curve right now, and what they would cost.
+    pub fn curve_buy_quote(&self, deposit: U128) -> CurveQuote {
+        let unit = self.internal_token_unit().as_u128();
+        let (amount, cost) =
+            self.internal_unwrap_curve().buy_quote(self.curve_supply, deposit.0, unit);
+        CurveQuote { amount: amount.into(), cost: cost.into() }
+    }
+
+    /// NEAR that selling `amount` tokens would pay right now.
+    pub fn curve_sell_quote(&self, amount: U128) -> U128 {
+        require!(amount.0 <= self.curve_supply, "The amount exceeds the curve supply");
+        let unit = self.internal_token_unit().as_u128();
+        let curve = self.internal_unwrap_curve();
+        (self.curve_reserve - curve.reserve_at(self.curve_supply - amount.0, unit).unwrap()).into()
+    }
+
+    /// Tokens `account_id` can sell back to the curve.
+    pub fn curve_balance_of(&self, account_id: AccountId) -> U128 {
+        self.internal_curve_balance(&account_id).into()
+    }
+
+    pub fn curve_state(&self) -> Option<CurveState> {
+        let curve = self.bonding_curve.clone()?;
+        let unit = self.internal_token_unit();
+        let spot_price =
+            curve.spot_price(self.curve_supply, unit.as_u128()).unwrap_or(Balance::MAX);
+        let value =
+            U512::from(self.curve_supply) * U512::from(spot_price) / U512::from(unit.as_u128());
+        let reserve_ratio_bps = if value.is_zero() {
+            None
+        } else {
+            Some((U512::from(self.curve_reserve) * U512::from(10_000) / value).low_u32())
+        };
+        Some(CurveState {
+            curve,
+            supply: self.curve_supply.into(),
+            reserve: self.curve_reserve.into(),
+            spot_price: spot_price.into(),
+            reserve_ratio_bps,
+        })
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    use super::*;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    const ONE_TOKEN: Balance = 1_000_000_000_000_000_000_000_000;
+
+    fn setup(curve: BondingCurve) -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        // Enough NEAR on the contract account for the refunds and payouts.
+        testing_env!(context.account_balance(1_000_000 * ONE_NEAR).build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        curve.assert_valid();
+        contract.bonding_curve = Some(curve);
+        for account in 2..5 {
+            contract.token.internal_register_account(&accounts(account));
+        }
+        (context, contract)
+    }
+
+    fn buy(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        buyer: usize,
+        deposit: Balance,
+    ) -> Balance {
+        testing_env!(context
+            .predecessor_account_id(accounts(buyer))
+            .attached_deposit(deposit)
+            .build());
+        contract.curve_buy(U128(0)).0
+    }
+
+    fn sell(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        seller: usize,
+        amount: Balance,
+    ) -> Balance {
+        testing_env!(context.predecessor_account_id(accounts(seller)).attached_deposit(1).build());
+        contract.curve_sell(U128(amount), U128(0)).0
+    }
+
+    /// xorshift64*, so the random operations are reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, max: u128) -> u128 {
+            ((self.next() as u128) << 64 | self.next() as u128) % max
+        }
+    }
+
+    #[test]
+    fn test_linear_curve_prices() {
+        let curve = BondingCurve::Linear { base_price: U128(ONE_NEAR), slope: U128(2 * ONE_NEAR) };
+        let (mut context, mut contract) = setup(curve);
+        // The first token costs the integral of 1 + 2s over [0, 1], i.e. 2 NEAR.
+        assert_eq!(buy(&mut context, &mut contract, 2, 2 * ONE_NEAR), ONE_TOKEN);
+        let state = contract.curve_state().unwrap();
+        assert_eq!(state.spot_price.0, 3 * ONE_NEAR);
+        // 2 NEAR in the reserve for a supply worth 3 NEAR.
+        assert_eq!(state.reserve_ratio_bps, Some(6_666));
+        assert_eq!(sell(&mut context, &mut contract, 2, ONE_TOKEN), 2 * ONE_NEAR);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    #[test]
+    fn test_exponential_curve_prices() {
+        let curve = BondingCurve::Exponential {
+            base_price: U128(ONE_NEAR),
+            doubling_supply: U128(ONE_TOKEN),
+        };
+        let (mut context, mut contract) = setup(curve);
+        // 1.5 NEAR for the first token, 3 NEAR for the second one.
+        assert_eq!(contract.curve_buy_quote(U128(9 * ONE_NEAR / 2)).amount.0, 2 * ONE_TOKEN);
+        assert_eq!(buy(&mut context, &mut contract, 2, 9 * ONE_NEAR / 2), 2 * ONE_TOKEN);
+        assert_eq!(contract.curve_state().unwrap().spot_price.0, 4 * ONE_NEAR);
+        assert_eq!(contract.curve_sell_quote(U128(ONE_TOKEN)).0, 3 * ONE_NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "Slippage: less NEAR than `min_near_out`")]
+    fn test_sell_slippage() {
+        let curve = BondingCurve::Linear { base_price: U128(ONE_NEAR), slope: U128(0) };
+        let (mut context, mut contract) = setup(curve);
+        buy(&mut context, &mut contract, 2, 10 * ONE_NEAR);
+        testing_env!(context.attached_deposit(1).build());
+        contract.curve_sell(U128(ONE_TOKEN), U128(ONE_NEAR + 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount exceeds the tokens bought on the curve")]
+    fn test_premint_cannot_drain_the_reserve() {
+        let curve = BondingCurve::Linear { base_price: U128(ONE_NEAR), slope: U128(0) };
+        let (mut context, mut contract) = setup(curve);
+        buy(&mut context, &mut contract, 2, 10 * ONE_NEAR);
+        assert_eq!(contract.curve_balance_of(accounts(2)).0, 10 * ONE_TOKEN);
+        // The owner holds the whole premint, but bought nothing on the curve.
+        sell(&mut context, &mut contract, 1, ONE_TOKEN);
+    }
+
+    #[test]
+    fn test_transferred_curve_tokens_stay_with_the_buyer() {
+        let curve = BondingCurve::Linear { base_price: U128(ONE_NEAR), slope: U128(0) };
+        let (mut context, mut contract) = setup(curve);
+        buy(&mut context, &mut contract, 2, 10 * ONE_NEAR);
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+        contract.ft_transfer(accounts(3), U128(4 * ONE_TOKEN), None);
+        assert_eq!(contract.curve_balance_of(accounts(3)).0, 0);
+        // Whatever the buyer holds now, it can only sell the 6 tokens left of its own.
+        assert_eq!(sell(&mut context, &mut contract, 2, 6 * ONE_TOKEN), 6 * ONE_NEAR);
+        assert_eq!(contract.curve_balance_of(accounts(2)).0, 4 * ONE_TOKEN);
+        assert_eq!(contract.curve_state().unwrap().reserve.0, 4 * ONE_NEAR);
+    }
+
+    /// Runs random buys and sells, checking that the reserve never leaves the curve, then sells
+    /// every curve token and checks that it empties the reserve to the last yoctoNEAR.
+    fn assert_solvent_under_random_operations(curve: BondingCurve, seed: u64) {
+        let (mut context, mut contract) = setup(curve.clone());
+        let mut rng = Rng(seed);
+        let unit = contract.internal_token_unit().as_u128();
+        let mut net_paid_in: Balance = 0;
+        for _ in 0..500 {
+            let account = 2 + rng.below(3) as usize;
+            let balance = contract.ft_balance_of(accounts(account)).0;
+            if balance == 0 || rng.below(2) == 0 {
+                let deposit = 1 + rng.below(50 * ONE_NEAR);
+                let (amount, cost) = curve.buy_quote(contract.curve_supply, deposit, unit);
+                if amount == 0 {
+                    continue;
+                }
+                assert_eq!(buy(&mut context, &mut contract, account, deposit), amount);
+                net_paid_in += cost;
+            } else {
+                let amount = 1 + rng.below(balance);
+                net_paid_in -= sell(&mut context, &mut contract, account, amount);
+            }
+            assert_eq!(contract.curve_reserve, net_paid_in);
+            assert_eq!(curve.reserve_at(contract.curve_supply, unit), Some(contract.curve_reserve));
+        }
+        for account in 2..5 {
+            let balance = contract.ft_balance_of(accounts(account)).0;
+            if balance > 0 {
+                net_paid_in -= sell(&mut context, &mut contract, account, balance);
+            }
+        }
+        assert_eq!(contract.curve_supply, 0);
+        assert_eq!(contract.curve_reserve, 0);
+        assert_eq!(net_paid_in, 0);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    #[test]
+    fn test_linear_curve_stays_solvent() {
+        let curve = BondingCurve::Linear {
+            base_price: U128(ONE_NEAR / 1_000 + 7),
+            slope: U128(ONE_NEAR / 3),
+        };
+        assert_solvent_under_random_operations(curve, 0x9e37_79b9_7f4a_7c15);
+    }
+
+    #[test]
+    fn test_exponential_curve_stays_solvent() {
+        let curve = BondingCurve::Exponential {
+            base_price: U128(ONE_NEAR / 100 + 3),
+            doubling_supply: U128(25 * ONE_TOKEN + 11),
+        };
+        assert_solvent_under_random_operations(curve, 0x2545_f491_4f6c_dd1d);
+    }
+}
@@ -0,0 +1,314 @@
+/*!
+Arbitrated escrow for ESTATES-denominated deals.
+
+An escrow is opened by sending tokens to this contract with `ft_transfer_call` and an
+`open_escrow` message (see [`TokenReceiverMessage`](crate::receiver::TokenReceiverMessage)).
+The escrowed tokens are held on the contract's own account until the escrow is closed:
+  - the payer or the arbiter can release the tokens to the payee,
+  - the payee or the arbiter can refund the tokens to the payer,
+  - once the deadline has passed, anyone can return the tokens to the payer.
+
+The storage of the escrow is paid from the payer's storage credit (see [`crate::record_storage`])
+and given back to the payer when the escrow is closed.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance};
+
+use crate::events::{EscrowClose, EscrowOpen};
+use crate::record_storage::refund_record_storage;
+use crate::*;
+
+/// Terms are referenced by their sha256 hash, the documents themselves live off-chain.
+const TERMS_HASH_LEN: usize = 32;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Escrow {
+    pub escrow_id: U64,
+    pub payer_id: AccountId,
+    pub payee_id: AccountId,
+    pub arbiter_id: AccountId,
+    pub amount: U128,
+    /// Timestamp in nanoseconds after which the escrow can only go back to the payer.
+    pub deadline: U64,
+    pub terms_hash: Base64VecU8,
+    /// NEAR the payer paid for the storage of the escrow.
+    pub storage_deposit: U128,
+}
+
+impl Contract {
+    /// Records a new escrow for `amount` tokens that were already moved to the contract account.
+    pub(crate) fn internal_open_escrow(
+        &mut self,
+        payer_id: AccountId,
+        amount: Balance,
+        payee_id: AccountId,
+        arbiter_id: AccountId,
+        deadline: U64,
+        terms_hash: Base64VecU8,
+    ) -> u64 {
+        require!(payer_id != payee_id, "Payer and payee should be different");
+        require!(deadline.0 > env::block_timestamp(), "Deadline should be in the future");
+        require!(terms_hash.0.len() == TERMS_HASH_LEN, "Terms hash should be a sha256 hash");
+
+        let initial_storage = env::storage_usage();
+        let escrow_id = self.next_escrow_id;
+        self.next_escrow_id += 1;
+        let mut escrow = Escrow {
+            escrow_id: escrow_id.into(),
+            payer_id,
+            payee_id,
+            arbiter_id,
+            amount: amount.into(),
+            deadline,
+            terms_hash,
+            storage_deposit: U128(0),
+        };
+        self.escrows.insert(&escrow_id, &escrow);
+        for account_id in escrow_parties(&escrow) {
+            self.escrows_per_account.insert(account_id, escrow_id);
+        }
+        escrow.storage_deposit =
+            self.internal_charge_storage_credit(&escrow.payer_id, initial_storage).into();
+        self.escrows.insert(&escrow_id, &escrow);
+        EscrowOpen {
+            escrow_id: escrow.escrow_id,
+            payer_id: &escrow.payer_id,
+            payee_id: &escrow.payee_id,
+            arbiter_id: &escrow.arbiter_id,
+            amount: &escrow.amount,
+            deadline: escrow.deadline,
+            terms_hash: &escrow.terms_hash,
+        }
+        .emit();
+        escrow_id
+    }
+
+    fn internal_close_escrow(&mut self, escrow: Escrow, outcome: &str, receiver_id: &AccountId) {
+        let escrow_id = escrow.escrow_id.0;
+        self.escrows.remove(&escrow_id);
+        for account_id in escrow_parties(&escrow) {
+            self.escrows_per_account.remove(account_id, escrow_id);
+        }
+        self.internal_transfer(
+            &env::current_account_id(),
+            receiver_id,
+            escrow.amount.0,
+            Some(format!("escrow {} {}", escrow_id, outcome)),
+        );
+        refund_record_storage(&escrow.payer_id, escrow.storage_deposit);
+        EscrowClose {
+            escrow_id: escrow.escrow_id,
+            outcome,
+            receiver_id,
+            amount: &escrow.amount,
+            closed_by: &env::predecessor_account_id(),
+        }
+        .emit();
+    }
+
+    fn internal_unwrap_open_escrow(&self, escrow_id: U64) -> Escrow {
+        let escrow =
+            self.escrows.get(&escrow_id.0).unwrap_or_else(|| env::panic_str("Escrow not found"));
+        require!(env::block_timestamp() < escrow.deadline.0, "Escrow has expired");
+        escrow
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Releases the escrowed tokens to the payee. Can be called by the payer or the arbiter
+    /// before the deadline. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn escrow_release(&mut self, escrow_id: U64) {
+        assert_one_yocto();
+        let escrow = self.internal_unwrap_open_escrow(escrow_id);
+        let caller_id = env::predecessor_account_id();
+        require!(
+            caller_id == escrow.payer_id || caller_id == escrow.arbiter_id,
+            "Only the payer or the arbiter can release the escrow"
+        );
+        let payee_id = escrow.payee_id.clone();
+        self.internal_close_escrow(escrow, "released", &payee_id);
+    }
+
+    /// Refunds the escrowed tokens to the payer. Can be called by the payee or the arbiter
+    /// before the deadline. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn escrow_refund(&mut self, escrow_id: U64) {
+        assert_one_yocto();
+        let escrow = self.internal_unwrap_open_escrow(escrow_id);
+        let caller_id = env::predecessor_account_id();
+        require!(
+            caller_id == escrow.payee_id || caller_id == escrow.arbiter_id,
+            "Only the payee or the arbiter can refund the escrow"
+        );
+        let payer_id = escrow.payer_id.clone();
+        self.internal_close_escrow(escrow, "refunded", &payer_id);
+    }
+
+    /// Returns the escrowed tokens to the payer once the deadline has passed. Can be called by
+    /// anyone.
+    pub fn escrow_claim_expired(&mut self, escrow_id: U64) {
+        let escrow =
+            self.escrows.get(&escrow_id.0).unwrap_or_else(|| env::panic_str("Escrow not found"));
+        require!(env::block_timestamp() >= escrow.deadline.0, "Escrow has not expired yet");
+        let payer_id = escrow.payer_id.clone();
+        self.internal_close_escrow(escrow, "expired", &payer_id);
+    }
+
+    pub fn escrow(&self, escrow_id: U64) -> Option<Escrow> {
+        self.escrows.get(&escrow_id.0)
+    }
+
+    /// Lists open escrows in which `account_id` is the payer, the payee or the arbiter.
+    pub fn escrows_by_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Escrow> {
+        self.escrows_per_account
+            .page(&account_id, from_index, limit)
+            .into_iter()
+            .filter_map(|escrow_id| self.escrows.get(&escrow_id))
+            .collect()
+    }
+}
+
+/// Accounts under which an escrow is indexed. The arbiter may also be one of the two parties.
+fn escrow_parties(escrow: &Escrow) -> Vec<&AccountId> {
+    let mut parties = vec![&escrow.payer_id, &escrow.payee_id];
+    if !parties.contains(&&escrow.arbiter_id) {
+        parties.push(&escrow.arbiter_id);
+    }
+    parties
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, near_sent_to, STORAGE_DEPOSIT, TOTAL_SUPPLY};
+
+    const DEADLINE: u64 = 1_000_000_000;
+    const AMOUNT: Balance = 1_000;
+
+    fn open_escrow_msg(payee_id: AccountId, arbiter_id: AccountId) -> String {
+        near_sdk::serde_json::json!({
+            "open_escrow": {
+                "payee_id": payee_id,
+                "arbiter_id": arbiter_id,
+                "deadline": U64(DEADLINE),
+                "terms_hash": Base64VecU8(vec![7; TERMS_HASH_LEN]),
+            }
+        })
+        .to_string()
+    }
+
+    /// Runs an `open_escrow` transfer of accounts(1) the way `ft_transfer_call` does.
+    fn open_escrow(
+        context: &mut near_sdk::test_utils::VMContextBuilder,
+        contract: &mut Contract,
+    ) -> PromiseOrValue<U128> {
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        let _ = contract.ft_transfer_call(
+            accounts(0),
+            AMOUNT.into(),
+            None,
+            open_escrow_msg(accounts(2), accounts(3)),
+        );
+        // `ft_on_transfer` is called by the contract itself.
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        contract.ft_on_transfer(
+            accounts(1),
+            AMOUNT.into(),
+            open_escrow_msg(accounts(2), accounts(3)),
+        )
+    }
+
+    /// accounts(1) pays accounts(2), accounts(3) arbitrates.
+    fn setup() -> (near_sdk::test_utils::VMContextBuilder, Contract, u64) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        contract.record_storage_deposit(None);
+        let unused = open_escrow(&mut context, &mut contract);
+        assert!(matches!(unused, PromiseOrValue::Value(U128(0))));
+        (context, contract, 0)
+    }
+
+    #[test]
+    fn test_open_escrow() {
+        let (_context, contract, escrow_id) = setup();
+        let escrow = contract.escrow(escrow_id.into()).unwrap();
+        assert_eq!(escrow.payer_id, accounts(1));
+        assert_eq!(escrow.amount.0, AMOUNT);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, AMOUNT);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY - AMOUNT);
+        for account_id in [accounts(1), accounts(2), accounts(3)] {
+            assert_eq!(contract.escrows_by_account(account_id, None, None), vec![escrow.clone()]);
+        }
+        assert!(escrow.storage_deposit.0 > 0);
+        assert!(escrow.storage_deposit.0 < STORAGE_DEPOSIT);
+        let credit = contract.record_storage_credit_of(accounts(1)).0;
+        assert!(credit < STORAGE_DEPOSIT - escrow.storage_deposit.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "see `record_storage_deposit`")]
+    fn test_open_escrow_without_storage_credit() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        let _ = open_escrow(&mut context, &mut contract);
+    }
+
+    #[test]
+    fn test_arbiter_release() {
+        let (mut context, mut contract, escrow_id) = setup();
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        let storage_deposit = contract.escrow(escrow_id.into()).unwrap().storage_deposit;
+        contract.escrow_release(escrow_id.into());
+        assert_eq!(near_sent_to(&accounts(1)), storage_deposit.0);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, AMOUNT);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 0);
+        assert!(contract.escrow(escrow_id.into()).is_none());
+        assert!(contract.escrows_by_account(accounts(1), None, None).is_empty());
+    }
+
+    #[test]
+    fn test_payee_refund() {
+        let (mut context, mut contract, escrow_id) = setup();
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+        contract.escrow_refund(escrow_id.into());
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 0);
+    }
+
+    #[test]
+    fn test_claim_expired() {
+        let (mut context, mut contract, escrow_id) = setup();
+        testing_env!(context.predecessor_account_id(accounts(4)).block_timestamp(DEADLINE).build());
+        contract.escrow_claim_expired(escrow_id.into());
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the payer or the arbiter can release the escrow")]
+    fn test_payee_cannot_release() {
+        let (mut context, mut contract, escrow_id) = setup();
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+        contract.escrow_release(escrow_id.into());
+    }
+}
@@ -0,0 +1,838 @@
+//! Contract-specific events following the NEP-297 events format.
+//!
+//! The standard NEP-141 events ([`FtMint`], [`FtTransfer`] and [`FtBurn`]) are emitted by
+//! `near_contract_standards`. Everything that is specific to ESTATES is logged here under the
+//! `estates` standard, so indexers can pick it up the same way. The NEP-245 events of the estate
+//! shares ([`MtMint`], [`MtTransfer`] and [`MtBurn`]) are logged here as well, under the `nep245`
+//! standard.
+//!
+//! <https://github.com/near/NEPs/blob/master/neps/nep-0297.md>
+//!
+//! [`FtMint`]: near_contract_standards::fungible_token::events::FtMint
+//! [`FtTransfer`]: near_contract_standards::fungible_token::events::FtTransfer
+//! [`FtBurn`]: near_contract_standards::fungible_token::events::FtBurn
+
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::{env, AccountId};
+
+use crate::auction::DutchAuction;
+use crate::compliance::Attestation;
+use crate::forced_transfer::ForcedTransferRecord;
+use crate::governance::{Proposal, Vote};
+use crate::inheritance::InheritancePlan;
+use crate::multi_token::EstateShares;
+use crate::oracle::PriceData;
+use crate::parcel::ParcelCoordinates;
+use crate::sale::SaleRound;
+use crate::treasury::{SpendProposal, SpendRecord, TreasuryCategory};
+
+pub const EVENT_STANDARD: &str = "estates";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+pub const MT_EVENT_STANDARD: &str = "nep245";
+pub const MT_EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+/// Logs `data` as a single NEP-297 event called `event` of the given standard.
+fn emit_standard_event<T: Serialize>(standard: &str, version: &str, event: &str, data: &[T]) {
+    let event = json!({
+        "standard": standard,
+        "version": version,
+        "event": event,
+        "data": data,
+    });
+    env::log_str(&format!("EVENT_JSON:{}", event));
+}
+
+/// Logs `data` as a single `estates` event called `event`.
+fn emit_event<T: Serialize>(event: &str, data: &[T]) {
+    emit_standard_event(EVENT_STANDARD, EVENT_STANDARD_VERSION, event, data)
+}
+
+/// Data to log when an escrow is funded by `ft_transfer_call`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowOpen<'a> {
+    pub escrow_id: U64,
+    pub payer_id: &'a AccountId,
+    pub payee_id: &'a AccountId,
+    pub arbiter_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub deadline: U64,
+    pub terms_hash: &'a Base64VecU8,
+}
+
+impl EscrowOpen<'_> {
+    pub fn emit(self) {
+        emit_event("escrow_open", &[self])
+    }
+}
+
+/// Data to log when an escrow is closed. `outcome` is one of `released`, `refunded` or
+/// `expired`, and `receiver_id` is the account that got the escrowed tokens.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowClose<'a> {
+    pub escrow_id: U64,
+    pub outcome: &'a str,
+    pub receiver_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub closed_by: &'a AccountId,
+}
+
+impl EscrowClose<'_> {
+    pub fn emit(self) {
+        emit_event("escrow_close", &[self])
+    }
+}
+
+/// Data to log when a payment stream is created.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StreamCreate<'a> {
+    pub stream_id: U64,
+    pub sender_id: &'a AccountId,
+    pub receiver_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub start: U64,
+    pub end: U64,
+}
+
+impl StreamCreate<'_> {
+    pub fn emit(self) {
+        emit_event("stream_create", &[self])
+    }
+}
+
+/// Data to log when the receiver withdraws from a payment stream.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StreamWithdraw<'a> {
+    pub stream_id: U64,
+    pub receiver_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl StreamWithdraw<'_> {
+    pub fn emit(self) {
+        emit_event("stream_withdraw", &[self])
+    }
+}
+
+/// Data to log when a payment stream is cancelled by one of its parties.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StreamCancel<'a> {
+    pub stream_id: U64,
+    pub cancelled_by: &'a AccountId,
+    pub paid_out: &'a U128,
+    pub refunded: &'a U128,
+}
+
+impl StreamCancel<'_> {
+    pub fn emit(self) {
+        emit_event("stream_cancel", &[self])
+    }
+}
+
+/// Data to log when a payer authorizes a recurring subscription.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SubscriptionCreate<'a> {
+    pub subscription_id: U64,
+    pub payer_id: &'a AccountId,
+    pub merchant_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub amount_in_usd: bool,
+    pub period: U64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_cycles: Option<u32>,
+}
+
+impl SubscriptionCreate<'_> {
+    pub fn emit(self) {
+        emit_event("subscription_create", &[self])
+    }
+}
+
+/// Data to log when a merchant charges one cycle of a subscription.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SubscriptionCharge<'a> {
+    pub subscription_id: U64,
+    pub payer_id: &'a AccountId,
+    pub merchant_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub cycle: u32,
+}
+
+impl SubscriptionCharge<'_> {
+    pub fn emit(self) {
+        emit_event("subscription_charge", &[self])
+    }
+}
+
+/// Data to log when a payer cancels a subscription.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SubscriptionCancel<'a> {
+    pub subscription_id: U64,
+    pub payer_id: &'a AccountId,
+    pub merchant_id: &'a AccountId,
+    pub cycles_charged: u32,
+}
+
+impl SubscriptionCancel<'_> {
+    pub fn emit(self) {
+        emit_event("subscription_cancel", &[self])
+    }
+}
+
+/// Data to log when tokens are sent into a time-locked bucket.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferLocked<'a> {
+    pub lock_id: U64,
+    pub sender_id: &'a AccountId,
+    pub receiver_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub unlock_at: U64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+
+impl TransferLocked<'_> {
+    pub fn emit(self) {
+        emit_event("transfer_locked", &[self])
+    }
+}
+
+/// Data to log when time-locked buckets are paid out, either claimed by the receiver or
+/// `reclaimed` by the sender.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LockRelease<'a> {
+    pub lock_ids: Vec<U64>,
+    pub account_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub reclaimed: bool,
+}
+
+impl LockRelease<'_> {
+    pub fn emit(self) {
+        emit_event("lock_release", &[self])
+    }
+}
+
+/// Data to log when an outgoing transfer is blocked by an outflow limit. The transfer itself
+/// fails, but the log stays in the outcome of the failed receipt.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferLimitBlock<'a> {
+    pub account_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub remaining: &'a U128,
+}
+
+impl TransferLimitBlock<'_> {
+    pub fn emit(self) {
+        emit_event("transfer_limit_block", &[self])
+    }
+}
+
+/// Data to log when the owner registers a new parcel.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ParcelRegister<'a> {
+    pub parcel_id: U64,
+    pub owner_id: &'a AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coordinates: Option<ParcelCoordinates>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_uri: Option<&'a str>,
+}
+
+impl ParcelRegister<'_> {
+    pub fn emit(self) {
+        emit_event("parcel_register", &[self])
+    }
+}
+
+/// Data to log when a parcel is listed for sale, repriced or unlisted (`price` is `null`).
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ParcelList<'a> {
+    pub parcel_id: U64,
+    pub owner_id: &'a AccountId,
+    pub price: Option<&'a U128>,
+    pub price_in_usd: bool,
+}
+
+impl ParcelList<'_> {
+    pub fn emit(self) {
+        emit_event("parcel_list", &[self])
+    }
+}
+
+/// Data to log when a parcel changes hands. `price` is set when the parcel was sold.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ParcelTransfer<'a> {
+    pub parcel_id: U64,
+    pub old_owner_id: &'a AccountId,
+    pub new_owner_id: &'a AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<&'a U128>,
+}
+
+impl ParcelTransfer<'_> {
+    pub fn emit(self) {
+        emit_event("parcel_transfer", &[self])
+    }
+}
+
+/// Data to log when revenue is deposited for all holders. `asset` is `estates` or `near`.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RevenueDistribute<'a> {
+    pub from_id: &'a AccountId,
+    pub asset: &'a str,
+    pub amount: &'a U128,
+    pub eligible_supply: &'a U128,
+}
+
+impl RevenueDistribute<'_> {
+    pub fn emit(self) {
+        emit_event("revenue_distribute", &[self])
+    }
+}
+
+/// Data to log when a holder claims their share of the revenue.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RevenueClaim<'a> {
+    pub account_id: &'a AccountId,
+    pub estates: &'a U128,
+    pub near: &'a U128,
+}
+
+impl RevenueClaim<'_> {
+    pub fn emit(self) {
+        emit_event("revenue_claim", &[self])
+    }
+}
+
+/// Data to log when the owner creates the share token of a parcel.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EstateSharesCreate<'a> {
+    #[serde(flatten)]
+    pub shares: &'a EstateShares,
+}
+
+impl EstateSharesCreate<'_> {
+    pub fn emit(self) {
+        emit_event("estate_shares_create", &[self])
+    }
+}
+
+/// Data to log when estate shares are minted.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MtMint<'a> {
+    pub owner_id: &'a AccountId,
+    pub token_ids: &'a [&'a str],
+    pub amounts: &'a [U128],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+
+impl MtMint<'_> {
+    pub fn emit(self) {
+        emit_standard_event(MT_EVENT_STANDARD, MT_EVENT_STANDARD_VERSION, "mt_mint", &[self])
+    }
+}
+
+/// Data to log when estate shares are transferred, refunds included.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MtTransfer<'a> {
+    pub old_owner_id: &'a AccountId,
+    pub new_owner_id: &'a AccountId,
+    pub token_ids: &'a [&'a str],
+    pub amounts: &'a [U128],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+
+impl MtTransfer<'_> {
+    pub fn emit(self) {
+        emit_standard_event(MT_EVENT_STANDARD, MT_EVENT_STANDARD_VERSION, "mt_transfer", &[self])
+    }
+}
+
+/// Data to log when estate shares are redeemed.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MtBurn<'a> {
+    pub owner_id: &'a AccountId,
+    pub token_ids: &'a [&'a str],
+    pub amounts: &'a [U128],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<&'a str>,
+}
+
+impl MtBurn<'_> {
+    pub fn emit(self) {
+        emit_standard_event(MT_EVENT_STANDARD, MT_EVENT_STANDARD_VERSION, "mt_burn", &[self])
+    }
+}
+
+/// Data to log when the owner opens a sale round.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleRoundCreate<'a> {
+    #[serde(flatten)]
+    pub round: &'a SaleRound,
+}
+
+impl SaleRoundCreate<'_> {
+    pub fn emit(self) {
+        emit_event("sale_round_create", &[self])
+    }
+}
+
+/// Data to log when tokens are bought in a sale round. `cost` is in yoctoNEAR.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokensPurchase<'a> {
+    pub round_id: U64,
+    pub buyer_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub cost: &'a U128,
+}
+
+impl TokensPurchase<'_> {
+    pub fn emit(self) {
+        emit_event("tokens_purchase", &[self])
+    }
+}
+
+/// Data to log when the owner creates a Dutch auction.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionCreate<'a> {
+    #[serde(flatten)]
+    pub auction: &'a DutchAuction,
+}
+
+impl AuctionCreate<'_> {
+    pub fn emit(self) {
+        emit_event("auction_create", &[self])
+    }
+}
+
+/// Data to log when NEAR is committed to a Dutch auction. `amount` is the part that was kept.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionCommit<'a> {
+    pub auction_id: U64,
+    pub account_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl AuctionCommit<'_> {
+    pub fn emit(self) {
+        emit_event("auction_commit", &[self])
+    }
+}
+
+/// Data to log when a bidder claims the tokens bought at the clearing price and the refund.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionClaim<'a> {
+    pub auction_id: U64,
+    pub account_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub refund: &'a U128,
+}
+
+impl AuctionClaim<'_> {
+    pub fn emit(self) {
+        emit_event("auction_claim", &[self])
+    }
+}
+
+/// Tokens minted on the bonding curve for `cost` yoctoNEAR.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CurveBuy<'a> {
+    pub account_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub cost: &'a U128,
+}
+
+impl CurveBuy<'_> {
+    pub fn emit(self) {
+        emit_event("curve_buy", &[self])
+    }
+}
+
+/// Tokens burned on the bonding curve for `payout` yoctoNEAR of the reserve.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CurveSell<'a> {
+    pub account_id: &'a AccountId,
+    pub amount: &'a U128,
+    pub payout: &'a U128,
+}
+
+impl CurveSell<'_> {
+    pub fn emit(self) {
+        emit_event("curve_sell", &[self])
+    }
+}
+
+/// Data to log when tokens are booked under a treasury category.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TreasuryFund<'a> {
+    pub category: TreasuryCategory,
+    pub sender_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl TreasuryFund<'_> {
+    pub fn emit(self) {
+        emit_event("treasury_fund", &[self])
+    }
+}
+
+/// Data to log when a treasury spending is proposed.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SpendPropose<'a> {
+    #[serde(flatten)]
+    pub proposal: &'a SpendProposal,
+}
+
+impl SpendPropose<'_> {
+    pub fn emit(self) {
+        emit_event("spend_propose", &[self])
+    }
+}
+
+/// Data to log when a treasury spending is paid, i.e. appended to the spend log.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SpendExecute<'a> {
+    #[serde(flatten)]
+    pub record: &'a SpendRecord,
+}
+
+impl SpendExecute<'_> {
+    pub fn emit(self) {
+        emit_event("spend_execute", &[self])
+    }
+}
+
+/// Data to log when a pending treasury spending is dropped.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SpendReject<'a> {
+    pub proposal_id: U64,
+    pub rejected_by: &'a AccountId,
+}
+
+impl SpendReject<'_> {
+    pub fn emit(self) {
+        emit_event("spend_reject", &[self])
+    }
+}
+
+/// Data to log when a governance proposal is created.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalCreate<'a> {
+    #[serde(flatten)]
+    pub proposal: &'a Proposal,
+}
+
+impl ProposalCreate<'_> {
+    pub fn emit(self) {
+        emit_event("proposal_create", &[self])
+    }
+}
+
+/// Data to log when a holder votes on a proposal with the balance held at its snapshot.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalVote<'a> {
+    pub proposal_id: U64,
+    pub voter_id: &'a AccountId,
+    pub vote: Vote,
+    pub weight: &'a U128,
+}
+
+impl ProposalVote<'_> {
+    pub fn emit(self) {
+        emit_event("proposal_vote", &[self])
+    }
+}
+
+/// Data to log when the actions of a passed proposal are dispatched.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalExecute<'a> {
+    pub proposal_id: U64,
+    pub executed_by: &'a AccountId,
+}
+
+impl ProposalExecute<'_> {
+    pub fn emit(self) {
+        emit_event("proposal_execute", &[self])
+    }
+}
+
+/// Data to log when a compliance officer attests an account.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AttestationSet<'a> {
+    pub account_id: &'a AccountId,
+    #[serde(flatten)]
+    pub attestation: &'a Attestation,
+}
+
+impl AttestationSet<'_> {
+    pub fn emit(self) {
+        emit_event("attestation_set", &[self])
+    }
+}
+
+/// Data to log when a compliance officer revokes the attestation of an account.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AttestationRevoke<'a> {
+    pub account_id: &'a AccountId,
+    pub revoked_by: &'a AccountId,
+}
+
+impl AttestationRevoke<'_> {
+    pub fn emit(self) {
+        emit_event("attestation_revoke", &[self])
+    }
+}
+
+/// Data to log when a compliance officer moves tokens without the holder's signature. Logged
+/// next to the standard `ft_transfer` event of the same transfer.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtForcedTransfer<'a> {
+    #[serde(flatten)]
+    pub record: &'a ForcedTransferRecord,
+}
+
+impl FtForcedTransfer<'_> {
+    pub fn emit(self) {
+        emit_event("ft_forced_transfer", &[self])
+    }
+}
+
+/// Data to log when a guardian starts recovering an account to a new one.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecoveryStart<'a> {
+    pub account_id: &'a AccountId,
+    pub new_account_id: &'a AccountId,
+    pub guardian_id: &'a AccountId,
+}
+
+impl RecoveryStart<'_> {
+    pub fn emit(self) {
+        emit_event("recovery_start", &[self])
+    }
+}
+
+/// Data to log when the holder cancels the pending recovery of their account.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecoveryVeto<'a> {
+    pub account_id: &'a AccountId,
+}
+
+impl RecoveryVeto<'_> {
+    pub fn emit(self) {
+        emit_event("recovery_veto", &[self])
+    }
+}
+
+/// Data to log when the balance and the registration of an account move to the new account.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecoveryFinish<'a> {
+    pub account_id: &'a AccountId,
+    pub new_account_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl RecoveryFinish<'_> {
+    pub fn emit(self) {
+        emit_event("recovery_finish", &[self])
+    }
+}
+
+/// Data to log when a holder names a beneficiary of their balance.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InheritanceSet<'a> {
+    pub account_id: &'a AccountId,
+    #[serde(flatten)]
+    pub plan: &'a InheritancePlan,
+}
+
+impl InheritanceSet<'_> {
+    pub fn emit(self) {
+        emit_event("inheritance_set", &[self])
+    }
+}
+
+/// Data to log when a beneficiary claims the balance of an inactive holder.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InheritanceClaim<'a> {
+    pub account_id: &'a AccountId,
+    pub beneficiary_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl InheritanceClaim<'_> {
+    pub fn emit(self) {
+        emit_event("inheritance_claim", &[self])
+    }
+}
+
+/// Data to log when tokens are locked to be minted on another chain.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BridgeOut<'a> {
+    pub nonce: U64,
+    pub sender_id: &'a AccountId,
+    pub dest_chain: &'a str,
+    pub dest_address: &'a str,
+    pub amount: &'a U128,
+}
+
+impl BridgeOut<'_> {
+    pub fn emit(self) {
+        emit_event("bridge_out", &[self])
+    }
+}
+
+/// Data to log when tokens coming back from another chain are unlocked.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BridgeIn<'a> {
+    pub source_chain: &'a str,
+    pub source_nonce: U64,
+    pub receiver_id: &'a AccountId,
+    pub amount: &'a U128,
+}
+
+impl BridgeIn<'_> {
+    pub fn emit(self) {
+        emit_event("bridge_in", &[self])
+    }
+}
+
+/// Data to log when a newer oracle price is stored.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OraclePriceUpdate<'a> {
+    #[serde(flatten)]
+    pub price: &'a PriceData,
+}
+
+impl OraclePriceUpdate<'_> {
+    pub fn emit(self) {
+        emit_event("oracle_price_update", &[self])
+    }
+}
+
+/// Data to log when ESTATES bought back on the DEX are burned.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Buyback<'a> {
+    pub amount_in: &'a U128,
+    pub amount_out: &'a U128,
+}
+
+impl Buyback<'_> {
+    pub fn emit(self) {
+        emit_event("buyback", &[self])
+    }
+}
+
+/// Data to log when a buyback doesn't deliver `min_out`: either the swap failed and its NEAR
+/// goes back to the sale proceeds, or the DEX delivered only `amount_out`, which is burned all the
+/// same.
+#[must_use]
+#[derive(Serialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BuybackFail<'a> {
+    pub amount_in: &'a U128,
+    pub min_out: &'a U128,
+    pub amount_out: &'a U128,
+}
+
+impl BuybackFail<'_> {
+    pub fn emit(self) {
+        emit_event("buyback_fail", &[self])
+    }
+}
@@ -0,0 +1,308 @@
+/*!
+Forced transfers (clawbacks) for court orders and recovered thefts.
+
+Compliance officers move tokens between accounts without the holder's signature, always with a
+reason. By default one officer is enough, the owner can require the approvals of more officers,
+in which case `forced_transfer` opens a request that executes on the last approval.
+
+A forced transfer skips the holder-side checks (outflow limits and attestations), but otherwise
+goes through the ledger like any transfer: the balance must cover it, the receiver must be
+registered, the revenue and the governance snapshots are settled, and the standard `ft_transfer`
+event is emitted next to the `ft_forced_transfer` one. The contract account can't be on either side:
+its tokens back escrows, streams, locks and the other in-contract records, which only their own
+methods may move. Every executed forced transfer is
+appended to a log that can be listed by account.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId};
+
+use crate::events::FtForcedTransfer;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ForcedTransferRequest {
+    pub request_id: U64,
+    pub from_id: AccountId,
+    pub to_id: AccountId,
+    pub amount: U128,
+    pub reason: String,
+    /// Compliance officers who approved the request, the one who opened it first.
+    pub approvals: Vec<AccountId>,
+}
+
+/// Entry of the forced transfers log.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ForcedTransferRecord {
+    /// Position in the log.
+    pub index: U64,
+    #[serde(flatten)]
+    pub request: ForcedTransferRequest,
+    /// Timestamp in nanoseconds.
+    pub executed_at: U64,
+}
+
+impl Contract {
+    fn internal_approve_forced_transfer(&mut self, mut request: ForcedTransferRequest) -> bool {
+        let officer_id = env::predecessor_account_id();
+        require!(!request.approvals.contains(&officer_id), "Already approved");
+        request.approvals.push(officer_id);
+        // Officers who lost the role since their approval don't count anymore.
+        let approvals = request
+            .approvals
+            .iter()
+            .filter(|officer_id| self.compliance_officers.contains(officer_id))
+            .count();
+        if approvals < self.forced_transfer_threshold as usize {
+            self.pending_forced_transfers.insert(&request.request_id.0, &request);
+            return false;
+        }
+        self.pending_forced_transfers.remove(&request.request_id.0);
+        self.internal_execute_forced_transfer(request);
+        true
+    }
+
+    fn internal_execute_forced_transfer(&mut self, request: ForcedTransferRequest) {
+        let amount = request.amount.0;
+        self.internal_before_balance_change(&request.from_id);
+        self.internal_before_balance_change(&request.to_id);
+        self.token.internal_transfer(
+            &request.from_id,
+            &request.to_id,
+            amount,
+            Some(format!("forced transfer: {}", request.reason)),
+        );
+        let index = self.forced_transfers.len();
+        self.forced_transfers_per_account.insert(&request.from_id, index);
+        self.forced_transfers_per_account.insert(&request.to_id, index);
+        let record = ForcedTransferRecord {
+            index: index.into(),
+            request,
+            executed_at: env::block_timestamp().into(),
+        };
+        FtForcedTransfer { record: &record }.emit();
+        self.forced_transfers.push(&record);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets how many compliance officers have to approve a forced transfer. Only the owner can
+    /// call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_forced_transfer_threshold(&mut self, threshold: u32) {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(threshold > 0, "The threshold should be a positive number");
+        self.forced_transfer_threshold = threshold;
+    }
+
+    /// Moves `amount` tokens from `from_id` to `to_id` without their signature. Executes right
+    /// away when one approval is enough, otherwise opens a request that the other officers
+    /// approve with `approve_forced_transfer`. Returns the request id.
+    /// Only a compliance officer can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn forced_transfer(
+        &mut self,
+        from_id: AccountId,
+        to_id: AccountId,
+        amount: U128,
+        reason: String,
+    ) -> U64 {
+        assert_one_yocto();
+        self.assert_compliance_officer();
+        require!(from_id != to_id, "The sender and the receiver should be different");
+        require!(
+            from_id != env::current_account_id() && to_id != env::current_account_id(),
+            "Tokens can't be forced to or from the contract account"
+        );
+        require!(amount.0 > 0, "The amount should be a positive number");
+        require!(!reason.trim().is_empty(), "A reason is required");
+        let request_id = self.next_forced_transfer_id;
+        self.next_forced_transfer_id += 1;
+        let request = ForcedTransferRequest {
+            request_id: request_id.into(),
+            from_id,
+            to_id,
+            amount,
+            reason,
+            approvals: vec![],
+        };
+        self.internal_approve_forced_transfer(request);
+        request_id.into()
+    }
+
+    /// Approves a pending request and returns whether it was executed.
+    /// Only a compliance officer can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn approve_forced_transfer(&mut self, request_id: U64) -> bool {
+        assert_one_yocto();
+        self.assert_compliance_officer();
+        let request = self
+            .pending_forced_transfers
+            .get(&request_id.0)
+            .unwrap_or_else(|| env::panic_str("Forced transfer request not found"));
+        self.internal_approve_forced_transfer(request)
+    }
+
+    /// Drops a pending request. Only a compliance officer can call this method.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn cancel_forced_transfer(&mut self, request_id: U64) {
+        assert_one_yocto();
+        self.assert_compliance_officer();
+        require!(
+            self.pending_forced_transfers.remove(&request_id.0).is_some(),
+            "Forced transfer request not found"
+        );
+    }
+
+    pub fn forced_transfer_threshold(&self) -> u32 {
+        self.forced_transfer_threshold
+    }
+
+    pub fn pending_forced_transfers(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<ForcedTransferRequest> {
+        self.pending_forced_transfers
+            .values()
+            .skip(from_index.map(|index| index.0 as usize).unwrap_or(0))
+            .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    /// Lists the executed forced transfers, oldest first.
+    pub fn forced_transfers(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<ForcedTransferRecord> {
+        self.forced_transfers
+            .iter()
+            .skip(from_index.map(|index| index.0 as usize).unwrap_or(0))
+            .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    /// Lists the executed forced transfers from or to `account_id`.
+    pub fn forced_transfers_by_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<ForcedTransferRecord> {
+        self.forced_transfers_per_account
+            .page(&account_id, from_index, limit)
+            .into_iter()
+            .filter_map(|index| self.forced_transfers.get(index))
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::compliance::ComplianceRule;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    const AMOUNT: Balance = 1_000;
+
+    /// accounts(2) holds `AMOUNT` tokens, accounts(3) and accounts(4) are compliance officers.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(1).build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        contract.ft_transfer(accounts(2), AMOUNT.into(), None);
+        contract.set_compliance_officer(accounts(3), true);
+        contract.set_compliance_officer(accounts(4), true);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        (context, contract)
+    }
+
+    fn claw_back(contract: &mut Contract) -> U64 {
+        contract.forced_transfer(accounts(2), accounts(1), AMOUNT.into(), "Court order 42".into())
+    }
+
+    #[test]
+    fn test_forced_transfer() {
+        let (_context, mut contract) = setup();
+        // The limits of the holder don't apply.
+        contract.compliance_rule = Some(ComplianceRule { threshold: U128(0), min_level: 1 });
+        claw_back(&mut contract);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 0);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY);
+
+        let logs = get_logs();
+        assert!(logs[0].contains(r#""event":"ft_transfer""#));
+        assert!(logs[1].contains(r#""event":"ft_forced_transfer""#));
+        assert!(logs[1].contains(r#""reason":"Court order 42""#));
+        for account_id in [accounts(1), accounts(2)] {
+            let records = contract.forced_transfers_by_account(account_id, None, None);
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].request.approvals, vec![accounts(3)]);
+        }
+        assert!(contract.forced_transfers_by_account(accounts(3), None, None).is_empty());
+    }
+
+    #[test]
+    fn test_multisig_forced_transfer() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.set_forced_transfer_threshold(2);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        let request_id = claw_back(&mut contract);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, AMOUNT);
+        assert_eq!(contract.pending_forced_transfers(None, None).len(), 1);
+
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        assert!(contract.approve_forced_transfer(request_id));
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 0);
+        assert!(contract.pending_forced_transfers(None, None).is_empty());
+        assert_eq!(contract.forced_transfers(None, None)[0].request.approvals.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only a compliance officer can call this method")]
+    fn test_holder_cannot_force() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        claw_back(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "A reason is required")]
+    fn test_reason_is_required() {
+        let (_context, mut contract) = setup();
+        contract.forced_transfer(accounts(2), accounts(1), AMOUNT.into(), " ".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "Tokens can't be forced to or from the contract account")]
+    fn test_contract_account_cannot_be_drained() {
+        let (_context, mut contract) = setup();
+        contract.forced_transfer(accounts(0), accounts(2), AMOUNT.into(), "Theft".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "Tokens can't be forced to or from the contract account")]
+    fn test_contract_account_cannot_be_credited() {
+        let (_context, mut contract) = setup();
+        contract.forced_transfer(accounts(2), accounts(0), AMOUNT.into(), "Theft".into());
+    }
+
+    #[test]
+    #[should_panic(expected = "The account doesn't have enough balance")]
+    fn test_forced_transfer_keeps_balances() {
+        let (_context, mut contract) = setup();
+        contract.forced_transfer(accounts(2), accounts(1), (AMOUNT + 1).into(), "Theft".into());
+    }
+}
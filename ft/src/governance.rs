@@ -0,0 +1,743 @@
+/*!
+On-chain governance weighted by ESTATES balances.
+
+Holders of at least `proposal_threshold_bps` of the eligible supply can create a proposal with a
+title, a description and optionally function calls to run if it passes. Creating a proposal takes a balance snapshot, and each holder votes once with
+the balance they held at that snapshot, so tokens moved after the proposal was created don't
+vote twice. Voting is open for `voting_period` after creation. The proposal passes when:
+  - the votes cast reach `quorum_bps` of the eligible supply at the snapshot,
+  - the yes votes exceed `threshold_bps` of the yes and no votes.
+
+Anyone can then execute a passed proposal, which dispatches its actions as promises from this
+contract. Actions can only call the admin methods of this contract listed in
+[`GOVERNED_METHODS`], which only work once the owner is this contract itself, see `set_owner`.
+Any other call to this contract would run with the contract account as the predecessor, which
+the token transfers and their callbacks trust. The NEAR the actions attach is paid from the
+governance budget, which only grows with the deposits made to `fund_governance_budget`, so a
+proposal can't spend the storage deposits and proceeds the contract holds for others.
+
+Snapshots are taken lazily: the first balance change of an account after a snapshot records the
+balance it had before, and accounts that didn't change since still hold their snapshot balance.
+Only the snapshots of the proposals still open for voting are kept: older ones are dropped on the
+next balance change, and nothing is recorded while no proposal is open. The tokens held by the
+contract account don't vote.
+
+At most [`MAX_OPEN_PROPOSALS`] proposals are open at a time, so the snapshots of an account are
+bounded, and the storage deposit of every registration covers them. Proposers pay for the storage
+of their proposals and voters for the storage of their votes, and both are kept with the contract
+as the proposals and the votes are never removed.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance, Gas, Promise};
+
+use crate::events::{ProposalCreate, ProposalExecute, ProposalVote};
+use crate::math::U256;
+use crate::record_storage::charge_attached_storage;
+use crate::*;
+
+/// 7 days in nanoseconds.
+pub const DEFAULT_VOTING_PERIOD: u64 = 7 * 24 * 3600 * 1_000_000_000;
+pub const DEFAULT_QUORUM_BPS: u32 = 1_000;
+pub const DEFAULT_THRESHOLD_BPS: u32 = 5_000;
+pub const DEFAULT_PROPOSAL_THRESHOLD_BPS: u32 = 100;
+const MAX_BPS: u32 = 10_000;
+pub const MAX_OPEN_PROPOSALS: u64 = 10;
+pub const MAX_TITLE_LENGTH: usize = 256;
+pub const MAX_DESCRIPTION_LENGTH: usize = 4_096;
+pub const MAX_ACTIONS: usize = 10;
+/// Bytes of the arguments of an action.
+pub const MAX_ACTION_ARGS_LENGTH: usize = 4_096;
+/// Admin methods of this contract that proposal actions can call. Those minting tokens or
+/// moving what the contract holds for others are left out.
+pub const GOVERNED_METHODS: [&str; 15] = [
+    "set_owner",
+    "set_governance_config",
+    "set_global_transfer_cap",
+    "set_transfer_limit_window",
+    "set_compliance_officer",
+    "set_compliance_rule",
+    "set_forced_transfer_threshold",
+    "set_oracle_config",
+    "set_buyback_dex",
+    "buyback",
+    "reset_buyback",
+    "set_treasury_council",
+    "set_closed_balance_policy",
+    "create_sale_round",
+    "set_sale_whitelist",
+];
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GovernanceConfig {
+    /// Nanoseconds.
+    pub voting_period: U64,
+    /// Share of the eligible supply that has to vote, in basis points.
+    pub quorum_bps: u32,
+    /// Share of the yes and no votes that the yes votes have to exceed, in basis points.
+    pub threshold_bps: u32,
+    /// Share of the eligible supply a proposer has to hold, in basis points.
+    pub proposal_threshold_bps: u32,
+}
+
+impl Default for GovernanceConfig {
+    fn default() -> Self {
+        Self {
+            voting_period: DEFAULT_VOTING_PERIOD.into(),
+            quorum_bps: DEFAULT_QUORUM_BPS,
+            threshold_bps: DEFAULT_THRESHOLD_BPS,
+            proposal_threshold_bps: DEFAULT_PROPOSAL_THRESHOLD_BPS,
+        }
+    }
+}
+
+/// Function call that a passed proposal makes from this contract.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalAction {
+    pub receiver_id: AccountId,
+    pub method_name: String,
+    pub args: Base64VecU8,
+    /// yoctoNEAR attached to the call, paid by this contract.
+    pub deposit: U128,
+    pub gas: U64,
+}
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Vote {
+    Yes,
+    No,
+    Abstain,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Proposal {
+    pub proposal_id: U64,
+    pub proposer_id: AccountId,
+    pub title: String,
+    pub description: String,
+    pub actions: Vec<ProposalAction>,
+    pub snapshot_id: U64,
+    /// Supply that can vote, i.e. everything the contract account didn't hold at the snapshot.
+    pub eligible_supply: U128,
+    /// Voting window in nanoseconds, `end_at` excluded.
+    pub start_at: U64,
+    pub end_at: U64,
+    /// Config in force when the proposal was created.
+    pub quorum_bps: u32,
+    pub threshold_bps: u32,
+    pub yes: U128,
+    pub no: U128,
+    pub abstain: U128,
+    pub executed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Active,
+    Passed,
+    Rejected,
+    Executed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalView {
+    #[serde(flatten)]
+    pub proposal: Proposal,
+    pub status: ProposalStatus,
+}
+
+impl Proposal {
+    fn status(&self, now: u64) -> ProposalStatus {
+        if self.executed {
+            return ProposalStatus::Executed;
+        }
+        if now < self.end_at.0 {
+            return ProposalStatus::Active;
+        }
+        let (yes, no, abstain) = (U256::from(self.yes.0), U256::from(self.no.0), self.abstain.0);
+        let cast = yes + no + U256::from(abstain);
+        let quorum = cast * MAX_BPS >= U256::from(self.eligible_supply.0) * self.quorum_bps;
+        let threshold = yes * MAX_BPS > (yes + no) * self.threshold_bps;
+        if quorum && threshold {
+            ProposalStatus::Passed
+        } else {
+            ProposalStatus::Rejected
+        }
+    }
+
+    fn add_vote(&mut self, vote: Vote, weight: Balance) {
+        let tally = match vote {
+            Vote::Yes => &mut self.yes,
+            Vote::No => &mut self.no,
+            Vote::Abstain => &mut self.abstain,
+        };
+        tally.0 += weight;
+    }
+}
+
+impl Contract {
+    /// Records the balance `account_id` held at the current snapshot, unless it's already
+    /// recorded. Must run before every change of that balance.
+    pub(crate) fn internal_record_snapshot(&mut self, account_id: &AccountId) {
+        let balance = self.token.accounts.get(account_id).unwrap_or(0);
+        self.internal_record_snapshot_balance(account_id, balance);
+    }
+
+    pub(crate) fn internal_record_snapshot_balance(
+        &mut self,
+        account_id: &AccountId,
+        balance: Balance,
+    ) {
+        let snapshot_id = self.governance_snapshot_id;
+        if snapshot_id == 0 {
+            return;
+        }
+        let oldest_id = match self.internal_oldest_open_snapshot() {
+            Some(oldest_id) => oldest_id,
+            None => {
+                self.balance_snapshots.remove(account_id);
+                return;
+            }
+        };
+        let mut snapshots = self.balance_snapshots.get(account_id).unwrap_or_default();
+        // Lookups at the open snapshots never read the entries recorded before the oldest one.
+        let stale = snapshots.partition_point(|&(recorded_id, _)| recorded_id < oldest_id);
+        let mut changed = stale > 0;
+        snapshots.drain(..stale);
+        if !matches!(snapshots.last(), Some(&(recorded_id, _)) if recorded_id == snapshot_id) {
+            snapshots.push((snapshot_id, balance));
+            changed = true;
+        }
+        if changed {
+            self.balance_snapshots.insert(account_id, &snapshots);
+        }
+    }
+
+    /// Snapshot of the oldest proposal still open for voting, if any. Skips the closed
+    /// proposals for good, so the scan is paid once per proposal.
+    fn internal_oldest_open_snapshot(&mut self) -> Option<u64> {
+        let now = env::block_timestamp();
+        while self.first_open_proposal_id < self.next_proposal_id {
+            let proposal = self.proposals.get(&self.first_open_proposal_id).unwrap();
+            if now < proposal.end_at.0 {
+                return Some(proposal.snapshot_id.0);
+            }
+            self.first_open_proposal_id += 1;
+        }
+        None
+    }
+
+    /// Balance of `account_id` at `snapshot_id`: the first one recorded at or after it, or the
+    /// current balance if it didn't change since. Only holds for the snapshots of open proposals.
+    fn internal_balance_at(&self, account_id: &AccountId, snapshot_id: u64) -> Balance {
+        let snapshots = self.balance_snapshots.get(account_id).unwrap_or_default();
+        let index = snapshots.partition_point(|&(recorded_id, _)| recorded_id < snapshot_id);
+        match snapshots.get(index) {
+            Some(&(_, balance)) => balance,
+            None => self.token.accounts.get(account_id).unwrap_or(0),
+        }
+    }
+
+    fn assert_valid_actions(actions: &[ProposalAction]) {
+        require!(actions.len() <= MAX_ACTIONS, "Too many actions");
+        for action in actions.iter() {
+            require!(
+                action.args.0.len() <= MAX_ACTION_ARGS_LENGTH,
+                "The arguments of the action are too long"
+            );
+            require!(
+                action.receiver_id != env::current_account_id()
+                    || GOVERNED_METHODS.contains(&action.method_name.as_str()),
+                format!("{} can't be called by a proposal", action.method_name)
+            );
+        }
+    }
+
+    fn internal_unwrap_proposal(&self, proposal_id: U64) -> Proposal {
+        self.proposals.get(&proposal_id.0).unwrap_or_else(|| env::panic_str("Proposal not found"))
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Only the owner can call this method. Applies to the proposals created afterwards.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_governance_config(&mut self, config: GovernanceConfig) {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(config.voting_period.0 > 0, "The voting period should be a positive number");
+        require!(config.quorum_bps <= MAX_BPS, "The quorum can't exceed 10000 bps");
+        require!(config.threshold_bps < MAX_BPS, "The threshold should be below 10000 bps");
+        require!(
+            config.proposal_threshold_bps <= MAX_BPS,
+            "The proposal threshold can't exceed 10000 bps"
+        );
+        self.governance_config = config;
+    }
+
+    /// Creates a proposal, takes the balance snapshot it's voted with, and returns its id.
+    /// Only holders of `proposal_threshold_bps` of the eligible supply can call this method,
+    /// while fewer than `MAX_OPEN_PROPOSALS` are open. Requires a deposit that covers the
+    /// storage of the proposal, the excess is refunded.
+    #[payable]
+    pub fn create_proposal(
+        &mut self,
+        title: String,
+        description: String,
+        actions: Option<Vec<ProposalAction>>,
+    ) -> U64 {
+        let proposer_id = env::predecessor_account_id();
+        let eligible_supply =
+            self.token.total_supply - self.token.ft_balance_of(env::current_account_id()).0;
+        let balance = self.token.ft_balance_of(proposer_id.clone()).0;
+        require!(
+            balance > 0
+                && U256::from(balance) * MAX_BPS
+                    >= U256::from(eligible_supply) * self.governance_config.proposal_threshold_bps,
+            "The balance is below the proposal threshold"
+        );
+        require!(!title.is_empty(), "The title should not be empty");
+        require!(title.len() <= MAX_TITLE_LENGTH, "The title is too long");
+        require!(description.len() <= MAX_DESCRIPTION_LENGTH, "The description is too long");
+        let actions = actions.unwrap_or_default();
+        Self::assert_valid_actions(&actions);
+        self.internal_oldest_open_snapshot();
+        require!(
+            self.next_proposal_id - self.first_open_proposal_id < MAX_OPEN_PROPOSALS,
+            "Too many proposals are open"
+        );
+        let initial_storage = env::storage_usage();
+
+        self.governance_snapshot_id += 1;
+        let proposal_id = self.next_proposal_id;
+        self.next_proposal_id += 1;
+        let now = env::block_timestamp();
+        let config = &self.governance_config;
+        let proposal = Proposal {
+            proposal_id: proposal_id.into(),
+            proposer_id,
+            title,
+            description,
+            actions,
+            snapshot_id: self.governance_snapshot_id.into(),
+            eligible_supply: eligible_supply.into(),
+            start_at: now.into(),
+            end_at: (now + config.voting_period.0).into(),
+            quorum_bps: config.quorum_bps,
+            threshold_bps: config.threshold_bps,
+            yes: U128(0),
+            no: U128(0),
+            abstain: U128(0),
+            executed: false,
+        };
+        self.proposals.insert(&proposal_id, &proposal);
+        charge_attached_storage(initial_storage);
+        ProposalCreate { proposal: &proposal }.emit();
+        proposal.proposal_id
+    }
+
+    /// Votes with the balance held at the proposal snapshot. Every account votes once.
+    /// Requires a deposit that covers the storage of the vote, the excess is refunded.
+    #[payable]
+    pub fn vote(&mut self, proposal_id: U64, vote: Vote) -> U128 {
+        let voter_id = env::predecessor_account_id();
+        let mut proposal = self.internal_unwrap_proposal(proposal_id);
+        require!(
+            proposal.status(env::block_timestamp()) == ProposalStatus::Active,
+            "Voting is closed"
+        );
+        require!(voter_id != env::current_account_id(), "The contract account can't vote");
+        let key = (proposal_id.0, voter_id.clone());
+        require!(self.proposal_votes.get(&key).is_none(), "Already voted");
+        let weight = self.internal_balance_at(&voter_id, proposal.snapshot_id.0);
+        require!(weight > 0, "No voting power at the proposal snapshot");
+
+        proposal.add_vote(vote, weight);
+        self.proposals.insert(&proposal_id.0, &proposal);
+        let initial_storage = env::storage_usage();
+        self.proposal_votes.insert(&key, &vote);
+        charge_attached_storage(initial_storage);
+        ProposalVote { proposal_id, voter_id: &voter_id, vote, weight: &U128(weight) }.emit();
+        weight.into()
+    }
+
+    /// Dispatches the actions of a passed proposal. Can be called by anyone, once.
+    pub fn execute(&mut self, proposal_id: U64) {
+        let mut proposal = self.internal_unwrap_proposal(proposal_id);
+        require!(
+            proposal.status(env::block_timestamp()) == ProposalStatus::Passed,
+            "The proposal has not passed"
+        );
+        let deposit = proposal
+            .actions
+            .iter()
+            .try_fold(0, |sum: Balance, action| sum.checked_add(action.deposit.0))
+            .filter(|deposit| *deposit <= self.governance_budget)
+            .unwrap_or_else(|| env::panic_str("The actions exceed the governance budget"));
+        self.governance_budget -= deposit;
+        proposal.executed = true;
+        self.proposals.insert(&proposal_id.0, &proposal);
+        for action in proposal.actions.iter() {
+            Promise::new(action.receiver_id.clone()).function_call(
+                action.method_name.clone(),
+                action.args.0.clone(),
+                action.deposit.0,
+                Gas(action.gas.0),
+            );
+        }
+        ProposalExecute { proposal_id, executed_by: &env::predecessor_account_id() }.emit();
+    }
+
+    /// Adds the attached deposit to the budget the actions of passed proposals attach their
+    /// deposits from, and returns the budget.
+    #[payable]
+    pub fn fund_governance_budget(&mut self) -> U128 {
+        self.governance_budget += env::attached_deposit();
+        self.governance_budget.into()
+    }
+
+    pub fn governance_budget(&self) -> U128 {
+        self.governance_budget.into()
+    }
+
+    pub fn governance_config(&self) -> GovernanceConfig {
+        self.governance_config.clone()
+    }
+
+    pub fn proposal(&self, proposal_id: U64) -> Option<ProposalView> {
+        let now = env::block_timestamp();
+        self.proposals
+            .get(&proposal_id.0)
+            .map(|proposal| ProposalView { status: proposal.status(now), proposal })
+    }
+
+    pub fn proposals(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<ProposalView> {
+        let now = env::block_timestamp();
+        self.proposals
+            .values()
+            .skip(from_index.map(|index| index.0 as usize).unwrap_or(0))
+            .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+            .map(|proposal| ProposalView { status: proposal.status(now), proposal })
+            .collect()
+    }
+
+    pub fn proposal_vote(&self, proposal_id: U64, account_id: AccountId) -> Option<Vote> {
+        self.proposal_votes.get(&(proposal_id.0, account_id))
+    }
+
+    /// Voting power of `account_id` on the proposal, whether it voted already or not. Only
+    /// holds while the proposal is open for voting.
+    pub fn voting_power(&self, proposal_id: U64, account_id: AccountId) -> U128 {
+        let proposal = self.internal_unwrap_proposal(proposal_id);
+        self.internal_balance_at(&account_id, proposal.snapshot_id.0).into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, STORAGE_DEPOSIT, TOTAL_SUPPLY};
+
+    const PERIOD: u64 = DEFAULT_VOTING_PERIOD;
+
+    /// accounts(1) owns the supply and gives 30% to accounts(2) and 10% to accounts(3).
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        for account in 2..5 {
+            contract.token.internal_register_account(&accounts(account));
+        }
+        testing_env!(context.attached_deposit(1).build());
+        contract.ft_transfer(accounts(2), (TOTAL_SUPPLY * 3 / 10).into(), None);
+        contract.ft_transfer(accounts(3), (TOTAL_SUPPLY / 10).into(), None);
+        (context, contract)
+    }
+
+    fn propose(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        actions: Option<Vec<ProposalAction>>,
+    ) -> U64 {
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(STORAGE_DEPOSIT)
+            .build());
+        let proposal_id =
+            contract.create_proposal("Build estate".to_string(), "Parcel 7".to_string(), actions);
+        testing_env!(context.attached_deposit(1).build());
+        proposal_id
+    }
+
+    fn vote(context: &mut VMContextBuilder, contract: &mut Contract, voter: usize, vote: Vote) {
+        vote_on(context, contract, 0, voter, vote);
+    }
+
+    fn vote_on(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        proposal_id: u64,
+        voter: usize,
+        vote: Vote,
+    ) {
+        testing_env!(context
+            .predecessor_account_id(accounts(voter))
+            .attached_deposit(STORAGE_DEPOSIT)
+            .build());
+        contract.vote(U64(proposal_id), vote);
+        testing_env!(context.attached_deposit(1).build());
+    }
+
+    #[test]
+    fn test_votes_use_snapshot_balances() {
+        let (mut context, mut contract) = setup();
+        let proposal_id = propose(&mut context, &mut contract, None);
+        // Moving tokens after the snapshot doesn't move the voting power.
+        contract.ft_transfer(accounts(4), (TOTAL_SUPPLY / 10).into(), None);
+        vote(&mut context, &mut contract, 2, Vote::Yes);
+        assert_eq!(contract.voting_power(proposal_id, accounts(2)).0, TOTAL_SUPPLY * 3 / 10);
+        assert_eq!(contract.voting_power(proposal_id, accounts(4)).0, 0);
+        vote(&mut context, &mut contract, 3, Vote::No);
+
+        // A later proposal sees the new balances.
+        let second_id = propose(&mut context, &mut contract, None);
+        assert_eq!(contract.voting_power(second_id, accounts(2)).0, TOTAL_SUPPLY / 5);
+        assert_eq!(contract.voting_power(second_id, accounts(4)).0, TOTAL_SUPPLY / 10);
+        assert_eq!(contract.voting_power(proposal_id, accounts(4)).0, 0);
+
+        let proposal = contract.proposal(proposal_id).unwrap().proposal;
+        assert_eq!(proposal.yes.0, TOTAL_SUPPLY * 3 / 10);
+        assert_eq!(proposal.no.0, TOTAL_SUPPLY / 10);
+        assert_eq!(contract.proposal_vote(proposal_id, accounts(3)), Some(Vote::No));
+        testing_env!(context.block_timestamp(PERIOD).build());
+        assert_eq!(contract.proposal(proposal_id).unwrap().status, ProposalStatus::Passed);
+    }
+
+    #[test]
+    fn test_quorum_and_threshold() {
+        let (mut context, mut contract) = setup();
+        contract.set_governance_config(GovernanceConfig {
+            voting_period: PERIOD.into(),
+            quorum_bps: 5_000,
+            threshold_bps: 5_000,
+            proposal_threshold_bps: DEFAULT_PROPOSAL_THRESHOLD_BPS,
+        });
+        propose(&mut context, &mut contract, None);
+        vote(&mut context, &mut contract, 2, Vote::Yes);
+        vote(&mut context, &mut contract, 3, Vote::Abstain);
+        testing_env!(context.block_timestamp(PERIOD).build());
+        // 40% of the supply voted.
+        assert_eq!(contract.proposal(U64(0)).unwrap().status, ProposalStatus::Rejected);
+
+        testing_env!(context.block_timestamp(0).build());
+        propose(&mut context, &mut contract, None);
+        vote_on(&mut context, &mut contract, 1, 1, Vote::No);
+        vote_on(&mut context, &mut contract, 1, 2, Vote::Yes);
+        testing_env!(context.block_timestamp(PERIOD).build());
+        // Quorum reached, but 60% against.
+        assert_eq!(contract.proposal(U64(1)).unwrap().status, ProposalStatus::Rejected);
+    }
+
+    fn own_action(method_name: &str, args: &[u8]) -> ProposalAction {
+        ProposalAction {
+            receiver_id: env::current_account_id(),
+            method_name: method_name.to_string(),
+            args: args.to_vec().into(),
+            deposit: U128(1),
+            gas: U64(10_000_000_000_000),
+        }
+    }
+
+    /// The owner hands the admin rights over to the contract, after which a proposal sets the
+    /// global transfer cap.
+    #[test]
+    fn test_execute_own_admin_method() {
+        let (mut context, mut contract) = setup();
+        contract.set_owner(env::current_account_id());
+        testing_env!(context.attached_deposit(10).build());
+        contract.fund_governance_budget();
+        let action = own_action("set_global_transfer_cap", br#"{"cap": "1000"}"#);
+        let proposal_id = propose(&mut context, &mut contract, Some(vec![action]));
+        vote(&mut context, &mut contract, 1, Vote::Yes);
+        testing_env!(context.predecessor_account_id(accounts(4)).block_timestamp(PERIOD).build());
+        contract.execute(proposal_id);
+        assert_eq!(contract.proposal(proposal_id).unwrap().status, ProposalStatus::Executed);
+        assert_eq!(contract.governance_budget().0, 9);
+
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, env::current_account_id());
+        match &receipts[0].actions[..] {
+            [VmAction::FunctionCall { function_name, args, deposit, .. }] => {
+                assert_eq!(function_name, "set_global_transfer_cap");
+                assert_eq!(*deposit, 1);
+                // The contract runs the call as its own owner.
+                testing_env!(context
+                    .predecessor_account_id(env::current_account_id())
+                    .attached_deposit(1)
+                    .build());
+                let args: near_sdk::serde_json::Value =
+                    near_sdk::serde_json::from_slice(args).unwrap();
+                contract.set_global_transfer_cap(Some(
+                    near_sdk::serde_json::from_value(args["cap"].clone()).unwrap(),
+                ));
+            }
+            actions => panic!("Unexpected actions {:?}", actions),
+        }
+        assert_eq!(contract.global_transfer_cap, Some(1000));
+    }
+
+    /// A self-call to `ft_on_transfer` would pass as a transfer the contract made, without any
+    /// tokens moving.
+    #[test]
+    #[should_panic(expected = "ft_on_transfer can't be called by a proposal")]
+    fn test_proposal_cannot_call_ft_on_transfer() {
+        let (mut context, mut contract) = setup();
+        let args = br#"{"sender_id": "bob", "amount": "1000", "msg": "{\"fund_treasury\": {}}"}"#;
+        propose(&mut context, &mut contract, Some(vec![own_action("ft_on_transfer", args)]));
+    }
+
+    /// The contract account holds the tokens of every in-contract deal.
+    #[test]
+    #[should_panic(expected = "ft_transfer can't be called by a proposal")]
+    fn test_proposal_cannot_call_ft_transfer() {
+        let (mut context, mut contract) = setup();
+        let args = br#"{"receiver_id": "charlie", "amount": "1000"}"#;
+        propose(&mut context, &mut contract, Some(vec![own_action("ft_transfer", args)]));
+    }
+
+    #[test]
+    #[should_panic(expected = "The actions exceed the governance budget")]
+    fn test_execute_over_budget() {
+        let (mut context, mut contract) = setup();
+        let action = ProposalAction {
+            receiver_id: accounts(4),
+            method_name: "deposit".to_string(),
+            args: b"{}".to_vec().into(),
+            deposit: U128(10),
+            gas: U64(10_000_000_000_000),
+        };
+        let proposal_id = propose(&mut context, &mut contract, Some(vec![action]));
+        vote(&mut context, &mut contract, 1, Vote::Yes);
+        testing_env!(context.attached_deposit(9).build());
+        contract.fund_governance_budget();
+        testing_env!(context.block_timestamp(PERIOD).build());
+        contract.execute(proposal_id);
+    }
+
+    #[test]
+    fn test_closed_snapshots_are_dropped() {
+        let (mut context, mut contract) = setup();
+        for _ in 0..9 {
+            propose(&mut context, &mut contract, None);
+            contract.ft_transfer(accounts(4), U128(1), None);
+        }
+        assert_eq!(contract.balance_snapshots.get(&accounts(2)).unwrap().len(), 9);
+
+        // Once the first nine proposals are closed, their snapshots go on the next change.
+        testing_env!(context.block_timestamp(PERIOD / 2).build());
+        propose(&mut context, &mut contract, None);
+        testing_env!(context.block_timestamp(PERIOD).build());
+        contract.ft_transfer(accounts(4), U128(1), None);
+        assert_eq!(contract.balance_snapshots.get(&accounts(2)).unwrap().len(), 1);
+        assert_eq!(contract.voting_power(U64(9), accounts(2)).0, TOTAL_SUPPLY * 3 / 10 - 9);
+
+        // Nothing is recorded while no proposal is open.
+        testing_env!(context.block_timestamp(2 * PERIOD).build());
+        contract.ft_transfer(accounts(4), U128(1), None);
+        assert!(contract.balance_snapshots.get(&accounts(2)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many proposals are open")]
+    fn test_open_proposals_are_capped() {
+        let (mut context, mut contract) = setup();
+        for _ in 0..MAX_OPEN_PROPOSALS {
+            propose(&mut context, &mut contract, None);
+        }
+        propose(&mut context, &mut contract, None);
+    }
+
+    #[test]
+    fn test_closed_proposals_free_the_cap() {
+        let (mut context, mut contract) = setup();
+        for _ in 0..MAX_OPEN_PROPOSALS {
+            propose(&mut context, &mut contract, None);
+        }
+        testing_env!(context.block_timestamp(PERIOD).build());
+        assert_eq!(propose(&mut context, &mut contract, None).0, MAX_OPEN_PROPOSALS);
+    }
+
+    /// Every registration pays for the snapshots an account can have.
+    #[test]
+    fn test_registration_covers_snapshots() {
+        let (_context, mut contract) = setup();
+        let balance_entry = FungibleToken::new(b"t".to_vec()).account_storage_usage;
+        let initial_storage = env::storage_usage();
+        let account_id: AccountId = "a".repeat(64).parse().unwrap();
+        let snapshots = vec![(u64::MAX, Balance::MAX); MAX_OPEN_PROPOSALS as usize];
+        contract.balance_snapshots.insert(&account_id, &snapshots);
+        let snapshots = env::storage_usage() - initial_storage;
+        assert!(contract.token.account_storage_usage >= balance_entry + snapshots);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires a deposit of")]
+    fn test_proposal_storage_is_paid() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.create_proposal("Build estate".to_string(), "Parcel 7".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The description is too long")]
+    fn test_description_is_capped() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let description = "a".repeat(MAX_DESCRIPTION_LENGTH + 1);
+        contract.create_proposal("Build estate".to_string(), description, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The balance is below the proposal threshold")]
+    fn test_small_holder_cannot_propose() {
+        let (mut context, mut contract) = setup();
+        contract.ft_transfer(accounts(4), (TOTAL_SUPPLY / 100 - 1).into(), None);
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.create_proposal("Spam".to_string(), String::new(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Already voted")]
+    fn test_vote_twice() {
+        let (mut context, mut contract) = setup();
+        propose(&mut context, &mut contract, None);
+        vote(&mut context, &mut contract, 2, Vote::Yes);
+        vote(&mut context, &mut contract, 2, Vote::No);
+    }
+
+    #[test]
+    #[should_panic(expected = "The proposal has not passed")]
+    fn test_execute_during_voting() {
+        let (mut context, mut contract) = setup();
+        propose(&mut context, &mut contract, None);
+        vote(&mut context, &mut contract, 1, Vote::Yes);
+        contract.execute(U64(0));
+    }
+}
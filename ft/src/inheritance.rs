@@ -0,0 +1,242 @@
+/*!
+Inactivity-based inheritance ("dead man's switch").
+
+A holder opts in by naming a beneficiary and an inactivity period. Every outgoing transfer or
+burn the holder signs, and an explicit `heartbeat`, records their last activity. Tokens pulled
+from the holder by others, e.g. subscription charges or forced transfers, don't. Once the holder
+stayed inactive for the whole period, the beneficiary can claim their full balance.
+
+Last activities are only recorded for the accounts that opted in, nobody else has a timer to
+reset. The claim is a transfer to another person, so unlike a recovery it's still subject to the
+compliance rule.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId};
+
+use crate::events::{InheritanceClaim, InheritanceSet};
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InheritancePlan {
+    pub beneficiary_id: AccountId,
+    /// Nanoseconds of inactivity after which the beneficiary can claim.
+    pub inactivity_period: U64,
+    /// Timestamp in nanoseconds of the last outgoing activity of the holder.
+    pub last_activity: U64,
+}
+
+impl InheritancePlan {
+    fn claimable_at(&self) -> u64 {
+        self.last_activity.0.saturating_add(self.inactivity_period.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InheritanceStatus {
+    #[serde(flatten)]
+    pub plan: InheritancePlan,
+    pub claimable_at: U64,
+    pub claimable: bool,
+}
+
+impl Contract {
+    /// Resets the inactivity timer of `account_id`, if it opted in and signed the call.
+    pub(crate) fn internal_record_activity(&mut self, account_id: &AccountId) {
+        if account_id != &env::predecessor_account_id() {
+            return;
+        }
+        if let Some(mut plan) = self.inheritance_plans.get(account_id) {
+            plan.last_activity = env::block_timestamp().into();
+            self.inheritance_plans.insert(account_id, &plan);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Names the beneficiary of the predecessor and starts the inactivity timer, replacing any
+    /// previous plan. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_inheritance(&mut self, beneficiary_id: AccountId, inactivity_period: U64) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require!(self.token.accounts.contains_key(&account_id), "The account is not registered");
+        require!(beneficiary_id != account_id, "An account can't inherit from itself");
+        require!(inactivity_period.0 > 0, "The inactivity period should be a positive number");
+        let plan = InheritancePlan {
+            beneficiary_id,
+            inactivity_period,
+            last_activity: env::block_timestamp().into(),
+        };
+        self.inheritance_plans.insert(&account_id, &plan);
+        InheritanceSet { account_id: &account_id, plan: &plan }.emit();
+    }
+
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn remove_inheritance(&mut self) {
+        assert_one_yocto();
+        require!(
+            self.inheritance_plans.remove(&env::predecessor_account_id()).is_some(),
+            "The account has no inheritance plan"
+        );
+    }
+
+    /// Resets the inactivity timer of the predecessor without moving any tokens.
+    pub fn heartbeat(&mut self) {
+        let account_id = env::predecessor_account_id();
+        require!(
+            self.inheritance_plans.contains_key(&account_id),
+            "The account has no inheritance plan"
+        );
+        self.internal_record_activity(&account_id);
+    }
+
+    /// Moves the full balance of the inactive `account_id` to its beneficiary. Only the
+    /// beneficiary can call this method, from a registered account. Requires exactly 1
+    /// yoctoNEAR attached.
+    #[payable]
+    pub fn claim_inheritance(&mut self, account_id: AccountId) -> U128 {
+        assert_one_yocto();
+        let plan = self
+            .inheritance_plans
+            .get(&account_id)
+            .unwrap_or_else(|| env::panic_str("The account has no inheritance plan"));
+        let beneficiary_id = env::predecessor_account_id();
+        require!(beneficiary_id == plan.beneficiary_id, "Only the beneficiary can claim");
+        require!(
+            env::block_timestamp() >= plan.claimable_at(),
+            "The account is not inactive long enough"
+        );
+        self.inheritance_plans.remove(&account_id);
+        let balance = self.token.internal_unwrap_balance_of(&account_id);
+        self.internal_check_compliance(&account_id, &beneficiary_id, balance);
+        self.internal_before_balance_change(&account_id);
+        self.internal_before_balance_change(&beneficiary_id);
+        if balance > 0 {
+            self.token.internal_transfer(
+                &account_id,
+                &beneficiary_id,
+                balance,
+                Some("inheritance".to_string()),
+            );
+        }
+        InheritanceClaim {
+            account_id: &account_id,
+            beneficiary_id: &beneficiary_id,
+            amount: &U128(balance),
+        }
+        .emit();
+        balance.into()
+    }
+
+    pub fn inheritance_status(&self, account_id: AccountId) -> Option<InheritanceStatus> {
+        self.inheritance_plans.get(&account_id).map(|plan| {
+            let claimable_at = plan.claimable_at();
+            InheritanceStatus {
+                plan,
+                claimable_at: claimable_at.into(),
+                claimable: env::block_timestamp() >= claimable_at,
+            }
+        })
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, STORAGE_DEPOSIT, TOTAL_SUPPLY};
+
+    const PERIOD: u64 = 1_000;
+
+    /// accounts(1) owns the supply and names accounts(2) as beneficiary at time 0.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(1).build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        for account in 2..4 {
+            contract.token.internal_register_account(&accounts(account));
+        }
+        contract.set_inheritance(accounts(2), PERIOD.into());
+        (context, contract)
+    }
+
+    #[test]
+    fn test_transfer_resets_timer() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.block_timestamp(PERIOD - 1).build());
+        contract.ft_transfer(accounts(3), U128(1), None);
+        testing_env!(context.block_timestamp(PERIOD).build());
+        let status = contract.inheritance_status(accounts(1)).unwrap();
+        assert_eq!(status.plan.last_activity.0, PERIOD - 1);
+        assert_eq!(status.claimable_at.0, 2 * PERIOD - 1);
+        assert!(!status.claimable);
+
+        // Receiving tokens isn't an activity.
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.ft_transfer(accounts(1), U128(1), None);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(2 * PERIOD)
+            .build());
+        assert!(contract.inheritance_status(accounts(1)).unwrap().claimable);
+        contract.heartbeat();
+        assert!(!contract.inheritance_status(accounts(1)).unwrap().claimable);
+    }
+
+    /// A merchant charging a subscription of the holder doesn't keep the holder alive.
+    #[test]
+    fn test_subscription_charge_keeps_timer() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        let subscription_id =
+            contract.create_subscription(accounts(3), U128(1), U64(PERIOD / 2), None);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .block_timestamp(PERIOD - 1)
+            .build());
+        contract.charge_subscription(subscription_id);
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, 1);
+        assert_eq!(contract.inheritance_status(accounts(1)).unwrap().plan.last_activity.0, 0);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).block_timestamp(PERIOD).build());
+        assert_eq!(contract.claim_inheritance(accounts(1)).0, TOTAL_SUPPLY - 1);
+    }
+
+    #[test]
+    fn test_claim_inheritance() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(2)).block_timestamp(PERIOD).build());
+        assert_eq!(contract.claim_inheritance(accounts(1)).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, 0);
+        assert!(contract.inheritance_status(accounts(1)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is not inactive long enough")]
+    fn test_claim_while_active() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(PERIOD - 1)
+            .build());
+        contract.claim_inheritance(accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the beneficiary can claim")]
+    fn test_claim_by_other_account() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(3)).block_timestamp(PERIOD).build());
+        contract.claim_inheritance(accounts(1));
+    }
+}
@@ -15,80 +15,592 @@ NOTES:
   - To prevent the deployed contract from being modified or deleted, it should not have any access
     keys on its account.
 */
+use std::collections::HashSet;
+
+use near_contract_standards::fungible_token::core::FungibleTokenCore;
 use near_contract_standards::fungible_token::metadata::{
-    FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC,
+    FungibleTokenMetadata, FungibleTokenMetadataProvider,
 };
+use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
 use near_contract_standards::fungible_token::FungibleToken;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LazyOption;
+use near_sdk::collections::{LazyOption, LookupMap, LookupSet, UnorderedMap, UnorderedSet, Vector};
 use near_sdk::json_types::U128;
-use near_sdk::{env, log, near_bindgen, AccountId, Balance, PanicOnDefault, PromiseOrValue};
+use near_sdk::{
+    env, log, near_bindgen, require, AccountId, Balance, BorshStorageKey, PanicOnDefault,
+    PromiseOrValue, PublicKey,
+};
+
+use crate::account_index::AccountIndex;
+use crate::auction::DutchAuction;
+use crate::buyback::BuybackInFlight;
+use crate::compliance::{Attestation, ComplianceRule};
+use crate::curve::BondingCurve;
+use crate::escrow::Escrow;
+use crate::forced_transfer::{ForcedTransferRecord, ForcedTransferRequest};
+use crate::governance::{GovernanceConfig, Proposal, Vote, MAX_OPEN_PROPOSALS};
+use crate::inheritance::InheritancePlan;
+use crate::lockup::LockedTransfer;
+use crate::math::U256;
+use crate::multi_token::{EstateShares, TokenId};
+use crate::oracle::{OracleConfig, PriceData};
+use crate::parcel::Parcel;
+use crate::rate_limit::{AccountTransferLimit, DEFAULT_TRANSFER_LIMIT_WINDOW};
+use crate::recovery::{PendingRecovery, RecoveryConfig};
+use crate::revenue::RevenueAccount;
+use crate::sale::SaleRound;
+use crate::stream::Stream;
+use crate::subscription::Subscription;
+use crate::treasury::{ClosedBalancePolicy, SpendProposal, SpendRecord, TreasuryCategory};
+
+mod account_index;
+pub mod auction;
+pub mod bridge;
+pub mod buyback;
+pub mod compliance;
+pub mod curve;
+pub mod escrow;
+pub mod events;
+pub mod forced_transfer;
+pub mod governance;
+pub mod inheritance;
+pub mod lockup;
+mod math;
+mod metadata;
+mod migrate;
+pub mod multi_token;
+pub mod oracle;
+mod owner;
+pub mod parcel;
+pub mod rate_limit;
+pub mod receiver;
+pub mod record_storage;
+pub mod recovery;
+pub mod revenue;
+pub mod sale;
+pub mod standards;
+pub mod stream;
+pub mod subscription;
+pub mod treasury;
+
+/// Storage prefixes of the collections added next to the token (`b"a"`) and metadata (`b"m"`).
+#[derive(BorshStorageKey, BorshSerialize)]
+pub(crate) enum StorageKey {
+    Escrows,
+    EscrowsPerAccount,
+    Streams,
+    StreamsPerAccount,
+    Subscriptions,
+    SubscriptionsPerPayer,
+    SubscriptionsPerMerchant,
+    LockedTransfers,
+    LockedTransfersPerReceiver,
+    LockedBalances,
+    TransferLimits,
+    Parcels,
+    ParcelsPerOwner,
+    ListedParcels,
+    RevenueAccounts,
+    EstateShares,
+    ShareBalances,
+    SaleRounds,
+    SaleWhitelist,
+    SalePurchases,
+    Auctions,
+    AuctionCommitments,
+    TreasuryBalances,
+    TreasuryCouncil,
+    SpendProposals,
+    SpendLog,
+    BalanceSnapshots,
+    Proposals,
+    ProposalVotes,
+    ComplianceOfficers,
+    Attestations,
+    PendingForcedTransfers,
+    ForcedTransfers,
+    ForcedTransfersPerAccount,
+    RecoveryConfigs,
+    PendingRecoveries,
+    InheritancePlans,
+    BridgeOperators,
+    BridgeUsedNonces,
+    CurveBalances,
+    RecordStorageCredits,
+    ClosedLockReceivers,
+}
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
     token: FungibleToken,
     metadata: LazyOption<FungibleTokenMetadata>,
+    /// Account allowed to call the admin methods.
+    owner_id: AccountId,
+    /// Open escrows by id. The escrowed tokens are held on the contract account.
+    escrows: UnorderedMap<u64, Escrow>,
+    escrows_per_account: AccountIndex,
+    next_escrow_id: u64,
+    /// NEAR prepaid by accounts for the storage of the records they open from `ft_on_transfer`.
+    record_storage_credits: LookupMap<AccountId, Balance>,
+    /// Active payment streams by id. The unpaid part of each stream is held on the contract
+    /// account.
+    streams: UnorderedMap<u64, Stream>,
+    streams_per_account: AccountIndex,
+    next_stream_id: u64,
+    /// Recurring pull payments by id, indexed by payer and by merchant.
+    subscriptions: UnorderedMap<u64, Subscription>,
+    subscriptions_per_payer: AccountIndex,
+    subscriptions_per_merchant: AccountIndex,
+    next_subscription_id: u64,
+    /// Time-locked buckets by id. The locked tokens are held on the contract account.
+    locked_transfers: UnorderedMap<u64, LockedTransfer>,
+    locked_transfers_per_receiver: AccountIndex,
+    /// Receiver -> total amount of their unclaimed buckets.
+    locked_balances: LookupMap<AccountId, Balance>,
+    /// Receivers of pending locked transfers that closed their account, which means they were
+    /// registered at some point, so their transfers can't be reclaimed.
+    closed_lock_receivers: LookupSet<AccountId>,
+    next_lock_id: u64,
+    /// Outflow limits and current window of the accounts that are limited.
+    transfer_limits: LookupMap<AccountId, AccountTransferLimit>,
+    global_transfer_cap: Option<Balance>,
+    transfer_limit_window: u64,
+    /// Estate parcels by id, indexed by owner, and the ids of those listed for sale.
+    parcels: UnorderedMap<u64, Parcel>,
+    parcels_per_owner: AccountIndex,
+    listed_parcels: UnorderedSet<u64>,
+    next_parcel_id: u64,
+    /// Magnified revenue per eligible token distributed so far, in ESTATES and in NEAR.
+    estates_per_token: U256,
+    near_per_token: U256,
+    revenue_accounts: LookupMap<AccountId, RevenueAccount>,
+    /// NEP-245 share tokens of fractionalized parcels by token id, and their balances.
+    estate_shares: UnorderedMap<TokenId, EstateShares>,
+    share_balances: LookupMap<(TokenId, AccountId), Balance>,
+    /// Sale rounds by id, with their whitelists and what each account bought in them.
+    sale_rounds: UnorderedMap<u64, SaleRound>,
+    sale_whitelist: LookupSet<(u64, AccountId)>,
+    sale_purchases: LookupMap<(u64, AccountId), Balance>,
+    next_sale_round_id: u64,
+    /// NEAR raised by the sale rounds and auctions that the owner hasn't withdrawn yet.
+    sale_proceeds: Balance,
+    /// Dutch auctions by id, and the NEAR committed by each bidder that isn't claimed yet.
+    auctions: UnorderedMap<u64, DutchAuction>,
+    auction_commitments: LookupMap<(u64, AccountId), Balance>,
+    next_auction_id: u64,
+    /// Bonding curve set at init, the tokens minted on it and the NEAR reserve backing them.
+    bonding_curve: Option<BondingCurve>,
+    curve_supply: Balance,
+    curve_reserve: Balance,
+    /// Tokens each account bought on the curve and hasn't sold back yet.
+    curve_balances: LookupMap<AccountId, Balance>,
+    /// Treasury funds per category, held on the contract account, and the council that approves
+    /// spending them along with the owner.
+    treasury_balances: LookupMap<TreasuryCategory, Balance>,
+    treasury_council: UnorderedSet<AccountId>,
+    treasury_council_threshold: u32,
+    /// Pending spending proposals by id, and the append-only log of the executed ones.
+    spend_proposals: UnorderedMap<u64, SpendProposal>,
+    next_spend_proposal_id: u64,
+    spend_log: Vector<SpendRecord>,
+    /// Governance proposals by id and the votes cast on them. Each proposal takes a new snapshot,
+    /// `balance_snapshots` holds the balances accounts had at the snapshots they changed after.
+    governance_config: GovernanceConfig,
+    governance_snapshot_id: u64,
+    balance_snapshots: LookupMap<AccountId, Vec<(u64, Balance)>>,
+    proposals: UnorderedMap<u64, Proposal>,
+    proposal_votes: LookupMap<(u64, AccountId), Vote>,
+    next_proposal_id: u64,
+    /// Every proposal before this one is closed.
+    first_open_proposal_id: u64,
+    /// NEAR set aside for the deposits of proposal actions.
+    governance_budget: Balance,
+    /// Accounts with the compliance role, the attestations they issued, and the rule that
+    /// requires them for large transfers.
+    compliance_officers: UnorderedSet<AccountId>,
+    attestations: LookupMap<AccountId, Attestation>,
+    compliance_rule: Option<ComplianceRule>,
+    /// Forced transfers waiting for more officer approvals, and the log of the executed ones
+    /// indexed by the accounts they moved tokens from and to.
+    forced_transfer_threshold: u32,
+    pending_forced_transfers: UnorderedMap<u64, ForcedTransferRequest>,
+    next_forced_transfer_id: u64,
+    forced_transfers: Vector<ForcedTransferRecord>,
+    forced_transfers_per_account: AccountIndex,
+    /// Guardians of the accounts that set them up, and the recoveries they started.
+    recovery_configs: LookupMap<AccountId, RecoveryConfig>,
+    pending_recoveries: LookupMap<AccountId, PendingRecovery>,
+    /// Beneficiaries of the accounts that opted in, with their last outgoing activity.
+    inheritance_plans: LookupMap<AccountId, InheritancePlan>,
+    /// Operator keys attesting the transfers coming back through the bridge, the source chain
+    /// nonces they used, and the tokens locked on the contract account by `bridge_out`.
+    bridge_operators: UnorderedSet<PublicKey>,
+    bridge_threshold: u32,
+    bridge_used_nonces: LookupSet<(String, u64)>,
+    next_bridge_nonce: u64,
+    bridge_locked: Balance,
+    /// Oracle the USD prices are converted with, and the latest price it answered.
+    oracle_config: Option<OracleConfig>,
+    latest_price: Option<PriceData>,
+    /// DEX the sale proceeds buy ESTATES back on, and what the buybacks spent and burned.
+    buyback_dex: Option<AccountId>,
+    buyback_spent: Balance,
+    buyback_burned: Balance,
+    /// ESTATES the DEX delivered to the buyback in flight, `None` when no buyback is.
+    buyback_received: Option<Balance>,
+    /// Swap of the buyback in flight, kept until `on_buyback` or `reset_buyback` settles it.
+    buyback_in_flight: Option<BuybackInFlight>,
+    closed_balance_policy: ClosedBalancePolicy,
 }
 
-const DATA_IMAGE_SVG_NEAR_ICON: &str = "data:image/svg+xml,%3Csvg id='Layer_2' data-name='Layer 2' xmlns='http://www.w3.org/2000/svg' xmlns:xlink='http://www.w3.org/1999/xlink' viewBox='0 0 71.68 59.2'%3E%3Cdefs%3E%3ClinearGradient id='linear-gradient' x1='35.84' y1='56.93' x2='35.84' y2='-1.76' gradientUnits='userSpaceOnUse'%3E%3Cstop offset='0' stop-color='%230e0c0d'/%3E%3Cstop offset='.54' stop-color='%23271f1b'/%3E%3Cstop offset='.99' stop-color='%2340332a'/%3E%3C/linearGradient%3E%3ClinearGradient id='linear-gradient-2' x1='35.84' y1='54.45' x2='35.84' y2='1.09' xlink:href='%23linear-gradient'/%3E%3ClinearGradient id='linear-gradient-3' x1='35.84' y1='54.88' x2='35.84' y2='14.08' gradientUnits='userSpaceOnUse'%3E%3Cstop offset='0' stop-color='%230e0c0d'/%3E%3Cstop offset='.29' stop-color='%23272322'/%3E%3Cstop offset='.89' stop-color='%23675f59'/%3E%3Cstop offset='.99' stop-color='%23736a63'/%3E%3C/linearGradient%3E%3C/defs%3E%3Cg id='Layer_1-2' data-name='Layer 1'%3E%3Cg%3E%3Ccircle cx='35.84' cy='29.6' r='29.35' style='fill: url(%23linear-gradient);'/%3E%3Cpath d='m35.84,59.2c-16.32,0-29.6-13.28-29.6-29.6S19.52,0,35.84,0s29.6,13.28,29.6,29.6-13.28,29.6-29.6,29.6Zm0-58.7C19.79.5,6.74,13.55,6.74,29.6s13.05,29.1,29.1,29.1,29.1-13.05,29.1-29.1S51.88.5,35.84.5Z' style='fill: %237f6c60;'/%3E%3C/g%3E%3Cg%3E%3Ccircle cx='35.84' cy='29.6' r='26.68' style='fill: url(%23linear-gradient-2);'/%3E%3Cpath d='m35.84,56.53c-14.85,0-26.93-12.08-26.93-26.93S20.99,2.67,35.84,2.67s26.93,12.08,26.93,26.93-12.08,26.93-26.93,26.93Zm0-53.37c-14.58,0-26.43,11.86-26.43,26.43s11.86,26.43,26.43,26.43,26.43-11.86,26.43-26.43S50.42,3.17,35.84,3.17Z' style='fill: %23966e4d;'/%3E%3C/g%3E%3Ccircle cx='35.84' cy='29.6' r='24.08' style='fill: %233e657e;'/%3E%3Cg%3E%3Cg%3E%3Cg%3E%3Crect x='29.31' y='8.41' width='12.5' height='7.06' style='fill: %23c6b673;'/%3E%3Cpath d='m42.06,15.72h-13v-7.56h13v7.56Zm-12.5-.5h12v-6.56h-12v6.56Z' style='fill: %23fff;'/%3E%3C/g%3E%3Cg%3E%3Cpolygon points='43.48 8.41 27.64 8.41 35.56 3.93 43.48 8.41' style='fill: %23c6b673;'/%3E%3Cpath d='m44.43,8.66h-17.73l8.87-5.01,8.87,5.01Zm-15.83-.5h13.93l-6.97-3.94-6.97,3.94Z' style='fill: %23fff;'/%3E%3C/g%3E%3Cg%3E%3Cpath d='m37.02,11.74s-.26-.87-1.46-.87-1.46.87-1.46.87v3.73h2.91v-3.73Z' style='fill: %230e0c0d;'/%3E%3Cpath d='m37.27,15.72h-3.41v-4.05s.34-1.05,1.71-1.05,1.68,1.01,1.7,1.05v.07s.01,3.98.01,3.98Zm-2.91-.5h2.41v-3.44c-.06-.14-.33-.67-1.21-.67s-1.15.53-1.21.67v3.44Z' style='fill: %23fff;'/%3E%3C/g%3E%3Cg%3E%3Crect x='23.11' y='10.89' width='6.2' height='4.58' style='fill: %23c6b673;'/%3E%3Cpath d='m29.56,15.72h-6.7v-5.08h6.7v5.08Zm-6.2-.5h5.7v-4.08h-5.7v4.08Z' style='fill: %23fff;'/%3E%3C/g%3E%3Cg%3E%3Crect x='41.81' y='10.89' width='6.2' height='4.58' style='fill: %23c6b673;'/%3E%3Cpath d='m48.26,15.72h-6.7v-5.08h6.7v5.08Zm-6.2-.5h5.7v-4.08h-5.7v4.08Z' style='fill: %23fff;'/%3E%3C/g%3E%3C/g%3E%3Cg%3E%3Cg%3E%3Cline x1='23.43' y1='11.11' x2='29.14' y2='15.15' style='fill: %230e0c0d;'/%3E%3Crect x='26.03' y='9.63' width='.5' height='6.99' transform='translate(.39 27) rotate(-54.72)' style='fill: %23fff;'/%3E%3C/g%3E%3Cg%3E%3Cline x1='41.81' y1='15.47' x2='48.01' y2='10.89' style='fill: %230e0c0d;'/%3E%3Crect x='41.06' y='12.93' width='7.71' height='.5' transform='translate(.95 29.24) rotate(-36.42)' style='fill: %23fff;'/%3E%3C/g%3E%3Cpolygon points='41.67 15.35 36.87 11.95 36.88 11.53 41.67 8.41 41.95 8.83 37.46 11.75 41.96 14.95 41.67 15.35' style='fill: %23fff;'/%3E%3Cpolygon points='29.47 15.67 29.16 15.28 33.68 11.76 29.17 8.73 29.45 8.31 34.24 11.54 34.26 11.94 29.47 15.67' style='fill: %23fff;'/%3E%3Crect x='35.31' y='4.18' width='.5' height='4.23' style='fill: %23fff;'/%3E%3C/g%3E%3C/g%3E%3Cg%3E%3Cpath d='m71.43,29.6c0-5.02-1.04-9.79-2.92-14.12H3.17C1.29,19.8.25,24.58.25,29.6s1.08,9.99,3.03,14.39h10.08c4.74,7.4,13.04,12.3,22.48,12.3s17.73-4.9,22.48-12.3h10.08c1.95-4.4,3.03-9.27,3.03-14.39Z' style='fill: url(%23linear-gradient-3);'/%3E%3Cpath d='m35.84,56.53c-9.18,0-17.62-4.59-22.61-12.3H3.12l-.07-.15c-2.02-4.58-3.05-9.45-3.05-14.49s.99-9.72,2.94-14.22l.07-.15h65.68l.07.15c1.95,4.5,2.94,9.29,2.94,14.22s-1.03,9.91-3.05,14.49l-.07.15h-10.11c-5,7.7-13.44,12.3-22.61,12.3ZM3.44,43.73h10.06l.07.12c4.89,7.63,13.22,12.18,22.27,12.18s17.37-4.55,22.27-12.18l.07-.12h10.06c1.95-4.47,2.94-9.22,2.94-14.14s-.95-9.48-2.83-13.87H3.33c-1.88,4.39-2.83,9.06-2.83,13.87s.99,9.67,2.94,14.14Z' style='fill: %23966e4d;'/%3E%3C/g%3E%3Cg%3E%3Cg%3E%3Cpath d='m11.51,19.29h1.46v6.71h-1.49l-3.77-4.49v4.49h-1.44v-6.71h1.49l3.75,4.47v-4.47Z' style='fill: %23fff;'/%3E%3Cpath d='m20.23,20.74h-4.73v1.17h3.81v1.45h-3.81v1.17h4.73v1.45h-6.19v-6.71h6.19v1.45Z' style='fill: %23fff;'/%3E%3Cpath d='m26.49,19.29c.76,0,1.39.61,1.39,1.38v5.33h-1.46v-2.16h-3.8v2.16h-1.44v-5.33c0-.76.61-1.38,1.38-1.38h3.94Zm-.07,3.09v-1.64h-3.8v1.64h3.8Z' style='fill: %23fff;'/%3E%3Cpath d='m35.64,22.41c0,.75-.62,1.38-1.39,1.38h-.07c.48.56.99,1.16,1.45,1.71v.49h-1.49l-1.85-2.21h-1.92s.03.03.03.07c0,0-.02,0-.03,0v2.15h-1.44v-6.7h5.32c.76,0,1.39.62,1.39,1.38v1.73Zm-5.26-.07h3.8v-1.59h-3.8v1.59Z' style='fill: %23fff;'/%3E%3C/g%3E%3Cg%3E%3Cpath d='m45.76,19.29v6.71h-1.23v-2.74h-6.66v2.74h-1.23v-6.71h1.23v2.74h6.66v-2.74h1.23Z' style='fill: %23fff;'/%3E%3Cpath d='m54.7,19.29h1.24v5.41c0,.72-.59,1.29-1.3,1.29h-6.38c-.72,0-1.29-.58-1.29-1.29v-5.41h1.23v5.41s.03.07.07.07h6.38s.06-.03.06-.07v-5.41Z' style='fill: %23fff;'/%3E%3Cpath d='m65.85,21.9c0,.18-.03.34-.09.48.21.22.36.55.36.88v1.44c0,.72-.59,1.29-1.3,1.29h-7.67v-6.71h7.41c.71,0,1.29.58,1.29,1.29v1.31Zm-7.41-1.38s-.07.03-.07.07v1.31s.03.07.07.07h6.11s.06-.03.06-.07v-1.31s-.03-.07-.06-.07h-6.11Zm6.44,2.74s-.03-.06-.07-.06h-6.37s-.07.02-.07.06v1.44s.03.07.07.07h6.37s.07-.03.07-.07v-1.44Z' style='fill: %23fff;'/%3E%3C/g%3E%3C/g%3E%3Cg%3E%3Cpath d='m6.33,28.62h6.04s0,1.01,0,1.01h-4.98s0,4.16,0,4.16h4.64s0,.99,0,.99h-4.64s0,4.43,0,4.43h4.98s0,1.01,0,1.01h-6.04s-.02-11.59-.02-11.59Z' style='fill: %23fff;'/%3E%3Cpath d='m20.78,29.09v1.22c-.83-.61-1.81-.92-2.7-.92-1.23,0-2.37.63-2.37,1.94,0,1.14.88,1.69,2.6,2.46,1.8.84,3.08,1.59,3.08,3.35,0,2.12-1.74,3.3-3.7,3.3-1.33,0-2.47-.54-3.16-1.09v-1.33c.8.84,2.01,1.37,3.17,1.37,1.33,0,2.59-.75,2.59-2.16,0-1.19-.88-1.76-2.64-2.57-1.78-.82-3.03-1.49-3.03-3.26,0-2.01,1.62-3.06,3.49-3.07,1.05,0,2.02.34,2.67.75Z' style='fill: %23fff;'/%3E%3Cpath d='m26.29,29.6h-3.75s0-1.01,0-1.01h8.57s0,1,0,1h-3.75s.02,10.59.02,10.59h-1.08s-.02-10.59-.02-10.59Z' style='fill: %23fff;'/%3E%3Cpath d='m39.94,40.17l-1.49-3.76h-5.19s-1.48,3.77-1.48,3.77h-1.16s4.62-11.61,4.62-11.61h1.19s4.67,11.59,4.67,11.59h-1.17Zm-1.88-4.79l-2.21-5.54-2.19,5.55h4.4Z' style='fill: %23fff;'/%3E%3Cpath d='m44.35,29.57h-3.75s0-1.01,0-1.01h8.57s0,1,0,1h-3.75s.02,10.59.02,10.59h-1.08s-.02-10.59-.02-10.59Z' style='fill: %23fff;'/%3E%3Cpath d='m51.23,28.55h6.04s0,1.01,0,1.01h-4.98s0,4.16,0,4.16h4.64s0,.99,0,.99h-4.64s0,4.43,0,4.43h4.98s0,1.01,0,1.01h-6.04s-.02-11.59-.02-11.59Z' style='fill: %23fff;'/%3E%3Cpath d='m65.68,29.02v1.22c-.83-.61-1.81-.92-2.7-.92-1.23,0-2.37.63-2.37,1.94,0,1.14.88,1.69,2.6,2.46,1.8.84,3.08,1.59,3.08,3.35,0,2.12-1.74,3.3-3.7,3.3-1.33,0-2.47-.54-3.16-1.09v-1.33c.8.84,2.01,1.37,3.17,1.37,1.33,0,2.59-.75,2.59-2.16,0-1.19-.88-1.76-2.64-2.57-1.78-.82-3.03-1.49-3.03-3.26,0-2.01,1.62-3.06,3.49-3.07,1.05,0,2.02.34,2.67.75Z' style='fill: %23fff;'/%3E%3C/g%3E%3Cg%3E%3Cpath d='m20.73,42.72v.4h-.88v1.77h-.4v-1.77h-.88v-.4h2.16Z' style='fill: %23fff;'/%3E%3Cpath d='m28.27,42.72c.23,0,.42.19.42.42v1.33c0,.23-.19.42-.42.42h-1.33c-.23,0-.42-.19-.42-.42v-1.33c0-.23.19-.42.42-.42h1.33Zm0,1.77s.02,0,.02-.02v-1.33s0-.02-.02-.02h-1.33s-.02,0-.02.02v1.33s0,.02.02.02h1.33Z' style='fill: %23fff;'/%3E%3Cpath d='m36.68,42.72v.1l-.82.98.82.98v.11h-.44l-.74-.88h-.51v.88h-.4v-2.16h.4v.88h.51c.24-.29.5-.6.74-.88h.44Z' style='fill: %23fff;'/%3E%3Cpath d='m44.56,43.12h-1.59v.49h1.28v.4h-1.28v.49h1.59v.4h-1.99v-2.16h1.99v.4Z' style='fill: %23fff;'/%3E%3Cpath d='m52.21,42.72h.4v2.16h-.43l-1.33-1.59v1.59h-.4v-2.16h.43l1.33,1.59v-1.59Z' style='fill: %23fff;'/%3E%3C/g%3E%3Cpath d='m20.13,47.35c4.17,3.73,9.67,5.99,15.71,5.99s11.54-2.27,15.71-5.99h-31.42Z' style='fill: %2384763a;'/%3E%3C/g%3E%3C/svg%3E";
-
 #[near_bindgen]
 impl Contract {
     /// Initializes the contract with the given total supply owned by the given `owner_id` with
-    /// default metadata (for example purposes only).
+    /// the default metadata, built from the files of `assets/`.
     #[init]
     pub fn new_default_meta(owner_id: AccountId, total_supply: U128) -> Self {
-        Self::new(
-            owner_id,
-            total_supply,
-            FungibleTokenMetadata {
-                spec: FT_METADATA_SPEC.to_string(),
-                name: "NEAR Hub Estates".to_string(),
-                symbol: "ESTATES".to_string(),
-                icon: Some(DATA_IMAGE_SVG_NEAR_ICON.to_string()),
-                reference: None,
-                reference_hash: None,
-                decimals: 24,
-            },
-        )
+        Self::new(owner_id, total_supply, metadata::default_metadata(), None)
     }
 
     /// Initializes the contract with the given total supply owned by the given `owner_id` with
-    /// the given fungible token metadata.
+    /// the given fungible token metadata, and optionally a bonding curve to mint and redeem
+    /// tokens against NEAR.
     #[init]
     pub fn new(
         owner_id: AccountId,
         total_supply: U128,
         metadata: FungibleTokenMetadata,
+        bonding_curve: Option<BondingCurve>,
+    ) -> Self {
+        let mut this = Self::internal_new(owner_id.clone(), metadata, bonding_curve);
+        this.internal_mint(&owner_id, total_supply.into(), "Initial tokens supply is minted");
+        this
+    }
+
+    /// Initializes the contract like [`Contract::new`], but distributes the total supply between
+    /// the accounts of `allocations` instead of crediting it all to `owner_id`. Every account is
+    /// registered, at the expense of the contract account, and the mints are logged as a single
+    /// `ft_mint` event. The allocations should add up to `total_supply`, and list each account
+    /// once with a positive amount.
+    #[init]
+    pub fn new_with_allocations(
+        owner_id: AccountId,
+        total_supply: U128,
+        allocations: Vec<(AccountId, U128)>,
+        metadata: FungibleTokenMetadata,
+        bonding_curve: Option<BondingCurve>,
+    ) -> Self {
+        let allocated =
+            allocations.iter().try_fold(0, |sum: Balance, (_, amount)| sum.checked_add(amount.0));
+        require!(
+            allocated == Some(total_supply.0),
+            "The allocations don't add up to the total supply"
+        );
+        let mut this = Self::internal_new(owner_id, metadata, bonding_curve);
+        let mut allocated_accounts = HashSet::new();
+        for (account_id, amount) in allocations.iter() {
+            require!(amount.0 > 0, "The amount should be a positive number");
+            require!(
+                allocated_accounts.insert(account_id),
+                format!("{} is allocated more than once", account_id)
+            );
+            if !this.token.accounts.contains_key(account_id) {
+                this.token.internal_register_account(account_id);
+            }
+            this.internal_before_balance_change(account_id);
+            this.token.internal_deposit(account_id, amount.0);
+        }
+        let mints: Vec<_> = allocations
+            .iter()
+            .map(|(account_id, amount)| near_contract_standards::fungible_token::events::FtMint {
+                owner_id: account_id,
+                amount,
+                memo: Some("Initial tokens supply is minted"),
+            })
+            .collect();
+        near_contract_standards::fungible_token::events::FtMint::emit_many(&mints);
+        this
+    }
+
+    /// State of a new contract without any tokens: only `owner_id` and the contract account are
+    /// registered.
+    fn internal_new(
+        owner_id: AccountId,
+        metadata: FungibleTokenMetadata,
+        bonding_curve: Option<BondingCurve>,
     ) -> Self {
         assert!(!env::state_exists(), "Already initialized");
         metadata.assert_valid();
+        if let Some(curve) = bonding_curve.as_ref() {
+            curve.assert_valid();
+        }
+        let mut this = Self::internal_with_token(
+            FungibleToken::new(b"a".to_vec()),
+            LazyOption::new(b"m".to_vec(), Some(&metadata)),
+            owner_id.clone(),
+            bonding_curve,
+        );
+        this.token.internal_register_account(&owner_id);
+        // The contract account holds the tokens of escrows and other in-contract deals.
+        if owner_id != env::current_account_id() {
+            this.token.internal_register_account(&env::current_account_id());
+        }
+        this
+    }
+
+    /// State around an existing token and metadata, with everything else empty.
+    fn internal_with_token(
+        token: FungibleToken,
+        metadata: LazyOption<FungibleTokenMetadata>,
+        owner_id: AccountId,
+        bonding_curve: Option<BondingCurve>,
+    ) -> Self {
         let mut this = Self {
-            token: FungibleToken::new(b"a".to_vec()),
-            metadata: LazyOption::new(b"m".to_vec(), Some(&metadata)),
+            token,
+            metadata,
+            owner_id,
+            escrows: UnorderedMap::new(StorageKey::Escrows),
+            escrows_per_account: AccountIndex::new(StorageKey::EscrowsPerAccount),
+            next_escrow_id: 0,
+            record_storage_credits: LookupMap::new(StorageKey::RecordStorageCredits),
+            streams: UnorderedMap::new(StorageKey::Streams),
+            streams_per_account: AccountIndex::new(StorageKey::StreamsPerAccount),
+            next_stream_id: 0,
+            subscriptions: UnorderedMap::new(StorageKey::Subscriptions),
+            subscriptions_per_payer: AccountIndex::new(StorageKey::SubscriptionsPerPayer),
+            subscriptions_per_merchant: AccountIndex::new(StorageKey::SubscriptionsPerMerchant),
+            next_subscription_id: 0,
+            locked_transfers: UnorderedMap::new(StorageKey::LockedTransfers),
+            locked_transfers_per_receiver: AccountIndex::new(
+                StorageKey::LockedTransfersPerReceiver,
+            ),
+            locked_balances: LookupMap::new(StorageKey::LockedBalances),
+            closed_lock_receivers: LookupSet::new(StorageKey::ClosedLockReceivers),
+            next_lock_id: 0,
+            transfer_limits: LookupMap::new(StorageKey::TransferLimits),
+            global_transfer_cap: None,
+            transfer_limit_window: DEFAULT_TRANSFER_LIMIT_WINDOW,
+            parcels: UnorderedMap::new(StorageKey::Parcels),
+            parcels_per_owner: AccountIndex::new(StorageKey::ParcelsPerOwner),
+            listed_parcels: UnorderedSet::new(StorageKey::ListedParcels),
+            next_parcel_id: 0,
+            estates_per_token: U256::zero(),
+            near_per_token: U256::zero(),
+            revenue_accounts: LookupMap::new(StorageKey::RevenueAccounts),
+            estate_shares: UnorderedMap::new(StorageKey::EstateShares),
+            share_balances: LookupMap::new(StorageKey::ShareBalances),
+            sale_rounds: UnorderedMap::new(StorageKey::SaleRounds),
+            sale_whitelist: LookupSet::new(StorageKey::SaleWhitelist),
+            sale_purchases: LookupMap::new(StorageKey::SalePurchases),
+            next_sale_round_id: 0,
+            sale_proceeds: 0,
+            auctions: UnorderedMap::new(StorageKey::Auctions),
+            auction_commitments: LookupMap::new(StorageKey::AuctionCommitments),
+            next_auction_id: 0,
+            bonding_curve,
+            curve_supply: 0,
+            curve_reserve: 0,
+            curve_balances: LookupMap::new(StorageKey::CurveBalances),
+            treasury_balances: LookupMap::new(StorageKey::TreasuryBalances),
+            treasury_council: UnorderedSet::new(StorageKey::TreasuryCouncil),
+            treasury_council_threshold: 1,
+            spend_proposals: UnorderedMap::new(StorageKey::SpendProposals),
+            next_spend_proposal_id: 0,
+            spend_log: Vector::new(StorageKey::SpendLog),
+            governance_config: GovernanceConfig::default(),
+            governance_snapshot_id: 0,
+            balance_snapshots: LookupMap::new(StorageKey::BalanceSnapshots),
+            proposals: UnorderedMap::new(StorageKey::Proposals),
+            proposal_votes: LookupMap::new(StorageKey::ProposalVotes),
+            next_proposal_id: 0,
+            first_open_proposal_id: 0,
+            governance_budget: 0,
+            compliance_officers: UnorderedSet::new(StorageKey::ComplianceOfficers),
+            attestations: LookupMap::new(StorageKey::Attestations),
+            compliance_rule: None,
+            forced_transfer_threshold: 1,
+            pending_forced_transfers: UnorderedMap::new(StorageKey::PendingForcedTransfers),
+            next_forced_transfer_id: 0,
+            forced_transfers: Vector::new(StorageKey::ForcedTransfers),
+            forced_transfers_per_account: AccountIndex::new(StorageKey::ForcedTransfersPerAccount),
+            recovery_configs: LookupMap::new(StorageKey::RecoveryConfigs),
+            pending_recoveries: LookupMap::new(StorageKey::PendingRecoveries),
+            inheritance_plans: LookupMap::new(StorageKey::InheritancePlans),
+            bridge_operators: UnorderedSet::new(StorageKey::BridgeOperators),
+            bridge_threshold: 0,
+            bridge_used_nonces: LookupSet::new(StorageKey::BridgeUsedNonces),
+            next_bridge_nonce: 0,
+            bridge_locked: 0,
+            oracle_config: None,
+            latest_price: None,
+            buyback_dex: None,
+            buyback_spent: 0,
+            buyback_burned: 0,
+            buyback_received: None,
+            buyback_in_flight: None,
+            closed_balance_policy: ClosedBalancePolicy::Burn,
         };
-        this.token.internal_register_account(&owner_id);
-        this.token.internal_deposit(&owner_id, total_supply.into());
+        this.internal_measure_account_storage();
+        this
+    }
+
+    /// Adds the largest records the contract keeps for every holder to the storage that a
+    /// registration pays for, measured like `FungibleToken::new` measures the balance entry.
+    fn internal_measure_account_storage(&mut self) {
+        let initial_storage = env::storage_usage();
+        let account_id = AccountId::new_unchecked("a".repeat(64));
+        let snapshots = vec![(u64::MAX, Balance::MAX); MAX_OPEN_PROPOSALS as usize];
+        self.balance_snapshots.insert(&account_id, &snapshots);
+        self.revenue_accounts.insert(&account_id, &RevenueAccount::default());
+        self.token.account_storage_usage += env::storage_usage() - initial_storage;
+        self.balance_snapshots.remove(&account_id);
+        self.revenue_accounts.remove(&account_id);
+    }
+
+    /// Checks every transfer that moves tokens out of `sender_id`, whichever path it comes from.
+    fn internal_before_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) {
+        self.internal_check_transfer_limit(sender_id, amount);
+        self.internal_check_compliance(sender_id, receiver_id, amount);
+        self.internal_record_activity(sender_id);
+        self.internal_before_balance_change(sender_id);
+        self.internal_before_balance_change(receiver_id);
+    }
+
+    /// Brings what depends on the balance of `account_id` up to date. Must run before every
+    /// change of that balance.
+    fn internal_before_balance_change(&mut self, account_id: &AccountId) {
+        self.internal_settle_revenue(account_id);
+        self.internal_record_snapshot(account_id);
+    }
+
+    /// Creates `amount` new tokens on the balance of `account_id`.
+    pub(crate) fn internal_mint(&mut self, account_id: &AccountId, amount: Balance, memo: &str) {
+        self.internal_before_balance_change(account_id);
+        self.token.internal_deposit(account_id, amount);
         near_contract_standards::fungible_token::events::FtMint {
-            owner_id: &owner_id,
-            amount: &total_supply,
-            memo: Some("Initial tokens supply is minted"),
+            owner_id: account_id,
+            amount: &U128(amount),
+            memo: Some(memo),
         }
         .emit();
-        this
+    }
+
+    /// Destroys `amount` tokens from the balance of `account_id`. The tokens leave the account, so
+    /// the burn counts against its outflow limit like a transfer.
+    pub(crate) fn internal_burn(&mut self, account_id: &AccountId, amount: Balance, memo: &str) {
+        self.internal_check_transfer_limit(account_id, amount);
+        self.internal_before_balance_change(account_id);
+        self.internal_record_activity(account_id);
+        self.token.internal_withdraw(account_id, amount);
+        near_contract_standards::fungible_token::events::FtBurn {
+            owner_id: account_id,
+            amount: &U128(amount),
+            memo: Some(memo),
+        }
+        .emit();
+    }
+
+    /// Transfers between internal balances. In-contract features must use this instead of
+    /// `self.token.internal_transfer`, so the transfer checks apply to them as well.
+    pub(crate) fn internal_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        self.internal_before_transfer(sender_id, receiver_id, amount);
+        self.token.internal_transfer(sender_id, receiver_id, amount, memo);
     }
 
     fn on_account_closed(&mut self, account_id: AccountId, balance: Balance) {
+        if balance > 0 && self.closed_balance_policy == ClosedBalancePolicy::Forbid {
+            env::panic_str("Can't force unregister an account with a positive balance");
+        }
+        // Revenue that was not claimed before closing the account is forfeited.
+        self.revenue_accounts.remove(&account_id);
+        self.internal_record_snapshot_balance(&account_id, balance);
+        self.internal_remove_recovery_config(&account_id, &account_id);
+        self.inheritance_plans.remove(&account_id);
+        if self.locked_balances.contains_key(&account_id) {
+            self.closed_lock_receivers.insert(&account_id);
+        }
         log!("Closed @{} with {}", account_id, balance);
+        if balance > 0 {
+            // `internal_storage_unregister` took the balance out of the total supply silently.
+            near_contract_standards::fungible_token::events::FtBurn {
+                owner_id: &account_id,
+                amount: &U128(balance),
+                memo: Some("account closed"),
+            }
+            .emit();
+            self.internal_collect_closed_balance(&account_id, balance);
+        }
     }
 
     fn on_tokens_burned(&mut self, account_id: AccountId, amount: Balance) {
+        // `internal_ft_resolve_transfer` already emitted the `ft_burn` event of the refund.
         log!("Account @{} burned {}", account_id, amount);
+        self.internal_collect_closed_balance(&account_id, amount);
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenCore for Contract {
+    #[payable]
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
+        self.internal_before_transfer(&env::predecessor_account_id(), &receiver_id, amount.into());
+        self.token.ft_transfer(receiver_id, amount, memo)
+    }
+
+    #[payable]
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.internal_before_transfer(&env::predecessor_account_id(), &receiver_id, amount.into());
+        self.token.ft_transfer_call(receiver_id, amount, memo, msg)
+    }
+
+    fn ft_total_supply(&self) -> U128 {
+        self.token.ft_total_supply()
+    }
+
+    fn ft_balance_of(&self, account_id: AccountId) -> U128 {
+        self.token.ft_balance_of(account_id)
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenResolver for Contract {
+    #[private]
+    fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        // Refunds move tokens back from the receiver, outside of the transfer checks.
+        self.internal_before_balance_change(&sender_id);
+        self.internal_before_balance_change(&receiver_id);
+        let (used_amount, burned_amount) =
+            self.token.internal_ft_resolve_transfer(&sender_id, receiver_id, amount);
+        if burned_amount > 0 {
+            self.on_tokens_burned(sender_id, burned_amount);
+        }
+        used_amount.into()
     }
 }
 
-near_contract_standards::impl_fungible_token_core!(Contract, token, on_tokens_burned);
 near_contract_standards::impl_fungible_token_storage!(Contract, token, on_account_closed);
 
 #[near_bindgen]
@@ -100,15 +612,30 @@ impl FungibleTokenMetadataProvider for Contract {
 
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::MockedBlockchain;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, get_logs, VMContextBuilder};
     use near_sdk::{testing_env, Balance};
 
     use super::*;
 
-    const TOTAL_SUPPLY: Balance = 1_000_000_000_000_000;
+    pub(crate) const TOTAL_SUPPLY: Balance = 1_000_000_000_000_000;
+    /// Covers the storage of any record in the tests, the excess is refunded.
+    pub(crate) const STORAGE_DEPOSIT: Balance = 100_000_000_000_000_000_000_000;
+
+    /// NEAR transferred to `account_id` by the receipts of the last call.
+    pub(crate) fn near_sent_to(account_id: &AccountId) -> Balance {
+        get_created_receipts()
+            .into_iter()
+            .filter(|receipt| &receipt.receiver_id == account_id)
+            .flat_map(|receipt| receipt.actions)
+            .map(|action| match action {
+                VmAction::Transfer { deposit } => deposit,
+                _ => 0,
+            })
+            .sum()
+    }
 
-    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+    pub(crate) fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
         let mut builder = VMContextBuilder::new();
         builder
             .current_account_id(accounts(0))
@@ -121,12 +648,54 @@ mod tests {
     fn test_new() {
         let mut context = get_context(accounts(1));
         testing_env!(context.build());
-        let contract = Contract::new_default_meta(accounts(1).into(), TOTAL_SUPPLY.into());
+        let contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
         testing_env!(context.is_view(true).build());
         assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
         assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY);
     }
 
+    #[test]
+    fn test_new_with_allocations() {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let allocations = vec![
+            (accounts(1), U128(TOTAL_SUPPLY / 2)),
+            (accounts(2), U128(TOTAL_SUPPLY / 4)),
+            (accounts(3), U128(TOTAL_SUPPLY / 4)),
+        ];
+        let contract = Contract::new_with_allocations(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            allocations,
+            metadata::default_metadata(),
+            None,
+        );
+        let logs = get_logs();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].matches(r#""owner_id""#).count(), 3);
+        testing_env!(context.is_view(true).build());
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY / 2);
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, TOTAL_SUPPLY / 4);
+        assert!(contract.storage_balance_of(accounts(2)).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "The allocations don't add up to the total supply")]
+    fn test_allocations_below_total_supply() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let allocations =
+            vec![(accounts(1), U128(TOTAL_SUPPLY / 2)), (accounts(2), U128(TOTAL_SUPPLY / 4))];
+        Contract::new_with_allocations(
+            accounts(1),
+            TOTAL_SUPPLY.into(),
+            allocations,
+            metadata::default_metadata(),
+            None,
+        );
+    }
+
     #[test]
     #[should_panic(expected = "The contract is not initialized")]
     fn test_default() {
@@ -139,7 +708,7 @@ mod tests {
     fn test_transfer() {
         let mut context = get_context(accounts(2));
         testing_env!(context.build());
-        let mut contract = Contract::new_default_meta(accounts(2).into(), TOTAL_SUPPLY.into());
+        let mut contract = Contract::new_default_meta(accounts(2), TOTAL_SUPPLY.into());
         testing_env!(context
             .storage_usage(env::storage_usage())
             .attached_deposit(contract.storage_balance_bounds().min.into())
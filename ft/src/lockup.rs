@@ -0,0 +1,304 @@
+/*!
+Time-locked transfers for bonuses and deferred payouts.
+
+`ft_transfer_locked` moves tokens from the sender to the contract account into a locked bucket
+for the receiver. The receiver doesn't need to be registered yet. Once `unlock_at` has passed,
+the receiver collects matured buckets by id with `claim_unlocked`.
+If the receiver never registered while the bucket was pending, the sender can take it back with
+`reclaim_locked` from `LOCK_RECLAIM_DELAY` after `unlock_at`.
+
+The sender pays for the storage of the bucket with the deposit attached to `ft_transfer_locked`,
+and gets it back when the bucket is claimed or reclaimed.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance};
+
+use crate::events::{LockRelease, TransferLocked};
+use crate::record_storage::{charge_attached_storage, refund_record_storage};
+use crate::*;
+
+/// 90 days in nanoseconds.
+pub const LOCK_RECLAIM_DELAY: u64 = 90 * 24 * 3600 * 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LockedTransfer {
+    pub lock_id: U64,
+    pub sender_id: AccountId,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+    /// Timestamp in nanoseconds from which the receiver can claim the tokens.
+    pub unlock_at: U64,
+    pub memo: Option<String>,
+    /// NEAR the sender paid for the storage of the bucket.
+    pub storage_deposit: U128,
+}
+
+impl Contract {
+    fn internal_remove_lock(&mut self, lock: &LockedTransfer) {
+        self.locked_transfers.remove(&lock.lock_id.0);
+        self.locked_transfers_per_receiver.remove(&lock.receiver_id, lock.lock_id.0);
+        let locked = self.locked_balances.get(&lock.receiver_id).unwrap_or(0) - lock.amount.0;
+        if locked == 0 {
+            self.locked_balances.remove(&lock.receiver_id);
+            self.closed_lock_receivers.remove(&lock.receiver_id);
+        } else {
+            self.locked_balances.insert(&lock.receiver_id, &locked);
+        }
+        refund_record_storage(&lock.sender_id, lock.storage_deposit);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Transfers `amount` tokens to `receiver_id` that can only be claimed from `unlock_at`
+    /// (nanoseconds). Requires a deposit that covers the storage of the bucket, the excess is
+    /// refunded.
+    #[payable]
+    pub fn ft_transfer_locked(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        unlock_at: U64,
+        memo: Option<String>,
+    ) -> U64 {
+        let sender_id = env::predecessor_account_id();
+        require!(sender_id != receiver_id, "Sender and receiver should be different");
+        require!(unlock_at.0 > env::block_timestamp(), "Unlock time should be in the future");
+        self.internal_transfer(
+            &sender_id,
+            &env::current_account_id(),
+            amount.0,
+            Some(format!("locked for {}", receiver_id)),
+        );
+
+        let initial_storage = env::storage_usage();
+        let lock_id = self.next_lock_id;
+        self.next_lock_id += 1;
+        let mut lock = LockedTransfer {
+            lock_id: lock_id.into(),
+            sender_id,
+            receiver_id,
+            amount,
+            unlock_at,
+            memo,
+            storage_deposit: U128(0),
+        };
+        self.locked_transfers.insert(&lock_id, &lock);
+        self.locked_transfers_per_receiver.insert(&lock.receiver_id, lock_id);
+        let locked = self.locked_balances.get(&lock.receiver_id).unwrap_or(0) + amount.0;
+        self.locked_balances.insert(&lock.receiver_id, &locked);
+        lock.storage_deposit = charge_attached_storage(initial_storage).into();
+        self.locked_transfers.insert(&lock_id, &lock);
+        TransferLocked {
+            lock_id: lock.lock_id,
+            sender_id: &lock.sender_id,
+            receiver_id: &lock.receiver_id,
+            amount: &lock.amount,
+            unlock_at: lock.unlock_at,
+            memo: lock.memo.as_deref(),
+        }
+        .emit();
+        lock.lock_id
+    }
+
+    /// Releases the matured buckets `lock_ids` of the predecessor to their balance and returns the
+    /// claimed amount. The predecessor must be registered. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn claim_unlocked(&mut self, lock_ids: Vec<U64>) -> U128 {
+        assert_one_yocto();
+        let receiver_id = env::predecessor_account_id();
+        let now = env::block_timestamp();
+        let mut matured = Vec::with_capacity(lock_ids.len());
+        for lock_id in lock_ids {
+            let lock = self
+                .locked_transfers
+                .get(&lock_id.0)
+                .unwrap_or_else(|| env::panic_str("Locked transfer not found"));
+            require!(
+                lock.receiver_id == receiver_id,
+                "Only the receiver can claim the locked transfer"
+            );
+            require!(lock.unlock_at.0 <= now, "The locked transfer is not unlocked yet");
+            // Removed right away, so a repeated id is not found.
+            self.internal_remove_lock(&lock);
+            matured.push(lock);
+        }
+        let claimed: Balance = matured.iter().map(|lock| lock.amount.0).sum();
+        if claimed > 0 {
+            self.internal_transfer(
+                &env::current_account_id(),
+                &receiver_id,
+                claimed,
+                Some("unlocked".to_string()),
+            );
+            LockRelease {
+                lock_ids: matured.iter().map(|lock| lock.lock_id).collect(),
+                account_id: &receiver_id,
+                amount: &U128(claimed),
+                reclaimed: false,
+            }
+            .emit();
+        }
+        claimed.into()
+    }
+
+    /// Returns a bucket to its sender when the receiver never registered while it was pending,
+    /// from `LOCK_RECLAIM_DELAY` after the unlock time. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn reclaim_locked(&mut self, lock_id: U64) -> U128 {
+        assert_one_yocto();
+        let lock = self
+            .locked_transfers
+            .get(&lock_id.0)
+            .unwrap_or_else(|| env::panic_str("Locked transfer not found"));
+        require!(
+            env::predecessor_account_id() == lock.sender_id,
+            "Only the sender can reclaim the locked transfer"
+        );
+        require!(
+            env::block_timestamp() >= lock.unlock_at.0.saturating_add(LOCK_RECLAIM_DELAY),
+            "The locked transfer can't be reclaimed yet"
+        );
+        require!(
+            !self.token.accounts.contains_key(&lock.receiver_id)
+                && !self.closed_lock_receivers.contains(&lock.receiver_id),
+            "The receiver is registered and can claim the locked transfer"
+        );
+        self.internal_remove_lock(&lock);
+        self.internal_transfer(
+            &env::current_account_id(),
+            &lock.sender_id,
+            lock.amount.0,
+            Some("reclaimed".to_string()),
+        );
+        LockRelease {
+            lock_ids: vec![lock_id],
+            account_id: &lock.sender_id,
+            amount: &lock.amount,
+            reclaimed: true,
+        }
+        .emit();
+        lock.amount
+    }
+
+    /// Total amount locked for `account_id`, matured or not, that hasn't been claimed yet.
+    pub fn ft_locked_balance_of(&self, account_id: AccountId) -> U128 {
+        self.locked_balances.get(&account_id).unwrap_or(0).into()
+    }
+
+    pub fn locked_transfers_by_receiver(
+        &self,
+        receiver_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<LockedTransfer> {
+        self.locked_transfers_per_receiver
+            .page(&receiver_id, from_index, limit)
+            .into_iter()
+            .filter_map(|lock_id| self.locked_transfers.get(&lock_id))
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, near_sent_to, STORAGE_DEPOSIT, TOTAL_SUPPLY};
+
+    const UNLOCK_AT: u64 = 1_000;
+
+    /// accounts(1) sends 100 tokens to accounts(2) unlocking at `UNLOCK_AT`, and 50 more
+    /// unlocking at `2 * UNLOCK_AT`.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        contract.ft_transfer_locked(accounts(2), U128(100), U64(UNLOCK_AT), None);
+        contract.ft_transfer_locked(accounts(2), U128(50), U64(2 * UNLOCK_AT), None);
+        testing_env!(context.attached_deposit(1).build());
+        (context, contract)
+    }
+
+    #[test]
+    fn test_locked_balance() {
+        let (_context, contract) = setup();
+        assert_eq!(contract.ft_locked_balance_of(accounts(2)).0, 150);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 0);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY - 150);
+        assert_eq!(contract.locked_transfers_by_receiver(accounts(2), None, None).len(), 2);
+    }
+
+    #[test]
+    fn test_claim_matured_buckets_only() {
+        let (mut context, mut contract) = setup();
+        contract.token.internal_register_account(&accounts(2));
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        assert_eq!(contract.claim_unlocked(vec![]).0, 0);
+
+        testing_env!(context.block_timestamp(UNLOCK_AT).build());
+        assert_eq!(contract.claim_unlocked(vec![U64(0)]).0, 100);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 100);
+        assert_eq!(contract.ft_locked_balance_of(accounts(2)).0, 50);
+
+        testing_env!(context.block_timestamp(2 * UNLOCK_AT).build());
+        assert_eq!(contract.claim_unlocked(vec![U64(1)]).0, 50);
+        assert_eq!(contract.ft_locked_balance_of(accounts(2)).0, 0);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The locked transfer is not unlocked yet")]
+    fn test_cannot_claim_before_unlock() {
+        let (mut context, mut contract) = setup();
+        contract.token.internal_register_account(&accounts(2));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(UNLOCK_AT)
+            .build());
+        contract.claim_unlocked(vec![U64(0), U64(1)]);
+    }
+
+    #[test]
+    fn test_sender_reclaims_when_receiver_never_registered() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.block_timestamp(UNLOCK_AT + LOCK_RECLAIM_DELAY).build());
+        let storage_deposit =
+            contract.locked_transfers_by_receiver(accounts(2), None, None)[0].storage_deposit;
+        assert_eq!(contract.reclaim_locked(U64(0)).0, 100);
+        assert!(storage_deposit.0 > 0);
+        assert_eq!(near_sent_to(&accounts(1)), storage_deposit.0);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY - 50);
+        assert_eq!(contract.ft_locked_balance_of(accounts(2)).0, 50);
+    }
+
+    #[test]
+    #[should_panic(expected = "The receiver is registered and can claim the locked transfer")]
+    fn test_cannot_reclaim_from_registered_receiver() {
+        let (mut context, mut contract) = setup();
+        contract.token.internal_register_account(&accounts(2));
+        testing_env!(context.block_timestamp(UNLOCK_AT + LOCK_RECLAIM_DELAY).build());
+        contract.reclaim_locked(U64(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "The receiver is registered and can claim the locked transfer")]
+    fn test_cannot_reclaim_after_receiver_closed_account() {
+        let (mut context, mut contract) = setup();
+        contract.token.internal_register_account(&accounts(2));
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        assert!(contract.storage_unregister(None));
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(UNLOCK_AT + LOCK_RECLAIM_DELAY)
+            .build());
+        contract.reclaim_locked(U64(0));
+    }
+}
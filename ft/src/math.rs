@@ -0,0 +1,29 @@
+//! 256 and 512-bit integers for intermediate results that don't fit in a `u128`, e.g. balances
+//! multiplied by a magnified per-token amount. Only `U256` is ever stored.
+
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use std::io;
+
+// The macro expansion trips a few style lints that don't apply to our code.
+#[allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+mod uints {
+    uint::construct_uint! {
+        pub struct U256(4);
+    }
+    uint::construct_uint! {
+        pub struct U512(8);
+    }
+}
+pub use uints::{U256, U512};
+
+impl BorshSerialize for U256 {
+    fn serialize<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.serialize(writer)
+    }
+}
+
+impl BorshDeserialize for U256 {
+    fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        Ok(Self(<[u64; 4]>::deserialize(buf)?))
+    }
+}
@@ -0,0 +1,72 @@
+/*!
+Default metadata of the token, built from the files of `assets/` by `build.rs`.
+
+The icon is `assets/icon.svg` minified into a data URI. The reference is `assets/reference.json`,
+addressed by content: `reference` is its `ipfs://` URI and `reference_hash` its sha256, so the file
+has to be pinned with `ipfs add --cid-version 1 --raw-leaves assets/reference.json` when it
+changes.
+*/
+use near_contract_standards::fungible_token::metadata::{FungibleTokenMetadata, FT_METADATA_SPEC};
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::{env, near_bindgen};
+
+use crate::*;
+
+const ICON: &str = include_str!(concat!(env!("OUT_DIR"), "/icon.uri"));
+const REFERENCE: &str = include_str!(concat!(env!("OUT_DIR"), "/reference.uri"));
+const REFERENCE_HASH: &[u8; 32] = include_bytes!(concat!(env!("OUT_DIR"), "/reference.sha256"));
+
+pub(crate) fn default_metadata() -> FungibleTokenMetadata {
+    FungibleTokenMetadata {
+        spec: FT_METADATA_SPEC.to_string(),
+        name: "NEAR Hub Estates".to_string(),
+        symbol: "ESTATES".to_string(),
+        icon: Some(ICON.to_string()),
+        reference: Some(REFERENCE.to_string()),
+        reference_hash: Some(REFERENCE_HASH.to_vec().into()),
+        decimals: 24,
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sha256 of the icon of the metadata, to check a copy of the icon served elsewhere against.
+    pub fn icon_hash(&self) -> Option<Base64VecU8> {
+        self.metadata.get().unwrap().icon.map(|icon| env::sha256(icon.as_bytes()).into())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::metadata::FungibleTokenMetadataProvider;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    fn setup() -> Contract {
+        testing_env!(get_context(accounts(1)).build());
+        Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into())
+    }
+
+    #[test]
+    fn test_reference_hash() {
+        let metadata = setup().ft_metadata();
+        let reference = include_bytes!("../assets/reference.json");
+        assert_eq!(metadata.reference_hash.unwrap().0, env::sha256(reference));
+        assert!(metadata.reference.unwrap().starts_with("ipfs://bafkrei"));
+    }
+
+    #[test]
+    fn test_icon_hash() {
+        let contract = setup();
+        let icon = contract.ft_metadata().icon.unwrap();
+        assert_eq!(contract.icon_hash().unwrap().0, env::sha256(icon.as_bytes()));
+
+        let svg = include_str!("../assets/icon.svg");
+        assert!(icon.starts_with("data:image/svg+xml,%3Csvg "));
+        assert!(!icon.contains(['<', '>', '#', '"', '\n']));
+        assert!(icon.len() < svg.len());
+    }
+}
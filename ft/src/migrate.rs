@@ -0,0 +1,90 @@
+/*!
+Upgrade of a contract deployed with the original state, which only held the token and its
+metadata.
+
+The token and the metadata keep their storage prefixes, so balances and registrations carry over
+as they are. Every other part of the state starts empty, like after `new` without any supply.
+*/
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_contract_standards::fungible_token::FungibleToken;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LazyOption;
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::*;
+
+/// State of the contract before the upgrade.
+#[derive(BorshDeserialize, BorshSerialize)]
+struct OldContract {
+    token: FungibleToken,
+    metadata: LazyOption<FungibleTokenMetadata>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Converts the state of the original contract to the current layout, with `owner_id` as the
+    /// owner, which the original contract didn't have. `owner_id` and the contract account are
+    /// registered if they aren't yet. Only the contract account can call this method, right after
+    /// deploying the new code.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate(owner_id: AccountId) -> Self {
+        let old: OldContract = env::state_read().expect("The contract is not initialized");
+        let mut this = Self::internal_with_token(old.token, old.metadata, owner_id, None);
+        for account_id in [this.owner_id.clone(), env::current_account_id()].iter() {
+            if !this.token.accounts.contains_key(account_id) {
+                this.token.internal_register_account(account_id);
+            }
+        }
+        this
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::fungible_token::metadata::FungibleTokenMetadataProvider;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::metadata::default_metadata;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    /// Writes the state the original contract had after `new_default_meta(accounts(2))`.
+    fn write_old_state() {
+        let mut token = FungibleToken::new(b"a".to_vec());
+        token.internal_register_account(&accounts(2));
+        token.internal_deposit(&accounts(2), TOTAL_SUPPLY);
+        let metadata = LazyOption::new(b"m".to_vec(), Some(&default_metadata()));
+        env::state_write(&OldContract { token, metadata });
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        write_old_state();
+        let mut contract = Contract::migrate(accounts(1));
+        assert_eq!(contract.get_owner(), accounts(1));
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_metadata().symbol, default_metadata().symbol);
+        assert!(contract.token.accounts.contains_key(&accounts(1)));
+        assert!(contract.token.accounts.contains_key(&accounts(0)));
+
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+        contract.ft_transfer(accounts(1), U128(100), None);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot deserialize the contract state")]
+    fn test_cannot_migrate_twice() {
+        testing_env!(get_context(accounts(0)).build());
+        write_old_state();
+        let contract = Contract::migrate(accounts(1));
+        env::state_write(&contract);
+        Contract::migrate(accounts(1));
+    }
+}
@@ -0,0 +1,706 @@
+/*!
+Fractional estate shares as NEP-245 multi-tokens, traded alongside ESTATES.
+
+Every fractionalized parcel gets its own share token, whose id is the parcel id. The owner
+creates the share token of a parcel, optionally with a price in ESTATES per share. Shares of a
+priced token are only minted to those who lock ESTATES on the contract account with
+`ft_transfer_call` and a `mint_shares` message, so every share is backed by its price. Whatever
+doesn't pay for a whole share is refunded by `ft_resolve_transfer`. Shares of the other tokens
+are minted by the owner with `mt_mint`. Holders get the locked ESTATES back with `redeem_shares`, which
+burns their shares and releases their pro rata part of the ESTATES locked for the token.
+
+Share balances live next to the ESTATES balances, and an account needs to be registered with the
+token (NEP-145 `storage_deposit`) to receive shares. Approvals are not supported.
+
+<https://github.com/near/NEPs/blob/master/neps/nep-0245.md>
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, near_bindgen, require, AccountId, Balance, Gas,
+    PromiseOrValue, PromiseResult,
+};
+
+use crate::events::{EstateSharesCreate, MtBurn, MtMint, MtTransfer};
+use crate::math::U256;
+use crate::*;
+
+pub type TokenId = String;
+
+pub const MT_METADATA_SPEC: &str = "mt-1.0.0";
+
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_MT_TRANSFER_CALL: Gas = Gas(25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER.0);
+
+#[ext_contract(ext_mt_receiver)]
+pub trait MultiTokenReceiver {
+    /// Called on the receiver by `mt_transfer_call` and `mt_batch_transfer_call`. Returns the
+    /// amounts of each token that should be refunded to the previous owners.
+    fn mt_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_ids: Vec<AccountId>,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>>;
+}
+
+#[ext_contract(ext_mt_resolver)]
+pub trait MultiTokenResolver {
+    fn mt_resolve_transfer(
+        &mut self,
+        previous_owner_ids: Vec<AccountId>,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+    ) -> Vec<U128>;
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MtContractMetadata {
+    pub spec: String,
+    pub name: String,
+}
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, Default, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MtTokenMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub media: Option<String>,
+    pub media_hash: Option<Base64VecU8>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<Base64VecU8>,
+}
+
+/// Share token of a fractionalized parcel.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EstateShares {
+    pub token_id: TokenId,
+    pub parcel_id: U64,
+    /// ESTATES to lock per share, `None` when only the owner can mint shares.
+    pub estates_per_share: Option<U128>,
+    pub total_supply: U128,
+    /// ESTATES locked on the contract account by share mints.
+    pub locked_estates: U128,
+    pub metadata: MtTokenMetadata,
+}
+
+fn assert_no_approvals<T>(approvals: Option<T>) {
+    require!(approvals.is_none(), "Approvals are not supported");
+}
+
+impl Contract {
+    fn internal_unwrap_shares(&self, token_id: &str) -> EstateShares {
+        self.estate_shares
+            .get(&token_id.to_string())
+            .unwrap_or_else(|| env::panic_str("Share token not found"))
+    }
+
+    fn internal_share_balance(&self, token_id: &str, account_id: &AccountId) -> Balance {
+        self.share_balances.get(&(token_id.to_string(), account_id.clone())).unwrap_or(0)
+    }
+
+    fn internal_set_share_balance(
+        &mut self,
+        token_id: &str,
+        account_id: &AccountId,
+        balance: Balance,
+    ) {
+        let key = (token_id.to_string(), account_id.clone());
+        if balance == 0 {
+            self.share_balances.remove(&key);
+        } else {
+            self.share_balances.insert(&key, &balance);
+        }
+    }
+
+    fn internal_share_deposit(&mut self, token_id: &str, account_id: &AccountId, amount: Balance) {
+        let balance = self.internal_share_balance(token_id, account_id);
+        let balance =
+            balance.checked_add(amount).unwrap_or_else(|| env::panic_str("Balance overflow"));
+        self.internal_set_share_balance(token_id, account_id, balance);
+    }
+
+    fn internal_share_withdraw(&mut self, token_id: &str, account_id: &AccountId, amount: Balance) {
+        let balance = self
+            .internal_share_balance(token_id, account_id)
+            .checked_sub(amount)
+            .unwrap_or_else(|| env::panic_str("The account doesn't have enough balance"));
+        self.internal_set_share_balance(token_id, account_id, balance);
+    }
+
+    fn internal_assert_registered(&self, account_id: &AccountId) {
+        if !self.token.accounts.contains_key(account_id) {
+            env::panic_str(format!("The account {} is not registered", account_id).as_str());
+        }
+    }
+
+    /// Moves `amounts` of `token_ids` from `sender_id` to `receiver_id`.
+    fn internal_mt_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        token_ids: &[TokenId],
+        amounts: &[U128],
+        memo: Option<String>,
+    ) {
+        require!(sender_id != receiver_id, "Sender and receiver should be different");
+        require!(!token_ids.is_empty(), "At least one token should be transferred");
+        require!(token_ids.len() == amounts.len(), "Token ids and amounts should be as many");
+        self.internal_assert_registered(receiver_id);
+        for (token_id, amount) in token_ids.iter().zip(amounts) {
+            require!(amount.0 > 0, "The amount should be a positive number");
+            self.internal_unwrap_shares(token_id);
+            self.internal_share_withdraw(token_id, sender_id, amount.0);
+            self.internal_share_deposit(token_id, receiver_id, amount.0);
+        }
+        let token_ids: Vec<&str> = token_ids.iter().map(String::as_str).collect();
+        MtTransfer {
+            old_owner_id: sender_id,
+            new_owner_id: receiver_id,
+            token_ids: &token_ids,
+            amounts,
+            memo: memo.as_deref(),
+        }
+        .emit();
+    }
+
+    fn internal_mt_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>> {
+        assert_one_yocto();
+        require!(env::prepaid_gas() > GAS_FOR_MT_TRANSFER_CALL, "More gas is required");
+        let sender_id = env::predecessor_account_id();
+        self.internal_mt_transfer(&sender_id, &receiver_id, &token_ids, &amounts, memo);
+        let receiver_gas = env::prepaid_gas()
+            .0
+            .checked_sub(GAS_FOR_MT_TRANSFER_CALL.0)
+            .unwrap_or_else(|| env::panic_str("Prepaid gas overflow"));
+        let previous_owner_ids = vec![sender_id.clone(); token_ids.len()];
+        ext_mt_receiver::ext(receiver_id.clone())
+            .with_static_gas(receiver_gas.into())
+            .mt_on_transfer(
+                sender_id,
+                previous_owner_ids.clone(),
+                token_ids.clone(),
+                amounts.clone(),
+                msg,
+            )
+            .then(
+                ext_mt_resolver::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                    .mt_resolve_transfer(previous_owner_ids, receiver_id, token_ids, amounts),
+            )
+            .into()
+    }
+
+    fn internal_mt_mint(
+        &mut self,
+        token_id: &str,
+        owner_id: &AccountId,
+        amount: Balance,
+        locked_estates: Balance,
+        memo: &str,
+    ) {
+        require!(amount > 0, "The amount should be a positive number");
+        self.internal_assert_registered(owner_id);
+        let mut shares = self.internal_unwrap_shares(token_id);
+        shares.total_supply = shares
+            .total_supply
+            .0
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic_str("Total supply overflow"))
+            .into();
+        shares.locked_estates = (shares.locked_estates.0 + locked_estates).into();
+        self.estate_shares.insert(&shares.token_id, &shares);
+        self.internal_share_deposit(token_id, owner_id, amount);
+        MtMint { owner_id, token_ids: &[token_id], amounts: &[U128(amount)], memo: Some(memo) }
+            .emit();
+    }
+
+    /// Mints as many shares of `token_id` as `amount` ESTATES, already sent to the contract
+    /// account by `sender_id`, pay for. Returns the unused amount.
+    pub(crate) fn internal_mint_shares(
+        &mut self,
+        sender_id: AccountId,
+        amount: Balance,
+        token_id: TokenId,
+    ) -> Balance {
+        let price = self
+            .internal_unwrap_shares(&token_id)
+            .estates_per_share
+            .unwrap_or_else(|| env::panic_str("Shares of this estate can't be bought"));
+        let shares = amount / price.0;
+        require!(shares > 0, "The attached amount is less than the share price");
+        let locked = shares * price.0;
+        self.internal_mt_mint(&token_id, &sender_id, shares, locked, "ESTATES locked");
+        amount - locked
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Creates the share token of a parcel, priced at `estates_per_share` ESTATES if shares can
+    /// be bought. Returns the token id. Only the owner can call this method.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn create_estate_shares(
+        &mut self,
+        parcel_id: U64,
+        estates_per_share: Option<U128>,
+        metadata: MtTokenMetadata,
+    ) -> TokenId {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(self.parcels.get(&parcel_id.0).is_some(), "Parcel not found");
+        if let Some(price) = estates_per_share {
+            require!(price.0 > 0, "The price should be a positive number");
+        }
+        let token_id = parcel_id.0.to_string();
+        require!(
+            self.estate_shares.get(&token_id).is_none(),
+            "The parcel is already fractionalized"
+        );
+        let shares = EstateShares {
+            token_id: token_id.clone(),
+            parcel_id,
+            estates_per_share,
+            total_supply: U128(0),
+            locked_estates: U128(0),
+            metadata,
+        };
+        self.estate_shares.insert(&token_id, &shares);
+        EstateSharesCreate { shares: &shares }.emit();
+        token_id
+    }
+
+    /// Mints `amount` shares of `token_id` to `owner_id`, if the shares of the token can't be
+    /// bought. Only the owner can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn mt_mint(
+        &mut self,
+        token_id: TokenId,
+        owner_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        self.assert_owner();
+        // Unbacked shares would take part of the ESTATES locked by the buyers on redemption.
+        require!(
+            self.internal_unwrap_shares(&token_id).estates_per_share.is_none(),
+            "Shares of this estate are only minted by locking ESTATES"
+        );
+        let memo = memo.unwrap_or_else(|| "Minted by the owner".to_string());
+        self.internal_mt_mint(&token_id, &owner_id, amount.0, 0, &memo);
+    }
+
+    /// Burns `amount` shares of `token_id` of the predecessor and releases their part of the
+    /// ESTATES locked for the token to them. Returns the released amount.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn redeem_shares(&mut self, token_id: TokenId, amount: U128) -> U128 {
+        assert_one_yocto();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        let owner_id = env::predecessor_account_id();
+        let mut shares = self.internal_unwrap_shares(&token_id);
+        self.internal_share_withdraw(&token_id, &owner_id, amount.0);
+        // Rounded down, so the last holders are never short of locked ESTATES.
+        let released = (U256::from(shares.locked_estates.0) * U256::from(amount.0)
+            / U256::from(shares.total_supply.0))
+        .as_u128();
+        shares.total_supply = (shares.total_supply.0 - amount.0).into();
+        shares.locked_estates = (shares.locked_estates.0 - released).into();
+        self.estate_shares.insert(&token_id, &shares);
+        MtBurn {
+            owner_id: &owner_id,
+            token_ids: &[token_id.as_str()],
+            amounts: &[amount],
+            memo: Some("ESTATES released"),
+        }
+        .emit();
+        if released > 0 {
+            self.internal_transfer(
+                &env::current_account_id(),
+                &owner_id,
+                released,
+                Some(format!("shares {} redeemed", token_id)),
+            );
+        }
+        released.into()
+    }
+
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn mt_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        approval: Option<(AccountId, u64)>,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        assert_no_approvals(approval);
+        let sender_id = env::predecessor_account_id();
+        self.internal_mt_transfer(&sender_id, &receiver_id, &[token_id], &[amount], memo);
+    }
+
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn mt_batch_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        approvals: Option<Vec<Option<(AccountId, u64)>>>,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        assert_no_approvals(approvals);
+        let sender_id = env::predecessor_account_id();
+        self.internal_mt_transfer(&sender_id, &receiver_id, &token_ids, &amounts, memo);
+    }
+
+    /// Transfers the shares and calls `mt_on_transfer` on the receiver. The amounts the receiver
+    /// returns are refunded by `mt_resolve_transfer`, whose result is the amounts it used.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn mt_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        approval: Option<(AccountId, u64)>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>> {
+        assert_no_approvals(approval);
+        self.internal_mt_transfer_call(receiver_id, vec![token_id], vec![amount], memo, msg)
+    }
+
+    /// Batch version of `mt_transfer_call`. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn mt_batch_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        approvals: Option<Vec<Option<(AccountId, u64)>>>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>> {
+        assert_no_approvals(approvals);
+        self.internal_mt_transfer_call(receiver_id, token_ids, amounts, memo, msg)
+    }
+
+    /// Refunds what the receiver didn't use, as far as it still holds it. A failed or malformed
+    /// `mt_on_transfer` refunds everything.
+    #[private]
+    pub fn mt_resolve_transfer(
+        &mut self,
+        previous_owner_ids: Vec<AccountId>,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+    ) -> Vec<U128> {
+        let unused_amounts: Vec<Balance> = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(value) => {
+                match near_sdk::serde_json::from_slice::<Vec<U128>>(&value) {
+                    Ok(unused) if unused.len() == amounts.len() => unused
+                        .iter()
+                        .zip(&amounts)
+                        .map(|(unused, amount)| unused.0.min(amount.0))
+                        .collect(),
+                    _ => amounts.iter().map(|amount| amount.0).collect(),
+                }
+            }
+            PromiseResult::Failed => amounts.iter().map(|amount| amount.0).collect(),
+        };
+        let mut used_amounts = Vec::with_capacity(amounts.len());
+        for (i, unused_amount) in unused_amounts.into_iter().enumerate() {
+            let token_id = &token_ids[i];
+            let refund = unused_amount.min(self.internal_share_balance(token_id, &receiver_id));
+            if refund > 0 {
+                self.internal_share_withdraw(token_id, &receiver_id, refund);
+                self.internal_share_deposit(token_id, &previous_owner_ids[i], refund);
+                MtTransfer {
+                    old_owner_id: &receiver_id,
+                    new_owner_id: &previous_owner_ids[i],
+                    token_ids: &[token_id.as_str()],
+                    amounts: &[U128(refund)],
+                    memo: Some("refund"),
+                }
+                .emit();
+            }
+            used_amounts.push(U128(amounts[i].0 - refund));
+        }
+        used_amounts
+    }
+
+    pub fn mt_balance_of(&self, account_id: AccountId, token_id: TokenId) -> U128 {
+        self.internal_share_balance(&token_id, &account_id).into()
+    }
+
+    pub fn mt_batch_balance_of(&self, account_id: AccountId, token_ids: Vec<TokenId>) -> Vec<U128> {
+        token_ids
+            .iter()
+            .map(|token_id| self.internal_share_balance(token_id, &account_id).into())
+            .collect()
+    }
+
+    pub fn mt_supply(&self, token_id: TokenId) -> Option<U128> {
+        self.estate_shares.get(&token_id).map(|shares| shares.total_supply)
+    }
+
+    pub fn mt_metadata_contract(&self) -> MtContractMetadata {
+        MtContractMetadata {
+            spec: MT_METADATA_SPEC.to_string(),
+            name: format!("{} shares", self.metadata.get().unwrap().name),
+        }
+    }
+
+    pub fn mt_metadata(&self, token_ids: Vec<TokenId>) -> Vec<Option<MtTokenMetadata>> {
+        token_ids
+            .iter()
+            .map(|token_id| self.estate_shares.get(token_id).map(|shares| shares.metadata))
+            .collect()
+    }
+
+    pub fn estate_shares(&self, token_id: TokenId) -> Option<EstateShares> {
+        self.estate_shares.get(&token_id)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::serde_json::json;
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+    use super::*;
+    use crate::tests::{get_context, STORAGE_DEPOSIT, TOTAL_SUPPLY};
+
+    const PRICE: Balance = 10;
+
+    /// Parcel 0 is fractionalized at `PRICE` ESTATES per share, parcels 1 and 2 only by the owner.
+    /// accounts(1) is the owner, accounts(2) and accounts(3) are registered.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        contract.token.internal_register_account(&accounts(3));
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        for parcel_id in 0..3 {
+            contract.register_parcel(accounts(2), None, Some(format!("ipfs://{}", parcel_id)));
+        }
+        testing_env!(context.attached_deposit(1).build());
+        let metadata =
+            MtTokenMetadata { title: Some("Parcel 0".to_string()), ..Default::default() };
+        contract.create_estate_shares(U64(0), Some(U128(PRICE)), metadata);
+        contract.create_estate_shares(U64(1), None, MtTokenMetadata::default());
+        contract.create_estate_shares(U64(2), None, MtTokenMetadata::default());
+        (context, contract)
+    }
+
+    /// Resolves an `mt_transfer_call` of accounts(1) to accounts(3) with the given outcome of
+    /// `mt_on_transfer`.
+    fn resolve(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        result: PromiseResult,
+    ) -> Vec<U128> {
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).attached_deposit(0).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+        let previous_owner_ids = vec![accounts(1); token_ids.len()];
+        contract.mt_resolve_transfer(previous_owner_ids, accounts(3), token_ids, amounts)
+    }
+
+    #[test]
+    fn test_mint_shares_by_locking_estates() {
+        let (mut context, mut contract) = setup();
+        let msg = json!({ "mint_shares": { "token_id": "0" } }).to_string();
+        let _ = contract.ft_transfer_call(accounts(0), U128(PRICE * 3 + 5), None, msg.clone());
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        match contract.ft_on_transfer(accounts(1), U128(PRICE * 3 + 5), msg) {
+            PromiseOrValue::Value(unused) => assert_eq!(unused.0, 5),
+            PromiseOrValue::Promise(_) => unreachable!(),
+        }
+
+        assert_eq!(contract.mt_balance_of(accounts(1), "0".to_string()).0, 3);
+        assert_eq!(contract.mt_supply("0".to_string()), Some(U128(3)));
+        let shares = contract.estate_shares("0".to_string()).unwrap();
+        assert_eq!(shares.locked_estates.0, PRICE * 3);
+        assert_eq!(
+            contract.mt_metadata(vec!["0".to_string()])[0].as_ref().unwrap().title.as_deref(),
+            Some("Parcel 0")
+        );
+    }
+
+    #[test]
+    fn test_create_estate_shares_event() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        contract.register_parcel(accounts(2), None, Some("ipfs://3".to_string()));
+        testing_env!(context.attached_deposit(1).build());
+        contract.create_estate_shares(U64(3), None, MtTokenMetadata::default());
+        let logs = get_logs();
+        assert!(logs.last().unwrap().contains(r#""event":"estate_shares_create""#));
+        assert!(logs.last().unwrap().contains(r#""token_id":"3""#));
+    }
+
+    #[test]
+    fn test_redeem_shares_releases_locked_estates() {
+        let (mut context, mut contract) = setup();
+        let msg = json!({ "mint_shares": { "token_id": "0" } }).to_string();
+        let _ = contract.ft_transfer_call(accounts(0), U128(PRICE * 4), None, msg.clone());
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        let _ = contract.ft_on_transfer(accounts(1), U128(PRICE * 4), msg);
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        contract.mt_transfer(accounts(3), "0".to_string(), U128(1), None, None);
+
+        assert_eq!(contract.redeem_shares("0".to_string(), U128(2)).0, PRICE * 2);
+        assert!(get_logs().iter().any(|log| log.contains(r#""event":"mt_burn""#)));
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY - PRICE * 2);
+        assert_eq!(contract.mt_balance_of(accounts(1), "0".to_string()).0, 1);
+        let shares = contract.estate_shares("0".to_string()).unwrap();
+        assert_eq!((shares.total_supply.0, shares.locked_estates.0), (2, PRICE * 2));
+
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        assert_eq!(contract.redeem_shares("0".to_string(), U128(1)).0, PRICE);
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, PRICE);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, PRICE);
+        let shares = contract.estate_shares("0".to_string()).unwrap();
+        assert_eq!((shares.total_supply.0, shares.locked_estates.0), (1, PRICE));
+    }
+
+    /// Shares minted for free would take part of the ESTATES the buyers locked on redemption.
+    #[test]
+    #[should_panic(expected = "Shares of this estate are only minted by locking ESTATES")]
+    fn test_owner_cannot_mint_bought_shares() {
+        let (mut context, mut contract) = setup();
+        let msg = json!({ "mint_shares": { "token_id": "0" } }).to_string();
+        let _ = contract.ft_transfer_call(accounts(0), U128(PRICE * 4), None, msg.clone());
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        let _ = contract.ft_on_transfer(accounts(1), U128(PRICE * 4), msg);
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        contract.mt_mint("0".to_string(), accounts(3), U128(4), None);
+    }
+
+    #[test]
+    fn test_batch_transfer() {
+        let (_context, mut contract) = setup();
+        contract.mt_mint("1".to_string(), accounts(1), U128(100), None);
+        contract.mt_mint("2".to_string(), accounts(1), U128(50), None);
+        let token_ids = vec!["1".to_string(), "2".to_string()];
+        contract.mt_batch_transfer(
+            accounts(2),
+            token_ids.clone(),
+            vec![U128(40), U128(50)],
+            None,
+            None,
+        );
+
+        assert_eq!(
+            contract.mt_batch_balance_of(accounts(1), token_ids.clone()),
+            vec![U128(60), U128(0)]
+        );
+        assert_eq!(contract.mt_batch_balance_of(accounts(2), token_ids), vec![U128(40), U128(50)]);
+    }
+
+    #[test]
+    fn test_transfer_call_refunds_unused_amounts() {
+        let (mut context, mut contract) = setup();
+        contract.mt_mint("1".to_string(), accounts(1), U128(100), None);
+        contract.mt_mint("2".to_string(), accounts(1), U128(100), None);
+        let token_ids = vec!["1".to_string(), "2".to_string()];
+        let amounts = vec![U128(60), U128(20)];
+        let _ = contract.mt_batch_transfer_call(
+            accounts(3),
+            token_ids.clone(),
+            amounts.clone(),
+            None,
+            None,
+            "".to_string(),
+        );
+
+        // The receiver asks to refund 10 of token 0 and more than it got of token 1.
+        let unused = near_sdk::serde_json::to_vec(&vec![U128(10), U128(30)]).unwrap();
+        let used = resolve(
+            &mut context,
+            &mut contract,
+            token_ids.clone(),
+            amounts,
+            PromiseResult::Successful(unused),
+        );
+        assert_eq!(used, vec![U128(50), U128(0)]);
+        assert_eq!(
+            contract.mt_batch_balance_of(accounts(1), token_ids.clone()),
+            vec![U128(50), U128(100)]
+        );
+        assert_eq!(contract.mt_batch_balance_of(accounts(3), token_ids), vec![U128(50), U128(0)]);
+    }
+
+    #[test]
+    fn test_failed_transfer_call_refunds_everything() {
+        let (mut context, mut contract) = setup();
+        contract.mt_mint("1".to_string(), accounts(1), U128(100), None);
+        let _ = contract.mt_transfer_call(
+            accounts(3),
+            "1".to_string(),
+            U128(70),
+            None,
+            None,
+            "".to_string(),
+        );
+
+        let used = resolve(
+            &mut context,
+            &mut contract,
+            vec!["1".to_string()],
+            vec![U128(70)],
+            PromiseResult::Failed,
+        );
+        assert_eq!(used, vec![U128(0)]);
+        assert_eq!(contract.mt_balance_of(accounts(1), "1".to_string()).0, 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account eugene is not registered")]
+    fn test_transfer_to_unregistered_account() {
+        let (_context, mut contract) = setup();
+        contract.mt_mint("1".to_string(), accounts(1), U128(100), None);
+        contract.mt_transfer(accounts(4), "1".to_string(), U128(1), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Shares of this estate can't be bought")]
+    fn test_owner_only_shares_cant_be_bought() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        let msg = json!({ "mint_shares": { "token_id": "1" } }).to_string();
+        let _ = contract.ft_on_transfer(accounts(1), U128(PRICE), msg);
+    }
+}
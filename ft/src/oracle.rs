@@ -0,0 +1,258 @@
+/*!
+USD prices from a price-feed oracle contract.
+
+Parcels can be listed and subscriptions priced in USD, and are paid in ESTATES converted at the
+latest price of the oracle the owner configured. Anyone can pull that price with `update_price`,
+which stores it once the oracle answers. Conversions panic when the stored price is older than
+the `max_age` of the config, or when its confidence interval is wider than `max_confidence_bps`
+of the price, so a deal never settles on a price nobody can vouch for.
+
+USD amounts have [`USD_DECIMALS`] decimals, e.g. `"1500000"` is 1.5 USD.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, near_bindgen, require, AccountId, Balance, Gas, Promise,
+    PromiseResult,
+};
+
+use crate::events::OraclePriceUpdate;
+use crate::math::U256;
+use crate::*;
+
+pub const USD_DECIMALS: u8 = 6;
+
+const GAS_FOR_GET_PRICE: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_ON_PRICE_UPDATE: Gas = Gas(10_000_000_000_000);
+const MAX_BPS: u16 = 10_000;
+
+/// Price of one whole token of an asset in USD, as reported by the oracle.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceData {
+    /// USD per whole token, times `10^decimals`.
+    pub price: U128,
+    /// Half width of the confidence interval around the price, in the same unit.
+    pub confidence: U128,
+    pub decimals: u8,
+    /// Timestamp in nanoseconds of the observation.
+    pub timestamp: U64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OracleConfig {
+    pub oracle_id: AccountId,
+    /// Asset the oracle prices ESTATES under.
+    pub asset_id: String,
+    /// Nanoseconds after its observation during which a price can be used.
+    pub max_age: U64,
+    /// Widest confidence interval accepted, in basis points of the price.
+    pub max_confidence_bps: u16,
+}
+
+// Defining cross-contract interface. This allows to create a new promise.
+#[ext_contract(ext_price_oracle)]
+pub trait PriceOracle {
+    fn get_price(&self, asset_id: String) -> Option<PriceData>;
+}
+
+#[ext_contract(ext_price_resolver)]
+pub trait PriceResolver {
+    fn on_price_update(&mut self) -> Option<PriceData>;
+}
+
+impl Contract {
+    fn internal_unwrap_oracle_config(&self) -> &OracleConfig {
+        self.oracle_config.as_ref().unwrap_or_else(|| env::panic_str("No oracle is configured"))
+    }
+
+    /// The latest price, if it's fresh and confident enough to be used.
+    fn internal_usable_price(&self) -> &PriceData {
+        let config = self.internal_unwrap_oracle_config();
+        let price = self
+            .latest_price
+            .as_ref()
+            .unwrap_or_else(|| env::panic_str("No oracle price is available"));
+        require!(
+            env::block_timestamp().saturating_sub(price.timestamp.0) <= config.max_age.0,
+            "The oracle price is stale"
+        );
+        require!(
+            U256::from(price.confidence.0) * U256::from(MAX_BPS)
+                <= U256::from(price.price.0) * U256::from(config.max_confidence_bps),
+            "The oracle price confidence is too low"
+        );
+        price
+    }
+
+    /// ESTATES in the smallest unit worth `amount_usd`, rounded up so that USD prices are never
+    /// undercharged.
+    pub(crate) fn internal_usd_to_estates(&self, amount_usd: Balance) -> Balance {
+        let price = self.internal_usable_price();
+        let numerator = U256::from(amount_usd)
+            * U256::from(10).pow(U256::from(price.decimals))
+            * self.internal_token_unit();
+        let denominator = U256::from(price.price.0) * U256::from(10).pow(U256::from(USD_DECIMALS));
+        let amount = (numerator + denominator - 1) / denominator;
+        require!(amount <= U256::from(Balance::MAX), "The amount overflows");
+        amount.as_u128()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the oracle the USD prices are converted with, `None` removes it. Drops the stored
+    /// price. Only the owner can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_oracle_config(&mut self, config: Option<OracleConfig>) {
+        assert_one_yocto();
+        self.assert_owner();
+        if let Some(config) = config.as_ref() {
+            require!(config.max_age.0 > 0, "The max age should be a positive number");
+            require!(
+                config.max_confidence_bps <= MAX_BPS,
+                "The max confidence exceeds 10000 basis points"
+            );
+        }
+        self.oracle_config = config;
+        self.latest_price = None;
+    }
+
+    /// Asks the oracle for the current price, which is stored once it answers. Can be called by
+    /// anyone.
+    pub fn update_price(&mut self) -> Promise {
+        let config = self.internal_unwrap_oracle_config();
+        ext_price_oracle::ext(config.oracle_id.clone())
+            .with_static_gas(GAS_FOR_GET_PRICE)
+            .get_price(config.asset_id.clone())
+            .then(
+                ext_price_resolver::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_PRICE_UPDATE)
+                    .on_price_update(),
+            )
+    }
+
+    /// Stores the price the oracle answered with if it's newer than the stored one, and returns
+    /// the stored price. A failed or malformed answer keeps the stored price.
+    #[private]
+    pub fn on_price_update(&mut self) -> Option<PriceData> {
+        let price = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<Option<PriceData>>(&value).ok().flatten()
+            }
+            PromiseResult::Failed => None,
+        };
+        if let Some(price) = price {
+            let newer = self
+                .latest_price
+                .as_ref()
+                .is_none_or(|latest| price.timestamp.0 > latest.timestamp.0);
+            if newer && price.price.0 > 0 && price.timestamp.0 <= env::block_timestamp() {
+                OraclePriceUpdate { price: &price }.emit();
+                self.latest_price = Some(price);
+            }
+        }
+        self.latest_price.clone()
+    }
+
+    pub fn oracle_config(&self) -> Option<OracleConfig> {
+        self.oracle_config.clone()
+    }
+
+    pub fn latest_price(&self) -> Option<PriceData> {
+        self.latest_price.clone()
+    }
+
+    /// ESTATES worth `amount_usd` at the latest price. Panics like the payments do when the price
+    /// can't be used.
+    pub fn usd_to_estates(&self, amount_usd: U128) -> U128 {
+        self.internal_usd_to_estates(amount_usd.0).into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+    use super::*;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    const MAX_AGE: u64 = 1_000;
+    /// ESTATES at 2.5 USD, give or take 0.01 USD.
+    const PRICE: Balance = 250_000_000;
+    const CONFIDENCE: Balance = 1_000_000;
+
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(1).build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.set_oracle_config(Some(OracleConfig {
+            oracle_id: accounts(4),
+            asset_id: "estates".into(),
+            max_age: MAX_AGE.into(),
+            max_confidence_bps: 100,
+        }));
+        (context, contract)
+    }
+
+    fn price(price: Balance, confidence: Balance, timestamp: u64) -> PriceData {
+        PriceData {
+            price: price.into(),
+            confidence: confidence.into(),
+            decimals: 8,
+            timestamp: timestamp.into(),
+        }
+    }
+
+    /// Feeds `answer` to `on_price_update` as the result of the oracle call.
+    fn answer(context: &mut VMContextBuilder, contract: &mut Contract, answer: &PriceData) {
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(near_sdk::serde_json::to_vec(&Some(answer)).unwrap())],
+        );
+        contract.on_price_update();
+    }
+
+    #[test]
+    fn test_update_price() {
+        let (mut context, mut contract) = setup();
+        let _ = contract.update_price();
+        assert_eq!(get_created_receipts()[0].receiver_id, accounts(4));
+
+        testing_env!(context.block_timestamp(100).build());
+        answer(&mut context, &mut contract, &price(PRICE, CONFIDENCE, 100));
+        assert_eq!(contract.latest_price(), Some(price(PRICE, CONFIDENCE, 100)));
+        // Older answers don't replace a newer price.
+        answer(&mut context, &mut contract, &price(2 * PRICE, CONFIDENCE, 50));
+        assert_eq!(contract.latest_price().unwrap().price.0, PRICE);
+
+        // 1 USD buys 0.4 ESTATES, with 24 decimals.
+        assert_eq!(contract.usd_to_estates(U128(1_000_000)).0, 400_000_000_000_000_000_000_000);
+        // Rounded up.
+        assert_eq!(contract.usd_to_estates(U128(1)).0, 400_000_000_000_000_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "The oracle price is stale")]
+    fn test_stale_price() {
+        let (mut context, mut contract) = setup();
+        answer(&mut context, &mut contract, &price(PRICE, CONFIDENCE, 0));
+        testing_env!(context.block_timestamp(MAX_AGE + 1).build());
+        contract.usd_to_estates(U128(1_000_000));
+    }
+
+    #[test]
+    #[should_panic(expected = "The oracle price confidence is too low")]
+    fn test_low_confidence_price() {
+        let (mut context, mut contract) = setup();
+        answer(&mut context, &mut contract, &price(PRICE, PRICE / 100 + 1, 0));
+        contract.usd_to_estates(U128(1_000_000));
+    }
+}
@@ -0,0 +1,29 @@
+use near_sdk::{assert_one_yocto, env, log, near_bindgen, require, AccountId};
+
+use crate::*;
+
+impl Contract {
+    pub(crate) fn assert_owner(&self) {
+        require!(
+            env::predecessor_account_id() == self.owner_id,
+            "Only the owner can call this method"
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    /// Hands the admin rights over to `new_owner_id`. Only the owner can call this method.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_owner(&mut self, new_owner_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        log!("Owner changed from @{} to @{}", self.owner_id, new_owner_id);
+        self.owner_id = new_owner_id;
+    }
+}
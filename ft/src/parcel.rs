@@ -0,0 +1,357 @@
+/*!
+Registry of NEAR Hub estate parcels priced in ESTATES.
+
+Parcels are registered by the contract owner and can then be listed for sale by their owner.
+A buyer purchases a listed parcel by sending at least its price to this contract with
+`ft_transfer_call` and a `buy_parcel` message. The seller is paid and the parcel changes hands in
+the same receipt, and anything sent above the price is refunded by `ft_resolve_transfer`.
+
+A parcel can also be listed at a USD price, which is converted to ESTATES at the latest oracle
+price when it's bought (see [`crate::oracle`]).
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance};
+
+use crate::events::{ParcelList, ParcelRegister, ParcelTransfer};
+use crate::record_storage::charge_attached_storage;
+use crate::*;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ParcelCoordinates {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Parcel {
+    pub parcel_id: U64,
+    pub owner_id: AccountId,
+    pub coordinates: Option<ParcelCoordinates>,
+    pub metadata_uri: Option<String>,
+    /// Listing price in ESTATES, `None` when the parcel is not for sale.
+    pub price: Option<U128>,
+    /// Whether the listing price is in USD instead, converted to ESTATES when the parcel is
+    /// bought.
+    pub price_in_usd: bool,
+}
+
+impl Contract {
+    fn internal_unwrap_parcel(&self, parcel_id: U64) -> Parcel {
+        self.parcels.get(&parcel_id.0).unwrap_or_else(|| env::panic_str("Parcel not found"))
+    }
+
+    fn internal_unwrap_owned_parcel(&self, parcel_id: U64) -> Parcel {
+        let parcel = self.internal_unwrap_parcel(parcel_id);
+        require!(
+            env::predecessor_account_id() == parcel.owner_id,
+            "Only the parcel owner can call this method"
+        );
+        parcel
+    }
+
+    /// Moves the parcel to `new_owner_id` and removes its listing.
+    fn internal_transfer_parcel(
+        &mut self,
+        mut parcel: Parcel,
+        new_owner_id: AccountId,
+        price: Option<U128>,
+    ) {
+        let parcel_id = parcel.parcel_id.0;
+        self.parcels_per_owner.remove(&parcel.owner_id, parcel_id);
+        self.parcels_per_owner.insert(&new_owner_id, parcel_id);
+        self.listed_parcels.remove(&parcel_id);
+        ParcelTransfer {
+            parcel_id: parcel.parcel_id,
+            old_owner_id: &parcel.owner_id,
+            new_owner_id: &new_owner_id,
+            price: price.as_ref(),
+        }
+        .emit();
+        parcel.owner_id = new_owner_id;
+        parcel.price = None;
+        parcel.price_in_usd = false;
+        self.parcels.insert(&parcel_id, &parcel);
+    }
+
+    fn internal_list_parcel(&mut self, parcel_id: U64, price: U128, price_in_usd: bool) {
+        let mut parcel = self.internal_unwrap_owned_parcel(parcel_id);
+        require!(price.0 > 0, "The price should be a positive number");
+        self.token.internal_unwrap_balance_of(&parcel.owner_id);
+        parcel.price = Some(price);
+        parcel.price_in_usd = price_in_usd;
+        self.parcels.insert(&parcel_id.0, &parcel);
+        self.listed_parcels.insert(&parcel_id.0);
+        ParcelList { parcel_id, owner_id: &parcel.owner_id, price: Some(&price), price_in_usd }
+            .emit();
+    }
+
+    /// Pays the seller of a listed parcel out of `amount` tokens that `buyer_id` sent to the
+    /// contract account, and hands the parcel over. Returns the unused amount.
+    pub(crate) fn internal_buy_parcel(
+        &mut self,
+        buyer_id: AccountId,
+        amount: Balance,
+        parcel_id: U64,
+    ) -> Balance {
+        let parcel = self.internal_unwrap_parcel(parcel_id);
+        let mut price = parcel.price.unwrap_or_else(|| env::panic_str("Parcel is not for sale"));
+        require!(buyer_id != parcel.owner_id, "The buyer already owns the parcel");
+        if parcel.price_in_usd {
+            price = self.internal_usd_to_estates(price.0).into();
+        }
+        require!(amount >= price.0, "The attached amount is less than the parcel price");
+        self.internal_transfer(
+            &env::current_account_id(),
+            &parcel.owner_id,
+            price.0,
+            Some(format!("parcel {} sale", parcel_id.0)),
+        );
+        self.internal_transfer_parcel(parcel, buyer_id, Some(price));
+        amount - price.0
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Registers a new parcel owned by `owner_id`. Only the contract owner can call this method.
+    /// Parcels stay registered for good, so the deposit that covers their storage is not
+    /// refunded, only the excess is.
+    #[payable]
+    pub fn register_parcel(
+        &mut self,
+        owner_id: AccountId,
+        coordinates: Option<ParcelCoordinates>,
+        metadata_uri: Option<String>,
+    ) -> U64 {
+        self.assert_owner();
+        require!(
+            coordinates.is_some() || metadata_uri.is_some(),
+            "Either coordinates or a metadata URI should be given"
+        );
+        let initial_storage = env::storage_usage();
+        let parcel_id = self.next_parcel_id;
+        self.next_parcel_id += 1;
+        let parcel = Parcel {
+            parcel_id: parcel_id.into(),
+            owner_id,
+            coordinates,
+            metadata_uri,
+            price: None,
+            price_in_usd: false,
+        };
+        self.parcels.insert(&parcel_id, &parcel);
+        self.parcels_per_owner.insert(&parcel.owner_id, parcel_id);
+        charge_attached_storage(initial_storage);
+        ParcelRegister {
+            parcel_id: parcel.parcel_id,
+            owner_id: &parcel.owner_id,
+            coordinates: parcel.coordinates,
+            metadata_uri: parcel.metadata_uri.as_deref(),
+        }
+        .emit();
+        parcel.parcel_id
+    }
+
+    /// Lists the parcel for sale at `price` ESTATES, or updates its price. The owner must be
+    /// registered with the token to get paid. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn list_parcel(&mut self, parcel_id: U64, price: U128) {
+        assert_one_yocto();
+        self.internal_list_parcel(parcel_id, price, false);
+    }
+
+    /// Same as `list_parcel`, with the price in USD. The oracle has to be configured.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn list_parcel_in_usd(&mut self, parcel_id: U64, price_usd: U128) {
+        assert_one_yocto();
+        require!(self.oracle_config.is_some(), "No oracle is configured");
+        self.internal_list_parcel(parcel_id, price_usd, true);
+    }
+
+    /// Takes the parcel off the market. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn unlist_parcel(&mut self, parcel_id: U64) {
+        assert_one_yocto();
+        let mut parcel = self.internal_unwrap_owned_parcel(parcel_id);
+        parcel.price = None;
+        parcel.price_in_usd = false;
+        self.parcels.insert(&parcel_id.0, &parcel);
+        self.listed_parcels.remove(&parcel_id.0);
+        ParcelList { parcel_id, owner_id: &parcel.owner_id, price: None, price_in_usd: false }
+            .emit();
+    }
+
+    /// Gives the parcel to `receiver_id` without payment. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn transfer_parcel(&mut self, parcel_id: U64, receiver_id: AccountId) {
+        assert_one_yocto();
+        let parcel = self.internal_unwrap_owned_parcel(parcel_id);
+        require!(parcel.owner_id != receiver_id, "The receiver already owns the parcel");
+        self.internal_transfer_parcel(parcel, receiver_id, None);
+    }
+
+    pub fn parcel(&self, parcel_id: U64) -> Option<Parcel> {
+        self.parcels.get(&parcel_id.0)
+    }
+
+    pub fn parcels_by_owner(
+        &self,
+        owner_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Parcel> {
+        self.parcels_per_owner
+            .page(&owner_id, from_index, limit)
+            .into_iter()
+            .filter_map(|parcel_id| self.parcels.get(&parcel_id))
+            .collect()
+    }
+
+    /// Lists parcels that are (`listed: true`, default) or aren't (`listed: false`) for sale.
+    pub fn parcels_by_listing(
+        &self,
+        listed: Option<bool>,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Parcel> {
+        let from_index = from_index.map(|index| index.0 as usize).unwrap_or(0);
+        let limit = limit.map(|limit| limit as usize).unwrap_or(usize::MAX);
+        if listed.unwrap_or(true) {
+            self.listed_parcels
+                .iter()
+                .skip(from_index)
+                .take(limit)
+                .filter_map(|parcel_id| self.parcels.get(&parcel_id))
+                .collect()
+        } else {
+            self.parcels
+                .values()
+                .filter(|parcel| parcel.price.is_none())
+                .skip(from_index)
+                .take(limit)
+                .collect()
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::serde_json::json;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::oracle::{OracleConfig, PriceData};
+    use crate::tests::{get_context, STORAGE_DEPOSIT, TOTAL_SUPPLY};
+
+    const PRICE: Balance = 500;
+
+    /// accounts(2) owns parcel 0, listed at `PRICE`, and parcel 1, not listed.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        contract.register_parcel(accounts(2), Some(ParcelCoordinates { x: 1, y: -2 }), None);
+        contract.register_parcel(accounts(2), None, Some("ipfs://parcel-1".to_string()));
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+        contract.list_parcel(U64(0), U128(PRICE));
+        (context, contract)
+    }
+
+    /// Runs a `buy_parcel` purchase of accounts(1) the way `ft_transfer_call` does.
+    fn buy(context: &mut VMContextBuilder, contract: &mut Contract, amount: Balance) -> U128 {
+        let msg = json!({ "buy_parcel": { "parcel_id": U64(0) } }).to_string();
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        let _ = contract.ft_transfer_call(accounts(0), amount.into(), None, msg.clone());
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        match contract.ft_on_transfer(accounts(1), amount.into(), msg) {
+            PromiseOrValue::Value(unused) => unused,
+            PromiseOrValue::Promise(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_listing_views() {
+        let (_context, contract) = setup();
+        assert_eq!(contract.parcels_by_owner(accounts(2), None, None).len(), 2);
+        let listed = contract.parcels_by_listing(None, None, None);
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].price, Some(U128(PRICE)));
+        let unlisted = contract.parcels_by_listing(Some(false), None, None);
+        assert_eq!(unlisted[0].parcel_id, U64(1));
+    }
+
+    #[test]
+    fn test_buy_parcel_pays_seller_and_refunds_excess() {
+        let (mut context, mut contract) = setup();
+        let unused = buy(&mut context, &mut contract, PRICE + 20);
+        assert_eq!(unused.0, 20);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, PRICE);
+        // The excess stays on the contract account until `ft_resolve_transfer` refunds it.
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 20);
+
+        let parcel = contract.parcel(U64(0)).unwrap();
+        assert_eq!(parcel.owner_id, accounts(1));
+        assert_eq!(parcel.price, None);
+        assert_eq!(contract.parcels_by_owner(accounts(1), None, None), vec![parcel]);
+        assert!(contract.parcels_by_listing(None, None, None).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached amount is less than the parcel price")]
+    fn test_buy_parcel_below_price() {
+        let (mut context, mut contract) = setup();
+        buy(&mut context, &mut contract, PRICE - 1);
+    }
+
+    #[test]
+    fn test_buy_parcel_listed_in_usd() {
+        let (mut context, mut contract) = setup();
+        contract.oracle_config = Some(OracleConfig {
+            oracle_id: accounts(4),
+            asset_id: "estates".into(),
+            max_age: U64(1_000),
+            max_confidence_bps: 100,
+        });
+        // A whole token is worth 10^12 USD, so 100 USD are 10^14 of the smallest unit.
+        contract.latest_price = Some(PriceData {
+            price: U128(1_000_000_000_000),
+            confidence: U128(0),
+            decimals: 0,
+            timestamp: U64(0),
+        });
+        contract.list_parcel_in_usd(U64(0), U128(100_000_000));
+        assert!(contract.parcel(U64(0)).unwrap().price_in_usd);
+
+        let estates_price = 100_000_000_000_000;
+        assert_eq!(buy(&mut context, &mut contract, estates_price + 20).0, 20);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, estates_price);
+        assert!(!contract.parcel(U64(0)).unwrap().price_in_usd);
+    }
+
+    #[test]
+    #[should_panic(expected = "to cover the storage")]
+    fn test_register_parcel_without_storage_deposit() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.register_parcel(accounts(2), Some(ParcelCoordinates { x: 2, y: 2 }), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the parcel owner can call this method")]
+    fn test_only_owner_lists_parcel() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.list_parcel(U64(1), U128(PRICE));
+    }
+}
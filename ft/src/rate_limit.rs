@@ -0,0 +1,335 @@
+/*!
+Outflow limits to contain compromised hot wallets.
+
+Outgoing transfers of an account, and the tokens it burns, are summed per window of `transfer_limit_window` nanoseconds.
+A new window starts with the first outgoing transfer after the previous window has elapsed.
+Two limits apply to every window, whichever is lower:
+  - the account's own limit, set by the account holder. Lowering it takes effect immediately,
+    raising or removing it only after `LIMIT_INCREASE_DELAY`, so a stolen key can't lift it,
+  - the global cap, set by the owner.
+
+The contract account itself is exempt, as it only pays out in-contract deals. A blocked transfer
+logs a `transfer_limit_block` event before it fails, which stays in the outcome of the failed
+receipt for wallets and monitors to pick up.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance};
+
+use crate::events::TransferLimitBlock;
+use crate::record_storage::charge_attached_storage;
+use crate::*;
+
+/// 24 hours in nanoseconds.
+pub const DEFAULT_TRANSFER_LIMIT_WINDOW: u64 = 24 * 3600 * 1_000_000_000;
+/// 24 hours in nanoseconds.
+pub const LIMIT_INCREASE_DELAY: u64 = 24 * 3600 * 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingTransferLimit {
+    /// `None` removes the limit.
+    pub limit: Option<U128>,
+    pub effective_at: U64,
+}
+
+/// Outflow limit of an account together with what it has sent in the current window.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct AccountTransferLimit {
+    pub limit: Option<U128>,
+    pub pending: Option<PendingTransferLimit>,
+    pub window_start: u64,
+    pub spent: Balance,
+}
+
+impl AccountTransferLimit {
+    /// Applies a pending increase once its delay has passed.
+    fn refresh(&mut self, now: u64) {
+        if let Some(pending) = self.pending.as_ref() {
+            if pending.effective_at.0 <= now {
+                self.limit = pending.limit;
+                self.pending = None;
+            }
+        }
+    }
+
+    /// Amount sent in the window that is current at `now`.
+    fn spent_at(&self, now: u64, window: u64) -> Balance {
+        if now >= self.window_start.saturating_add(window) {
+            0
+        } else {
+            self.spent
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferLimitView {
+    pub limit: Option<U128>,
+    pub pending: Option<PendingTransferLimit>,
+    pub global_cap: Option<U128>,
+    pub window: U64,
+    /// What the account can still send in the current window, `None` when unlimited.
+    pub remaining: Option<U128>,
+}
+
+impl Contract {
+    fn internal_transfer_limit(&self, account_id: &AccountId) -> AccountTransferLimit {
+        let mut limit = self.transfer_limits.get(account_id).unwrap_or_default();
+        limit.refresh(env::block_timestamp());
+        limit
+    }
+
+    /// Lowest of the account's own limit and the global cap.
+    fn internal_effective_limit(&self, limit: &AccountTransferLimit) -> Option<Balance> {
+        match (limit.limit.map(|limit| limit.0), self.global_transfer_cap) {
+            (Some(own), Some(global)) => Some(own.min(global)),
+            (own, global) => own.or(global),
+        }
+    }
+
+    /// Records an outgoing transfer of `amount` from `account_id` against its limits and panics
+    /// if that would exceed them.
+    pub(crate) fn internal_check_transfer_limit(
+        &mut self,
+        account_id: &AccountId,
+        amount: Balance,
+    ) {
+        if let Err(remaining) = self.internal_record_outflow(account_id, amount) {
+            env::panic_str(&format!(
+                "The transfer exceeds the outflow limit of the current window, {} can still be sent",
+                remaining
+            ));
+        }
+    }
+
+    /// Records an outgoing transfer of `amount` from `account_id` against its limits. If that
+    /// would exceed them, records nothing, logs the block and returns what can still be sent.
+    fn internal_record_outflow(
+        &mut self,
+        account_id: &AccountId,
+        amount: Balance,
+    ) -> Result<(), Balance> {
+        if account_id == &env::current_account_id() {
+            return Ok(());
+        }
+        let mut limit = self.internal_transfer_limit(account_id);
+        let effective_limit = match self.internal_effective_limit(&limit) {
+            Some(effective_limit) => effective_limit,
+            None => return Ok(()),
+        };
+        let now = env::block_timestamp();
+        let spent = limit.spent_at(now, self.transfer_limit_window);
+        if spent == 0 {
+            limit.window_start = now;
+        }
+        let spent_after = spent.saturating_add(amount);
+        if spent_after > effective_limit {
+            let remaining = effective_limit.saturating_sub(spent);
+            TransferLimitBlock { account_id, amount: &U128(amount), remaining: &U128(remaining) }
+                .emit();
+            return Err(remaining);
+        }
+        limit.spent = spent_after;
+        self.transfer_limits.insert(account_id, &limit);
+        Ok(())
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the outflow limit per window of the predecessor, `None` removes it. A lower limit
+    /// applies right away, a higher one after `LIMIT_INCREASE_DELAY`. Only registered accounts
+    /// can call this method. Requires at least 1 yoctoNEAR attached, and the first time a deposit
+    /// that covers the storage of the limit, which stays with the contract. The excess is
+    /// refunded.
+    #[payable]
+    pub fn set_transfer_limit(&mut self, limit: Option<U128>) {
+        require!(env::attached_deposit() > 0, "Requires attached deposit of at least 1 yoctoNEAR");
+        let account_id = env::predecessor_account_id();
+        self.token.internal_unwrap_balance_of(&account_id);
+        let initial_storage = env::storage_usage();
+        let mut account_limit = self.internal_transfer_limit(&account_id);
+        let is_decrease = match (account_limit.limit, limit) {
+            (Some(current), Some(new)) => new.0 <= current.0,
+            (None, Some(_)) => true,
+            (_, None) => false,
+        };
+        if is_decrease {
+            account_limit.limit = limit;
+            account_limit.pending = None;
+        } else {
+            account_limit.pending = Some(PendingTransferLimit {
+                limit,
+                effective_at: (env::block_timestamp() + LIMIT_INCREASE_DELAY).into(),
+            });
+        }
+        self.transfer_limits.insert(&account_id, &account_limit);
+        charge_attached_storage(initial_storage);
+    }
+
+    /// Sets the outflow cap per window that applies to every account, `None` removes it.
+    /// Only the owner can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_global_transfer_cap(&mut self, cap: Option<U128>) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.global_transfer_cap = cap.map(|cap| cap.0);
+    }
+
+    /// Sets the window length in nanoseconds. Only the owner can call this method.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_transfer_limit_window(&mut self, window: U64) {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(window.0 > 0, "The window should be a positive number");
+        self.transfer_limit_window = window.0;
+    }
+
+    pub fn transfer_limit_of(&self, account_id: AccountId) -> TransferLimitView {
+        let limit = self.internal_transfer_limit(&account_id);
+        TransferLimitView {
+            limit: limit.limit,
+            pending: limit.pending,
+            global_cap: self.global_transfer_cap.map(U128),
+            window: self.transfer_limit_window.into(),
+            remaining: self.remaining_transfer_allowance(account_id),
+        }
+    }
+
+    /// What `account_id` can still send in the current window, `None` when unlimited.
+    pub fn remaining_transfer_allowance(&self, account_id: AccountId) -> Option<U128> {
+        if account_id == env::current_account_id() {
+            return None;
+        }
+        let limit = self.internal_transfer_limit(&account_id);
+        let spent = limit.spent_at(env::block_timestamp(), self.transfer_limit_window);
+        self.internal_effective_limit(&limit).map(|limit| U128(limit.saturating_sub(spent)))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, STORAGE_DEPOSIT, TOTAL_SUPPLY};
+
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        testing_env!(context.attached_deposit(1).build());
+        (context, contract)
+    }
+
+    fn set_limit(context: &mut VMContextBuilder, contract: &mut Contract, limit: Option<U128>) {
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        contract.set_transfer_limit(limit);
+        testing_env!(context.attached_deposit(1).build());
+    }
+
+    #[test]
+    fn test_own_limit_resets_with_window() {
+        let (mut context, mut contract) = setup();
+        set_limit(&mut context, &mut contract, Some(U128(100)));
+        contract.ft_transfer(accounts(2), U128(60), None);
+        assert_eq!(contract.remaining_transfer_allowance(accounts(1)), Some(U128(40)));
+
+        testing_env!(context.block_timestamp(DEFAULT_TRANSFER_LIMIT_WINDOW).build());
+        assert_eq!(contract.remaining_transfer_allowance(accounts(1)), Some(U128(100)));
+        contract.ft_transfer(accounts(2), U128(100), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 160);
+    }
+
+    #[test]
+    #[should_panic(expected = "The transfer exceeds the outflow limit of the current window")]
+    fn test_own_limit_blocks_transfer_call() {
+        let (mut context, mut contract) = setup();
+        set_limit(&mut context, &mut contract, Some(U128(100)));
+        contract.ft_transfer(accounts(2), U128(60), None);
+        let _ = contract.ft_transfer_call(accounts(2), U128(41), None, "".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "The transfer exceeds the outflow limit of the current window, 40")]
+    fn test_own_limit_blocks_curve_sell() {
+        let (mut context, mut contract) = setup();
+        contract.bonding_curve = Some(BondingCurve::Linear { base_price: U128(1), slope: U128(0) });
+        testing_env!(context.attached_deposit(1_000).build());
+        contract.curve_buy(U128(0));
+        testing_env!(context.attached_deposit(1).build());
+        set_limit(&mut context, &mut contract, Some(U128(100)));
+        contract.ft_transfer(accounts(2), U128(60), None);
+        contract.curve_sell(U128(41), U128(0));
+    }
+
+    #[test]
+    fn test_limit_increase_is_delayed() {
+        let (mut context, mut contract) = setup();
+        set_limit(&mut context, &mut contract, Some(U128(100)));
+        set_limit(&mut context, &mut contract, None);
+        let view = contract.transfer_limit_of(accounts(1));
+        assert_eq!(view.limit, Some(U128(100)));
+        assert_eq!(view.pending.unwrap().effective_at.0, LIMIT_INCREASE_DELAY);
+
+        testing_env!(context.block_timestamp(LIMIT_INCREASE_DELAY).build());
+        assert_eq!(contract.remaining_transfer_allowance(accounts(1)), None);
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+    }
+
+    #[test]
+    fn test_global_cap_applies_to_every_account() {
+        let (mut context, mut contract) = setup();
+        contract.set_global_transfer_cap(Some(U128(50)));
+        set_limit(&mut context, &mut contract, Some(U128(100)));
+        assert_eq!(contract.remaining_transfer_allowance(accounts(1)), Some(U128(50)));
+        assert_eq!(contract.remaining_transfer_allowance(accounts(2)), Some(U128(50)));
+        assert_eq!(contract.remaining_transfer_allowance(accounts(0)), None);
+
+        contract.ft_transfer(accounts(2), U128(50), None);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_transfer(accounts(1), U128(20), None);
+        assert_eq!(contract.remaining_transfer_allowance(accounts(2)), Some(U128(30)));
+    }
+
+    #[test]
+    fn test_blocked_transfer_logs_event() {
+        let (mut context, mut contract) = setup();
+        set_limit(&mut context, &mut contract, Some(U128(100)));
+        contract.ft_transfer(accounts(2), U128(60), None);
+        assert_eq!(contract.internal_record_outflow(&accounts(1), 41), Err(40));
+        assert_eq!(contract.remaining_transfer_allowance(accounts(1)), Some(U128(40)));
+        let logs = get_logs();
+        assert!(logs.last().unwrap().contains(r#""event":"transfer_limit_block""#));
+        assert!(logs.last().unwrap().contains(r#""amount":"41","remaining":"40""#));
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires a deposit of")]
+    fn test_limit_storage_is_paid() {
+        let (_context, mut contract) = setup();
+        contract.set_transfer_limit(Some(U128(100)));
+    }
+
+    #[test]
+    #[should_panic(expected = "The account eugene is not registered")]
+    fn test_unregistered_account_cannot_set_limit() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        set_limit(&mut context, &mut contract, Some(U128(100)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn test_only_owner_sets_global_cap() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.set_global_transfer_cap(Some(U128(50)));
+    }
+}
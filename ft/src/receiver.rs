@@ -0,0 +1,82 @@
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::serde::Deserialize;
+use near_sdk::{env, near_bindgen, require, AccountId, PromiseOrValue};
+
+use crate::multi_token::TokenId;
+use crate::treasury::TreasuryCategory;
+use crate::*;
+
+/// Messages accepted in `msg` when ESTATES are sent to this contract with `ft_transfer_call`.
+/// E.g. `{"open_escrow": {"payee_id": "bob.near", ...}}` or `{"buy_parcel": {"parcel_id": "0"}}`.
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TokenReceiverMessage {
+    OpenEscrow {
+        payee_id: AccountId,
+        arbiter_id: AccountId,
+        deadline: U64,
+        terms_hash: Base64VecU8,
+    },
+    BuyParcel {
+        parcel_id: U64,
+    },
+    DistributeRevenue {},
+    MintShares {
+        token_id: TokenId,
+    },
+    FundTreasury {
+        category: TreasuryCategory,
+    },
+    /// ESTATES a DEX delivers to the buyback in flight.
+    Buyback {},
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+    /// Handles ESTATES sent to the contract itself. The tokens are already on the contract
+    /// account at this point, so every message is settled on the internal balances without
+    /// calling any other contract. The returned unused amount is refunded to the sender by
+    /// `ft_resolve_transfer`, and panicking here refunds the whole amount.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        require!(
+            env::predecessor_account_id() == env::current_account_id(),
+            "Only ESTATES transfers are accepted"
+        );
+        let message: TokenReceiverMessage = near_sdk::serde_json::from_str(&msg)
+            .unwrap_or_else(|_| env::panic_str("Invalid transfer message"));
+        let unused_amount = match message {
+            TokenReceiverMessage::OpenEscrow { payee_id, arbiter_id, deadline, terms_hash } => {
+                self.internal_open_escrow(
+                    sender_id, amount.0, payee_id, arbiter_id, deadline, terms_hash,
+                );
+                0
+            }
+            TokenReceiverMessage::BuyParcel { parcel_id } => {
+                self.internal_buy_parcel(sender_id, amount.0, parcel_id)
+            }
+            TokenReceiverMessage::DistributeRevenue {} => {
+                self.internal_distribute_revenue(&sender_id, amount.0, true);
+                0
+            }
+            TokenReceiverMessage::MintShares { token_id } => {
+                self.internal_mint_shares(sender_id, amount.0, token_id)
+            }
+            TokenReceiverMessage::FundTreasury { category } => {
+                self.internal_fund_treasury(&sender_id, amount.0, category);
+                0
+            }
+            TokenReceiverMessage::Buyback {} => {
+                self.internal_receive_buyback(&sender_id, amount.0);
+                0
+            }
+        };
+        PromiseOrValue::Value(U128(unused_amount))
+    }
+}
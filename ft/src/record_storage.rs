@@ -0,0 +1,108 @@
+/*!
+Storage deposits of the records accounts open on the contract: escrows, streams, subscriptions,
+locked transfers and parcels.
+
+The account that opens a record pays for the storage the record and its index entries take, and
+gets the deposit back when the record is closed. The paid amount is kept in the record itself.
+  - Methods called directly charge the attached deposit and refund what's left of it.
+  - Records opened from `ft_on_transfer`, where no deposit can be attached, are paid from a
+    storage credit the account tops up beforehand with `record_storage_deposit`.
+*/
+use near_sdk::json_types::U128;
+use near_sdk::{
+    assert_one_yocto, env, near_bindgen, require, AccountId, Balance, Promise, StorageUsage,
+};
+
+use crate::*;
+
+/// Cost of the storage used since `initial_storage`.
+fn storage_cost_since(initial_storage: StorageUsage) -> Balance {
+    Balance::from(env::storage_usage().saturating_sub(initial_storage)) * env::storage_byte_cost()
+}
+
+/// Charges the storage used since `initial_storage` to the attached deposit, refunds the rest of
+/// the deposit to the predecessor and returns the charged amount.
+pub(crate) fn charge_attached_storage(initial_storage: StorageUsage) -> Balance {
+    let cost = storage_cost_since(initial_storage);
+    let deposit = env::attached_deposit();
+    require!(
+        deposit >= cost,
+        format!("Requires a deposit of {} yoctoNEAR to cover the storage", cost)
+    );
+    if deposit > cost {
+        Promise::new(env::predecessor_account_id()).transfer(deposit - cost);
+    }
+    cost
+}
+
+/// Gives the storage deposit of a closed record back to the account that paid it.
+pub(crate) fn refund_record_storage(account_id: &AccountId, deposit: U128) {
+    if deposit.0 > 0 {
+        Promise::new(account_id.clone()).transfer(deposit.0);
+    }
+}
+
+impl Contract {
+    /// Charges the storage used since `initial_storage` to the storage credit of `account_id` and
+    /// returns the charged amount.
+    pub(crate) fn internal_charge_storage_credit(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage: StorageUsage,
+    ) -> Balance {
+        let cost = storage_cost_since(initial_storage);
+        let credit = self.record_storage_credits.get(account_id).unwrap_or(0);
+        require!(
+            credit >= cost,
+            format!(
+                "Requires a storage credit of {} yoctoNEAR, see `record_storage_deposit`",
+                cost
+            )
+        );
+        self.record_storage_credits.insert(account_id, &(credit - cost));
+        cost
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Adds the attached deposit to the storage credit of `account_id`, the predecessor by
+    /// default, minus the storage of the credit itself the first time. Returns the credit.
+    #[payable]
+    pub fn record_storage_deposit(&mut self, account_id: Option<AccountId>) -> U128 {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let deposit = env::attached_deposit();
+        let initial_storage = env::storage_usage();
+        let credit = self.record_storage_credits.get(&account_id).unwrap_or(0);
+        self.record_storage_credits.insert(&account_id, &credit);
+        let cost = storage_cost_since(initial_storage);
+        require!(deposit > cost, format!("Requires a deposit of more than {} yoctoNEAR", cost));
+        let credit = credit + deposit - cost;
+        self.record_storage_credits.insert(&account_id, &credit);
+        credit.into()
+    }
+
+    /// Sends `amount` of the predecessor's storage credit, all of it by default, back to them.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn record_storage_withdraw(&mut self, amount: Option<U128>) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let credit = self.record_storage_credits.get(&account_id).unwrap_or(0);
+        let amount = amount.map(|amount| amount.0).unwrap_or(credit);
+        require!(amount <= credit, "The amount exceeds the storage credit");
+        if amount == credit {
+            self.record_storage_credits.remove(&account_id);
+        } else {
+            self.record_storage_credits.insert(&account_id, &(credit - amount));
+        }
+        if amount > 0 {
+            Promise::new(account_id).transfer(amount);
+        }
+        amount.into()
+    }
+
+    pub fn record_storage_credit_of(&self, account_id: AccountId) -> U128 {
+        self.record_storage_credits.get(&account_id).unwrap_or(0).into()
+    }
+}
@@ -0,0 +1,407 @@
+/*!
+Guardian-based recovery of accounts whose keys are lost.
+
+A holder names guardian accounts, how many of them have to agree, and a delay. A guardian starts
+a recovery of the holder to a new account, and the other guardians approve it. Once the
+threshold is reached the delay starts, during which the holder can still veto the recovery with
+their keys. After it, anyone can finish the recovery, which moves the balance, the unclaimed
+revenue, the storage registration, the inheritance plan and the outflow limit of the holder to
+the new account. A plan or a limit the new account already has is kept instead, and the storage
+deposit of the holder's registration is refunded to a new account that was already registered.
+
+Only the liquid balance moves: locked transfers, streams, escrows and the like stay with the
+lost account. Like forced transfers, a recovery skips the outflow limits of the holder, but the
+new account has to satisfy the compliance rule like any receiver. The holder pays for the
+storage of their guardians, and gets the deposit back when the config is removed.
+*/
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Promise};
+
+use crate::events::{RecoveryFinish, RecoveryStart, RecoveryVeto};
+use crate::record_storage::{charge_attached_storage, refund_record_storage};
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecoveryConfig {
+    pub guardians: Vec<AccountId>,
+    /// Guardian approvals that start the delay.
+    pub threshold: u32,
+    /// Nanoseconds between reaching the threshold and the recovery.
+    pub delay: U64,
+    /// NEAR the holder paid for the storage of the config.
+    pub storage_deposit: U128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingRecovery {
+    pub new_account_id: AccountId,
+    pub approvals: Vec<AccountId>,
+    /// Timestamp in nanoseconds from which the recovery can be finished, set once the threshold
+    /// is reached.
+    pub ready_at: Option<U64>,
+}
+
+impl Contract {
+    fn internal_unwrap_recovery_config(&self, account_id: &AccountId) -> RecoveryConfig {
+        self.recovery_configs
+            .get(account_id)
+            .unwrap_or_else(|| env::panic_str("The account has no recovery configured"))
+    }
+
+    fn internal_assert_guardian(&self, config: &RecoveryConfig) -> AccountId {
+        let guardian_id = env::predecessor_account_id();
+        require!(
+            config.guardians.contains(&guardian_id),
+            "Only a guardian of the account can call this method"
+        );
+        guardian_id
+    }
+
+    fn internal_approve_recovery(
+        &mut self,
+        account_id: &AccountId,
+        config: &RecoveryConfig,
+        mut recovery: PendingRecovery,
+        guardian_id: AccountId,
+    ) -> PendingRecovery {
+        require!(!recovery.approvals.contains(&guardian_id), "Already approved");
+        recovery.approvals.push(guardian_id);
+        if recovery.ready_at.is_none() && recovery.approvals.len() >= config.threshold as usize {
+            recovery.ready_at = Some((env::block_timestamp() + config.delay.0).into());
+        }
+        self.pending_recoveries.insert(account_id, &recovery);
+        recovery
+    }
+
+    /// Removes the config and any pending recovery of `account_id`, and refunds the storage
+    /// deposit of the config to `refund_id`.
+    pub(crate) fn internal_remove_recovery_config(
+        &mut self,
+        account_id: &AccountId,
+        refund_id: &AccountId,
+    ) {
+        self.pending_recoveries.remove(account_id);
+        if let Some(config) = self.recovery_configs.remove(account_id) {
+            refund_record_storage(refund_id, config.storage_deposit);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Sets the guardians of the predecessor, replacing the previous ones and dropping any
+    /// pending recovery. Requires a deposit that covers the storage of the config, the excess
+    /// and the deposit of the previous config are refunded.
+    #[payable]
+    pub fn set_recovery_config(&mut self, guardians: Vec<AccountId>, threshold: u32, delay: U64) {
+        let account_id = env::predecessor_account_id();
+        require!(self.token.accounts.contains_key(&account_id), "The account is not registered");
+        require!(!guardians.contains(&account_id), "An account can't guard itself");
+        let mut unique = guardians.clone();
+        unique.sort();
+        unique.dedup();
+        require!(unique.len() == guardians.len(), "The guardians should be different");
+        require!(threshold > 0, "The threshold should be a positive number");
+        require!(
+            threshold as usize <= guardians.len(),
+            "The threshold exceeds the number of guardians"
+        );
+        self.internal_remove_recovery_config(&account_id, &account_id);
+        let initial_storage = env::storage_usage();
+        let mut config = RecoveryConfig { guardians, threshold, delay, storage_deposit: U128(0) };
+        self.recovery_configs.insert(&account_id, &config);
+        config.storage_deposit = charge_attached_storage(initial_storage).into();
+        self.recovery_configs.insert(&account_id, &config);
+    }
+
+    /// Removes the guardians of the predecessor and refunds the storage deposit of the config.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn remove_recovery_config(&mut self) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.internal_remove_recovery_config(&account_id, &account_id);
+    }
+
+    /// Starts recovering `account_id` to `new_account_id`, with the approval of the predecessor.
+    /// Only a guardian of the account can call this method. Requires exactly 1 yoctoNEAR
+    /// attached.
+    #[payable]
+    pub fn start_recovery(
+        &mut self,
+        account_id: AccountId,
+        new_account_id: AccountId,
+    ) -> PendingRecovery {
+        assert_one_yocto();
+        let config = self.internal_unwrap_recovery_config(&account_id);
+        let guardian_id = self.internal_assert_guardian(&config);
+        require!(
+            self.pending_recoveries.get(&account_id).is_none(),
+            "A recovery of the account is already pending"
+        );
+        require!(account_id != new_account_id, "The new account should be a different one");
+        require!(new_account_id != env::current_account_id(), "Can't recover to the contract");
+        RecoveryStart {
+            account_id: &account_id,
+            new_account_id: &new_account_id,
+            guardian_id: &guardian_id,
+        }
+        .emit();
+        let recovery = PendingRecovery { new_account_id, approvals: vec![], ready_at: None };
+        self.internal_approve_recovery(&account_id, &config, recovery, guardian_id)
+    }
+
+    /// Approves the pending recovery of `account_id`. Only a guardian of the account can call
+    /// this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn approve_recovery(&mut self, account_id: AccountId) -> PendingRecovery {
+        assert_one_yocto();
+        let config = self.internal_unwrap_recovery_config(&account_id);
+        let guardian_id = self.internal_assert_guardian(&config);
+        let recovery = self
+            .pending_recoveries
+            .get(&account_id)
+            .unwrap_or_else(|| env::panic_str("No recovery of the account is pending"));
+        self.internal_approve_recovery(&account_id, &config, recovery, guardian_id)
+    }
+
+    /// Cancels the pending recovery of the predecessor. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn veto_recovery(&mut self) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require!(
+            self.pending_recoveries.remove(&account_id).is_some(),
+            "No recovery of the account is pending"
+        );
+        RecoveryVeto { account_id: &account_id }.emit();
+    }
+
+    /// Moves the balance and the registration of `account_id` to the new account once the delay
+    /// of its approved recovery has passed. The storage deposit of the config goes to the new
+    /// account. Can be called by anyone.
+    pub fn finish_recovery(&mut self, account_id: AccountId) -> U128 {
+        let recovery = self
+            .pending_recoveries
+            .get(&account_id)
+            .unwrap_or_else(|| env::panic_str("No recovery of the account is pending"));
+        match recovery.ready_at {
+            Some(ready_at) if env::block_timestamp() >= ready_at.0 => {}
+            _ => env::panic_str("The recovery is not ready yet"),
+        }
+        let new_account_id = recovery.new_account_id;
+        self.internal_remove_recovery_config(&account_id, &new_account_id);
+
+        let balance = self.token.internal_unwrap_balance_of(&account_id);
+        self.internal_check_compliance(&account_id, &new_account_id, balance);
+        self.internal_before_balance_change(&account_id);
+        self.internal_before_balance_change(&new_account_id);
+        if self.token.accounts.contains_key(&new_account_id) {
+            // The holder's registration isn't needed anymore.
+            Promise::new(new_account_id.clone()).transfer(self.storage_balance_bounds().min.0);
+        } else {
+            self.token.internal_register_account(&new_account_id);
+        }
+        if balance > 0 {
+            self.token.internal_transfer(
+                &account_id,
+                &new_account_id,
+                balance,
+                Some("account recovery".to_string()),
+            );
+        }
+        self.internal_move_revenue(&account_id, &new_account_id);
+        if let Some(mut plan) = self.inheritance_plans.remove(&account_id) {
+            if !self.inheritance_plans.contains_key(&new_account_id) {
+                plan.last_activity = env::block_timestamp().into();
+                self.inheritance_plans.insert(&new_account_id, &plan);
+            }
+        }
+        if let Some(limit) = self.transfer_limits.remove(&account_id) {
+            if !self.transfer_limits.contains_key(&new_account_id) {
+                self.transfer_limits.insert(&new_account_id, &limit);
+            }
+        }
+        // The new account holds the balance at the open snapshots that it had before.
+        self.balance_snapshots.remove(&account_id);
+        if self.locked_balances.contains_key(&account_id) {
+            self.closed_lock_receivers.insert(&account_id);
+        }
+        self.token.accounts.remove(&account_id);
+        RecoveryFinish {
+            account_id: &account_id,
+            new_account_id: &new_account_id,
+            amount: &U128(balance),
+        }
+        .emit();
+        balance.into()
+    }
+
+    pub fn recovery_config(&self, account_id: AccountId) -> Option<RecoveryConfig> {
+        self.recovery_configs.get(&account_id)
+    }
+
+    pub fn pending_recovery(&self, account_id: AccountId) -> Option<PendingRecovery> {
+        self.pending_recoveries.get(&account_id)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::compliance::ComplianceRule;
+    use crate::tests::{get_context, near_sent_to, STORAGE_DEPOSIT, TOTAL_SUPPLY};
+
+    const DELAY: u64 = 1_000;
+    const AMOUNT: Balance = 500;
+
+    /// accounts(2) holds `AMOUNT` and is guarded by accounts(3) and accounts(4), both needed.
+    /// accounts(3) starts recovering it to accounts(5), which isn't registered.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.attached_deposit(1).build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        contract.ft_transfer(accounts(2), AMOUNT.into(), None);
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(STORAGE_DEPOSIT)
+            .build());
+        contract.set_recovery_config(vec![accounts(3), accounts(4)], 2, DELAY.into());
+        testing_env!(context.predecessor_account_id(accounts(3)).attached_deposit(1).build());
+        let recovery = contract.start_recovery(accounts(2), new_account());
+        assert_eq!(recovery.ready_at, None);
+        (context, contract)
+    }
+
+    fn new_account() -> AccountId {
+        "frank".parse().unwrap()
+    }
+
+    #[test]
+    fn test_recovery() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(4)).block_timestamp(10).build());
+        let recovery = contract.approve_recovery(accounts(2));
+        assert_eq!(recovery.ready_at, Some(U64(10 + DELAY)));
+        assert_eq!(contract.pending_recovery(accounts(2)), Some(recovery));
+
+        testing_env!(context.block_timestamp(10 + DELAY).build());
+        assert_eq!(contract.finish_recovery(accounts(2)).0, AMOUNT);
+        assert_eq!(contract.ft_balance_of(new_account()).0, AMOUNT);
+        assert!(contract.storage_balance_of(new_account()).is_some());
+        assert!(contract.storage_balance_of(accounts(2)).is_none());
+        assert!(contract.recovery_config(accounts(2)).is_none());
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    /// The guardians approve the recovery started in `setup`, and the delay passes.
+    fn approve(context: &mut VMContextBuilder, contract: &mut Contract) {
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.approve_recovery(accounts(2));
+        testing_env!(context.block_timestamp(DELAY).build());
+    }
+
+    #[test]
+    fn test_recovery_to_registered_account() {
+        let (mut context, mut contract) = setup();
+        let storage_deposit = contract.recovery_config(accounts(2)).unwrap().storage_deposit.0;
+        assert!(storage_deposit > 0);
+        contract.token.internal_register_account(&new_account());
+        approve(&mut context, &mut contract);
+        contract.finish_recovery(accounts(2));
+        // The registration of the holder and the storage of its config are given back.
+        let registration = contract.storage_balance_bounds().min.0;
+        assert_eq!(near_sent_to(&new_account()), registration + storage_deposit);
+    }
+
+    #[test]
+    fn test_recovery_moves_plan_and_limit() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(STORAGE_DEPOSIT)
+            .build());
+        contract.set_transfer_limit(Some(U128(100)));
+        testing_env!(context.attached_deposit(1).build());
+        contract.set_inheritance(accounts(1), U64(DELAY * 10));
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(STORAGE_DEPOSIT)
+            .build());
+        contract.create_proposal("Recovery".to_string(), String::new(), None);
+        testing_env!(context.attached_deposit(1).build());
+        contract.ft_transfer(accounts(2), U128(1), None);
+        assert!(contract.balance_snapshots.get(&accounts(2)).is_some());
+
+        approve(&mut context, &mut contract);
+        contract.finish_recovery(accounts(2));
+        assert_eq!(contract.transfer_limit_of(new_account()).limit, Some(U128(100)));
+        assert!(contract.transfer_limits.get(&accounts(2)).is_none());
+        let status = contract.inheritance_status(new_account()).unwrap();
+        assert_eq!(status.plan.beneficiary_id, accounts(1));
+        assert_eq!(status.plan.last_activity.0, DELAY);
+        assert!(contract.inheritance_status(accounts(2)).is_none());
+        assert!(contract.balance_snapshots.get(&accounts(2)).is_none());
+        assert_eq!(contract.voting_power(U64(0), accounts(2)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The receiver needs a valid attestation for this amount")]
+    fn test_recovery_checks_compliance() {
+        let (mut context, mut contract) = setup();
+        contract.compliance_rule = Some(ComplianceRule { threshold: U128(100), min_level: 1 });
+        contract.compliance_officers.insert(&accounts(1));
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.attest(accounts(2), 1, U64(DELAY * 10));
+        approve(&mut context, &mut contract);
+        contract.finish_recovery(accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires a deposit of")]
+    fn test_guardians_storage_is_paid() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.set_recovery_config(vec![accounts(3)], 1, DELAY.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "No recovery of the account is pending")]
+    fn test_holder_veto() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.approve_recovery(accounts(2));
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.veto_recovery();
+        assert!(contract.pending_recovery(accounts(2)).is_none());
+        testing_env!(context.block_timestamp(DELAY).build());
+        contract.finish_recovery(accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "The recovery is not ready yet")]
+    fn test_recovery_before_delay() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.approve_recovery(accounts(2));
+        testing_env!(context.block_timestamp(DELAY - 1).build());
+        contract.finish_recovery(accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only a guardian of the account can call this method")]
+    fn test_outsider_cannot_approve() {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.approve_recovery(accounts(2));
+    }
+}
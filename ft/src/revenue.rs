@@ -0,0 +1,269 @@
+/*!
+Pro-rata distribution of estate revenue to all ESTATES holders.
+
+Revenue is deposited either in ESTATES, with `ft_transfer_call` and a `distribute_revenue`
+message, or in NEAR, with `distribute_revenue_near`. Every deposit raises a magnified
+revenue-per-token accumulator by `amount * MAGNITUDE / eligible supply`, so distributing costs
+O(1) no matter how many holders there are.
+
+Each account remembers the accumulator value its revenue was last settled at. Before any change
+of its balance (transfer, mint or burn), the revenue earned since then is credited to the account
+and the snapshot is moved forward, so tokens only earn revenue distributed while they are held.
+
+Tokens held by the contract account itself (escrows, streams, undistributed revenue, ...) are
+not eligible.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance, Promise};
+
+use crate::events::{RevenueClaim, RevenueDistribute};
+use crate::math::U256;
+use crate::*;
+
+/// Scales the revenue per token so that small deposits over a large supply don't round to zero.
+const MAGNITUDE: u128 = 1 << 64;
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct RevenueAccount {
+    estates_per_token_paid: U256,
+    near_per_token_paid: U256,
+    estates_owed: Balance,
+    near_owed: Balance,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimableRevenue {
+    pub estates: U128,
+    pub near: U128,
+}
+
+/// Revenue of `balance` tokens while the accumulator went from `paid` to `current`.
+fn earned(balance: Balance, current: U256, paid: U256) -> Balance {
+    ((current - paid) * U256::from(balance) / U256::from(MAGNITUDE)).as_u128()
+}
+
+impl Contract {
+    /// Tokens that earn revenue: everything except what the contract account holds.
+    fn internal_revenue_eligible_supply(&self) -> Balance {
+        self.token.total_supply - self.token.ft_balance_of(env::current_account_id()).0
+    }
+
+    fn internal_settled_revenue(&self, account_id: &AccountId) -> RevenueAccount {
+        let mut revenue = self.revenue_accounts.get(account_id).unwrap_or_default();
+        let balance = self.token.accounts.get(account_id).unwrap_or(0);
+        revenue.estates_owed +=
+            earned(balance, self.estates_per_token, revenue.estates_per_token_paid);
+        revenue.near_owed += earned(balance, self.near_per_token, revenue.near_per_token_paid);
+        revenue.estates_per_token_paid = self.estates_per_token;
+        revenue.near_per_token_paid = self.near_per_token;
+        revenue
+    }
+
+    /// Credits the revenue earned by the current balance of `account_id`. Must run before every
+    /// change of that balance.
+    pub(crate) fn internal_settle_revenue(&mut self, account_id: &AccountId) {
+        if account_id == &env::current_account_id() {
+            return;
+        }
+        let revenue = self.internal_settled_revenue(account_id);
+        self.revenue_accounts.insert(account_id, &revenue);
+    }
+
+    /// Hands the unclaimed revenue of `from_id` over to `to_id`, e.g. when an account is
+    /// recovered.
+    pub(crate) fn internal_move_revenue(&mut self, from_id: &AccountId, to_id: &AccountId) {
+        let from = self.internal_settled_revenue(from_id);
+        let mut to = self.internal_settled_revenue(to_id);
+        to.estates_owed += from.estates_owed;
+        to.near_owed += from.near_owed;
+        self.revenue_accounts.insert(to_id, &to);
+        self.revenue_accounts.remove(from_id);
+    }
+
+    /// Spreads `amount` over the eligible supply. `estates` tells which accumulator to raise.
+    pub(crate) fn internal_distribute_revenue(
+        &mut self,
+        from_id: &AccountId,
+        amount: Balance,
+        estates: bool,
+    ) {
+        require!(amount > 0, "The amount should be a positive number");
+        let eligible_supply = self.internal_revenue_eligible_supply();
+        require!(eligible_supply > 0, "There are no holders to distribute revenue to");
+        let increase = U256::from(amount) * U256::from(MAGNITUDE) / U256::from(eligible_supply);
+        if estates {
+            self.estates_per_token += increase;
+        } else {
+            self.near_per_token += increase;
+        }
+        RevenueDistribute {
+            from_id,
+            asset: if estates { "estates" } else { "near" },
+            amount: &U128(amount),
+            eligible_supply: &U128(eligible_supply),
+        }
+        .emit();
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Distributes the attached NEAR to all holders in proportion to their balances.
+    #[payable]
+    pub fn distribute_revenue_near(&mut self) {
+        self.internal_distribute_revenue(
+            &env::predecessor_account_id(),
+            env::attached_deposit(),
+            false,
+        );
+    }
+
+    pub fn claimable_revenue(&self, account_id: AccountId) -> ClaimableRevenue {
+        if account_id == env::current_account_id() {
+            return ClaimableRevenue { estates: U128(0), near: U128(0) };
+        }
+        let revenue = self.internal_settled_revenue(&account_id);
+        ClaimableRevenue { estates: revenue.estates_owed.into(), near: revenue.near_owed.into() }
+    }
+
+    /// Pays out the revenue earned by the predecessor: ESTATES to their balance and NEAR to
+    /// their account. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn claim_revenue(&mut self) -> ClaimableRevenue {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        require!(account_id != env::current_account_id(), "The contract can't claim revenue");
+        let mut revenue = self.internal_settled_revenue(&account_id);
+        let claimed = ClaimableRevenue {
+            estates: revenue.estates_owed.into(),
+            near: revenue.near_owed.into(),
+        };
+        revenue.estates_owed = 0;
+        revenue.near_owed = 0;
+        self.revenue_accounts.insert(&account_id, &revenue);
+        if claimed.estates.0 > 0 {
+            self.internal_transfer(
+                &env::current_account_id(),
+                &account_id,
+                claimed.estates.0,
+                Some("revenue".to_string()),
+            );
+        }
+        if claimed.near.0 > 0 {
+            Promise::new(account_id.clone()).transfer(claimed.near.0);
+        }
+        RevenueClaim { account_id: &account_id, estates: &claimed.estates, near: &claimed.near }
+            .emit();
+        claimed
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::fungible_token::FungibleToken;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    /// accounts(1) holds 300 tokens, accounts(2) and accounts(3) are registered.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let context = crate::tests::get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), U128(300));
+        contract.token.internal_register_account(&accounts(2));
+        contract.token.internal_register_account(&accounts(3));
+        (context, contract)
+    }
+
+    fn transfer(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        from: usize,
+        to: usize,
+        amount: Balance,
+    ) {
+        testing_env!(context.predecessor_account_id(accounts(from)).attached_deposit(1).build());
+        contract.ft_transfer(accounts(to), amount.into(), None);
+    }
+
+    fn distribute_near(context: &mut VMContextBuilder, contract: &mut Contract, amount: Balance) {
+        testing_env!(context.predecessor_account_id(accounts(4)).attached_deposit(amount).build());
+        contract.distribute_revenue_near();
+    }
+
+    #[test]
+    fn test_transfers_between_distributions() {
+        let (mut context, mut contract) = setup();
+        distribute_near(&mut context, &mut contract, 3_000);
+        transfer(&mut context, &mut contract, 1, 2, 100);
+        distribute_near(&mut context, &mut contract, 3_000);
+        transfer(&mut context, &mut contract, 2, 3, 50);
+        transfer(&mut context, &mut contract, 1, 3, 200);
+        distribute_near(&mut context, &mut contract, 3_000);
+
+        // accounts(1): 3000 + 2000 + 0, accounts(2): 1000 + 500, accounts(3): 2500
+        assert_eq!(contract.claimable_revenue(accounts(1)).near.0, 5_000);
+        assert_eq!(contract.claimable_revenue(accounts(2)).near.0, 1_500);
+        assert_eq!(contract.claimable_revenue(accounts(3)).near.0, 2_500);
+    }
+
+    #[test]
+    fn test_estates_revenue_excludes_contract_balance() {
+        let (mut context, mut contract) = setup();
+        transfer(&mut context, &mut contract, 1, 2, 200);
+        // accounts(2) pays 100 tokens of rent into the distribution.
+        let msg = r#"{"distribute_revenue": {}}"#.to_string();
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+        let _ = contract.ft_transfer_call(accounts(0), U128(100), None, msg.clone());
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        let _ = contract.ft_on_transfer(accounts(2), U128(100), msg);
+
+        // The eligible supply is 100 + 100, the distributed tokens themselves don't count.
+        assert_eq!(contract.claimable_revenue(accounts(1)).estates.0, 50);
+        assert_eq!(contract.claimable_revenue(accounts(2)).estates.0, 50);
+        assert_eq!(contract.claimable_revenue(accounts(0)).estates.0, 0);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        assert_eq!(contract.claim_revenue().estates.0, 50);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, 150);
+        assert_eq!(contract.claimable_revenue(accounts(1)).estates.0, 0);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 50);
+    }
+
+    #[test]
+    fn test_claim_near_revenue() {
+        let (mut context, mut contract) = setup();
+        distribute_near(&mut context, &mut contract, 600);
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        let claimed = contract.claim_revenue();
+        assert_eq!(claimed, ClaimableRevenue { estates: U128(0), near: U128(600) });
+        assert_eq!(contract.claimable_revenue(accounts(1)).near.0, 0);
+    }
+
+    #[test]
+    fn test_large_amounts_do_not_overflow() {
+        let (mut context, mut contract) = setup();
+        distribute_near(&mut context, &mut contract, u128::MAX / 2);
+        assert_eq!(contract.claimable_revenue(accounts(1)).near.0, u128::MAX / 2 - 1);
+    }
+
+    #[test]
+    fn test_registration_covers_revenue_record() {
+        let (mut context, mut contract) = setup();
+        let balance_entry = FungibleToken::new(b"t".to_vec()).account_storage_usage;
+        transfer(&mut context, &mut contract, 1, 2, 100);
+        assert!(contract.revenue_accounts.contains_key(&accounts(2)));
+        let initial_storage = env::storage_usage();
+        let account_id: AccountId = "a".repeat(64).parse().unwrap();
+        contract
+            .revenue_accounts
+            .insert(&account_id, &contract.internal_settled_revenue(&accounts(2)));
+        let revenue_record = env::storage_usage() - initial_storage;
+        assert!(contract.token.account_storage_usage >= balance_entry + revenue_record);
+    }
+}
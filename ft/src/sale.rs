@@ -0,0 +1,352 @@
+/*!
+Direct sale of ESTATES for NEAR in rounds, for estate drops.
+
+The owner opens rounds, each with its own price, sale window, hard cap and optional per-account
+cap, and optionally restricted to a whitelist. `buy_tokens` mints as many tokens as the attached
+NEAR pays for, within the caps, and refunds the rest. The proceeds stay on the contract until the
+owner withdraws them.
+
+Prices are in yoctoNEAR per whole token, i.e. per `10^decimals` of the smallest unit.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance, Promise};
+
+use crate::events::{SaleRoundCreate, TokensPurchase};
+use crate::math::U256;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleRound {
+    pub round_id: U64,
+    /// yoctoNEAR per whole token.
+    pub price: U128,
+    /// Sale window in nanoseconds, `end_at` excluded.
+    pub start_at: U64,
+    pub end_at: U64,
+    /// Most tokens the round sells, in the smallest unit.
+    pub hard_cap: U128,
+    /// Most tokens a single account can buy in the round, in the smallest unit.
+    pub account_cap: Option<U128>,
+    pub whitelist_only: bool,
+    pub sold: U128,
+    pub raised: U128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum SaleStatus {
+    Upcoming,
+    Active,
+    SoldOut,
+    Ended,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleRoundView {
+    #[serde(flatten)]
+    pub round: SaleRound,
+    pub status: SaleStatus,
+}
+
+impl SaleRound {
+    fn status(&self, now: u64) -> SaleStatus {
+        if now < self.start_at.0 {
+            SaleStatus::Upcoming
+        } else if self.sold.0 >= self.hard_cap.0 {
+            SaleStatus::SoldOut
+        } else if now >= self.end_at.0 {
+            SaleStatus::Ended
+        } else {
+            SaleStatus::Active
+        }
+    }
+}
+
+impl Contract {
+    fn internal_unwrap_sale_round(&self, round_id: U64) -> SaleRound {
+        self.sale_rounds.get(&round_id.0).unwrap_or_else(|| env::panic_str("Sale round not found"))
+    }
+
+    /// Smallest units per whole token.
+    pub(crate) fn internal_token_unit(&self) -> U256 {
+        U256::from(10).pow(U256::from(self.metadata.get().unwrap().decimals))
+    }
+
+    fn internal_sale_purchased(&self, round_id: u64, account_id: &AccountId) -> Balance {
+        self.sale_purchases.get(&(round_id, account_id.clone())).unwrap_or(0)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Opens a sale round and returns its id. Only the owner can call this method.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn create_sale_round(
+        &mut self,
+        price: U128,
+        start_at: U64,
+        end_at: U64,
+        hard_cap: U128,
+        account_cap: Option<U128>,
+        whitelist_only: bool,
+    ) -> U64 {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(price.0 > 0, "The price should be a positive number");
+        require!(start_at.0 < end_at.0, "The sale should end after it starts");
+        require!(end_at.0 > env::block_timestamp(), "The sale should end in the future");
+        require!(hard_cap.0 > 0, "The hard cap should be a positive number");
+        if let Some(account_cap) = account_cap {
+            require!(account_cap.0 > 0, "The account cap should be a positive number");
+        }
+        let round_id = self.next_sale_round_id;
+        self.next_sale_round_id += 1;
+        let round = SaleRound {
+            round_id: round_id.into(),
+            price,
+            start_at,
+            end_at,
+            hard_cap,
+            account_cap,
+            whitelist_only,
+            sold: U128(0),
+            raised: U128(0),
+        };
+        self.sale_rounds.insert(&round_id, &round);
+        SaleRoundCreate { round: &round }.emit();
+        round.round_id
+    }
+
+    /// Adds `account_ids` to the whitelist of the round, or removes them when `whitelisted` is
+    /// false. Only the owner can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_sale_whitelist(
+        &mut self,
+        round_id: U64,
+        account_ids: Vec<AccountId>,
+        whitelisted: bool,
+    ) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.internal_unwrap_sale_round(round_id);
+        for account_id in account_ids {
+            let key = (round_id.0, account_id);
+            if whitelisted {
+                self.sale_whitelist.insert(&key);
+            } else {
+                self.sale_whitelist.remove(&key);
+            }
+        }
+    }
+
+    /// Buys as many tokens as the attached NEAR pays for in the round, within its caps. The
+    /// buyer must be registered. Whatever isn't spent is refunded. Returns the tokens bought.
+    #[payable]
+    pub fn buy_tokens(&mut self, round_id: U64) -> U128 {
+        let buyer_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        let mut round = self.internal_unwrap_sale_round(round_id);
+        require!(
+            round.status(env::block_timestamp()) == SaleStatus::Active,
+            "The sale round is not active"
+        );
+        require!(
+            !round.whitelist_only || self.sale_whitelist.contains(&(round_id.0, buyer_id.clone())),
+            "The account is not whitelisted for this round"
+        );
+
+        let unit = self.internal_token_unit();
+        let price = U256::from(round.price.0);
+        let affordable = U256::from(deposit) * unit / price;
+        let purchased = self.internal_sale_purchased(round_id.0, &buyer_id);
+        let mut available = round.hard_cap.0 - round.sold.0;
+        if let Some(account_cap) = round.account_cap {
+            available = available.min(account_cap.0.saturating_sub(purchased));
+        }
+        let amount =
+            if affordable > U256::from(available) { available } else { affordable.as_u128() };
+        require!(amount > 0, "The attached deposit doesn't buy any tokens");
+        // Rounded up, so the sale never undercharges.
+        let cost = ((U256::from(amount) * price + unit - 1) / unit).as_u128();
+
+        round.sold = (round.sold.0 + amount).into();
+        round.raised = (round.raised.0 + cost).into();
+        self.sale_rounds.insert(&round_id.0, &round);
+        self.sale_purchases.insert(&(round_id.0, buyer_id.clone()), &(purchased + amount));
+        self.sale_proceeds += cost;
+        self.internal_mint(&buyer_id, amount, "Tokens sale");
+        TokensPurchase { round_id, buyer_id: &buyer_id, amount: &U128(amount), cost: &U128(cost) }
+            .emit();
+        if deposit > cost {
+            Promise::new(buyer_id).transfer(deposit - cost);
+        }
+        amount.into()
+    }
+
+    /// Sends `amount` of the sale proceeds, all of them by default, to the owner. Only the owner
+    /// can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn withdraw_sale_proceeds(&mut self, amount: Option<U128>) -> U128 {
+        assert_one_yocto();
+        self.assert_owner();
+        let amount = amount.map(|amount| amount.0).unwrap_or(self.sale_proceeds);
+        require!(amount > 0, "There are no proceeds to withdraw");
+        require!(amount <= self.sale_proceeds, "The amount exceeds the sale proceeds");
+        self.sale_proceeds -= amount;
+        Promise::new(self.owner_id.clone()).transfer(amount);
+        amount.into()
+    }
+
+    pub fn sale_round(&self, round_id: U64) -> Option<SaleRoundView> {
+        let now = env::block_timestamp();
+        self.sale_rounds
+            .get(&round_id.0)
+            .map(|round| SaleRoundView { status: round.status(now), round })
+    }
+
+    pub fn sale_rounds(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<SaleRoundView> {
+        let now = env::block_timestamp();
+        self.sale_rounds
+            .values()
+            .skip(from_index.map(|index| index.0 as usize).unwrap_or(0))
+            .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+            .map(|round| SaleRoundView { status: round.status(now), round })
+            .collect()
+    }
+
+    /// Tokens `account_id` bought in the round.
+    pub fn sale_purchased(&self, round_id: U64, account_id: AccountId) -> U128 {
+        self.internal_sale_purchased(round_id.0, &account_id).into()
+    }
+
+    pub fn is_sale_whitelisted(&self, round_id: U64, account_id: AccountId) -> bool {
+        self.sale_whitelist.contains(&(round_id.0, account_id))
+    }
+
+    /// Sale and auction proceeds in yoctoNEAR that the owner hasn't withdrawn yet.
+    pub fn sale_proceeds(&self) -> U128 {
+        self.sale_proceeds.into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    use super::*;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    /// A whole token has 24 decimals, like NEAR.
+    const ONE_TOKEN: Balance = 1_000_000_000_000_000_000_000_000;
+    const START: u64 = 1_000;
+    const END: u64 = 2_000;
+
+    /// Round 0 sells 100 tokens at 0.5 NEAR each, at most 30 per account.
+    fn setup(whitelist_only: bool) -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        contract.token.internal_register_account(&accounts(3));
+        testing_env!(context.attached_deposit(1).build());
+        contract.create_sale_round(
+            U128(ONE_NEAR / 2),
+            U64(START),
+            U64(END),
+            U128(100 * ONE_TOKEN),
+            Some(U128(30 * ONE_TOKEN)),
+            whitelist_only,
+        );
+        (context, contract)
+    }
+
+    fn buy(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        buyer: usize,
+        deposit: Balance,
+    ) -> Balance {
+        testing_env!(context
+            .predecessor_account_id(accounts(buyer))
+            .attached_deposit(deposit)
+            .block_timestamp(START)
+            .build());
+        contract.buy_tokens(U64(0)).0
+    }
+
+    #[test]
+    fn test_buy_tokens_within_account_cap() {
+        let (mut context, mut contract) = setup(false);
+        assert_eq!(buy(&mut context, &mut contract, 2, 10 * ONE_NEAR + 3), 20 * ONE_TOKEN + 6);
+        // Only 10 more tokens fit under the account cap, the rest is refunded.
+        assert_eq!(buy(&mut context, &mut contract, 2, 10 * ONE_NEAR), 10 * ONE_TOKEN - 6);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 30 * ONE_TOKEN);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY + 30 * ONE_TOKEN);
+
+        let round = contract.sale_round(U64(0)).unwrap();
+        assert_eq!(round.status, SaleStatus::Active);
+        assert_eq!(round.round.sold.0, 30 * ONE_TOKEN);
+        assert_eq!(contract.sale_proceeds().0, 15 * ONE_NEAR);
+        assert_eq!(contract.sale_purchased(U64(0), accounts(2)).0, 30 * ONE_TOKEN);
+    }
+
+    #[test]
+    fn test_hard_cap_sells_out_round() {
+        let (mut context, mut contract) = setup(false);
+        testing_env!(context.attached_deposit(1).build());
+        contract.create_sale_round(
+            U128(ONE_NEAR),
+            U64(START),
+            U64(END),
+            U128(5 * ONE_TOKEN),
+            None,
+            false,
+        );
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(8 * ONE_NEAR)
+            .block_timestamp(START)
+            .build());
+        assert_eq!(contract.buy_tokens(U64(1)).0, 5 * ONE_TOKEN);
+        assert_eq!(contract.sale_round(U64(1)).unwrap().status, SaleStatus::SoldOut);
+        assert_eq!(contract.sale_proceeds().0, 5 * ONE_NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is not whitelisted for this round")]
+    fn test_whitelist_only_round() {
+        let (mut context, mut contract) = setup(true);
+        contract.set_sale_whitelist(U64(0), vec![accounts(2)], true);
+        assert_eq!(buy(&mut context, &mut contract, 2, ONE_NEAR), 2 * ONE_TOKEN);
+        buy(&mut context, &mut contract, 3, ONE_NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "The sale round is not active")]
+    fn test_buy_after_end() {
+        let (mut context, mut contract) = setup(false);
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_NEAR)
+            .block_timestamp(END)
+            .build());
+        contract.buy_tokens(U64(0));
+    }
+
+    #[test]
+    fn test_owner_withdraws_proceeds() {
+        let (mut context, mut contract) = setup(false);
+        buy(&mut context, &mut contract, 2, 4 * ONE_NEAR);
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        assert_eq!(contract.withdraw_sale_proceeds(Some(U128(ONE_NEAR))).0, ONE_NEAR);
+        assert_eq!(contract.withdraw_sale_proceeds(None).0, 3 * ONE_NEAR);
+        assert_eq!(contract.sale_proceeds().0, 0);
+    }
+}
@@ -0,0 +1,140 @@
+/*!
+NEP-330 source metadata, and the standards the contract implements.
+
+The version is the version of the `fungible-token` package, and the link is the `NEP330_LINK`
+environment variable of the build if it's set, or the `repository` of the package otherwise.
+
+<https://github.com/near/NEPs/blob/master/neps/nep-0330.md>
+*/
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::events::MT_EVENT_STANDARD_VERSION;
+use crate::*;
+
+/// The standards of [`Contract::supported_standards`], and their versions.
+const SUPPORTED_STANDARDS: [(&str, &str); 6] = [
+    ("nep141", "1.0.0"),
+    ("nep145", "1.0.0"),
+    ("nep148", "1.0.0"),
+    ("nep245", MT_EVENT_STANDARD_VERSION),
+    ("nep297", "1.0.0"),
+    ("nep330", "1.1.0"),
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Standard {
+    pub standard: String,
+    pub version: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContractSourceMetadata {
+    pub version: Option<String>,
+    /// Where the source code of this version can be found.
+    pub link: Option<String>,
+    pub standards: Vec<Standard>,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn contract_source_metadata(&self) -> ContractSourceMetadata {
+        let link = option_env!("NEP330_LINK").unwrap_or(env!("CARGO_PKG_REPOSITORY"));
+        ContractSourceMetadata {
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            link: Some(link.to_string()).filter(|link| !link.is_empty()),
+            standards: self.supported_standards(),
+        }
+    }
+
+    pub fn supported_standards(&self) -> Vec<Standard> {
+        SUPPORTED_STANDARDS
+            .iter()
+            .map(|(standard, version)| Standard {
+                standard: standard.to_string(),
+                version: version.to_string(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::fungible_token::metadata::{
+        FungibleTokenMetadataProvider, FT_METADATA_SPEC,
+    };
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::events::{EVENT_STANDARD, MT_EVENT_STANDARD};
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    // Each check only builds if the contract passed to it implements the standard, and gives the
+    // entry the standard should have in `supported_standards`.
+    type Entry = (&'static str, &'static str);
+
+    fn nep141<T: FungibleTokenCore + FungibleTokenResolver + FungibleTokenReceiver>(
+        _: &T,
+    ) -> Entry {
+        ("nep141", "1.0.0")
+    }
+
+    fn nep145<T: StorageManagement>(_: &T) -> Entry {
+        ("nep145", "1.0.0")
+    }
+
+    fn nep148<T: FungibleTokenMetadataProvider>(_: &T) -> Entry {
+        ("nep148", FT_METADATA_SPEC.trim_start_matches("ft-"))
+    }
+
+    fn nep245<Transfer, BalanceOf, Metadata>(_methods: (Transfer, BalanceOf, Metadata)) -> Entry {
+        (MT_EVENT_STANDARD, MT_EVENT_STANDARD_VERSION)
+    }
+
+    fn nep297(_event_standard: &str) -> Entry {
+        ("nep297", "1.0.0")
+    }
+
+    fn nep330<SourceMetadata: Fn(&Contract) -> ContractSourceMetadata>(
+        _method: SourceMetadata,
+    ) -> Entry {
+        ("nep330", "1.1.0")
+    }
+
+    /// Removing an implementation breaks the build of this test, and listing a standard that
+    /// isn't checked here fails it.
+    #[test]
+    fn test_supported_standards() {
+        testing_env!(get_context(accounts(1)).build());
+        let contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        let implemented: Vec<_> = [
+            nep141(&contract),
+            nep145(&contract),
+            nep148(&contract),
+            nep245((Contract::mt_transfer, Contract::mt_balance_of, Contract::mt_metadata)),
+            nep297(EVENT_STANDARD),
+            nep330(Contract::contract_source_metadata),
+        ]
+        .iter()
+        .map(|(standard, version)| (standard.to_string(), version.to_string()))
+        .collect();
+
+        let supported: Vec<_> = contract
+            .supported_standards()
+            .into_iter()
+            .map(|standard| (standard.standard, standard.version))
+            .collect();
+        assert_eq!(supported, implemented);
+
+        let metadata = contract.contract_source_metadata();
+        assert_eq!(metadata.version.unwrap(), env!("CARGO_PKG_VERSION"));
+        assert_eq!(metadata.standards, contract.supported_standards());
+    }
+}
@@ -0,0 +1,347 @@
+/*!
+Per-second payment streams.
+
+The sender locks the full stream amount on the contract account when the stream is created.
+Tokens then accrue to the receiver linearly between `start` and `end`:
+  - the receiver can withdraw whatever has accrued at any time,
+  - either party can cancel the stream, which pays out the accrued amount to the receiver and
+    refunds the rest to the sender.
+
+The sender pays for the storage of the stream with the deposit attached to `create_stream`, and
+gets it back when the stream is fully withdrawn or cancelled.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance};
+
+use crate::events::{StreamCancel, StreamCreate, StreamWithdraw};
+use crate::record_storage::{charge_attached_storage, refund_record_storage};
+use crate::*;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Stream {
+    pub stream_id: U64,
+    pub sender_id: AccountId,
+    pub receiver_id: AccountId,
+    /// Total amount streamed between `start` and `end`.
+    pub amount: U128,
+    /// Timestamps in nanoseconds.
+    pub start: U64,
+    pub end: U64,
+    /// Amount already withdrawn by the receiver.
+    pub withdrawn: U128,
+    /// NEAR the sender paid for the storage of the stream.
+    pub storage_deposit: U128,
+}
+
+impl Stream {
+    /// Amount accrued to the receiver at `timestamp`, including what was already withdrawn.
+    pub fn accrued_at(&self, timestamp: u64) -> Balance {
+        if timestamp <= self.start.0 {
+            0
+        } else if timestamp >= self.end.0 {
+            self.amount.0
+        } else {
+            mul_div(self.amount.0, timestamp - self.start.0, self.end.0 - self.start.0)
+        }
+    }
+
+    pub fn withdrawable_at(&self, timestamp: u64) -> Balance {
+        self.accrued_at(timestamp) - self.withdrawn.0
+    }
+}
+
+/// Computes `amount * numerator / denominator` without overflowing, given that
+/// `numerator <= denominator`.
+fn mul_div(amount: Balance, numerator: u64, denominator: u64) -> Balance {
+    let (numerator, denominator) = (numerator as u128, denominator as u128);
+    amount / denominator * numerator + amount % denominator * numerator / denominator
+}
+
+impl Contract {
+    fn internal_unwrap_stream(&self, stream_id: U64) -> Stream {
+        self.streams.get(&stream_id.0).unwrap_or_else(|| env::panic_str("Stream not found"))
+    }
+
+    fn internal_remove_stream(&mut self, stream: &Stream) {
+        self.streams.remove(&stream.stream_id.0);
+        self.streams_per_account.remove(&stream.sender_id, stream.stream_id.0);
+        self.streams_per_account.remove(&stream.receiver_id, stream.stream_id.0);
+        refund_record_storage(&stream.sender_id, stream.storage_deposit);
+    }
+
+    /// Pays out everything accrued so far to the receiver and returns the paid amount.
+    fn internal_stream_payout(&mut self, stream: &mut Stream) -> Balance {
+        let amount = stream.withdrawable_at(env::block_timestamp());
+        if amount > 0 {
+            stream.withdrawn = (stream.withdrawn.0 + amount).into();
+            self.internal_transfer(
+                &env::current_account_id(),
+                &stream.receiver_id,
+                amount,
+                Some(format!("stream {}", stream.stream_id.0)),
+            );
+        }
+        amount
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Creates a stream from the predecessor to `receiver_id`, funded from the predecessor's
+    /// balance. Exactly one of `amount` (total) or `rate` (tokens per second) must be given.
+    /// `start` defaults to the current block timestamp. Requires a deposit that covers the
+    /// storage of the stream, the excess is refunded.
+    #[payable]
+    pub fn create_stream(
+        &mut self,
+        receiver_id: AccountId,
+        amount: Option<U128>,
+        rate: Option<U128>,
+        start: Option<U64>,
+        end: U64,
+    ) -> U64 {
+        let sender_id = env::predecessor_account_id();
+        let start = start.map(|start| start.0).unwrap_or_else(env::block_timestamp);
+        require!(start >= env::block_timestamp(), "Stream cannot start in the past");
+        require!(end.0 > start, "Stream should end after it starts");
+        let duration = end.0 - start;
+        let amount: Balance = match (amount, rate) {
+            (Some(amount), None) => amount.0,
+            (None, Some(rate)) => {
+                require!(
+                    duration.is_multiple_of(NANOS_PER_SECOND),
+                    "Stream duration should be whole seconds when a rate is given"
+                );
+                rate.0
+                    .checked_mul((duration / NANOS_PER_SECOND) as u128)
+                    .unwrap_or_else(|| env::panic_str("Stream amount overflow"))
+            }
+            _ => env::panic_str("Either amount or rate should be given"),
+        };
+        require!(amount > 0, "The amount should be a positive number");
+        // Fails early if the receiver could never withdraw.
+        self.token.internal_unwrap_balance_of(&receiver_id);
+        self.internal_transfer(
+            &sender_id,
+            &env::current_account_id(),
+            amount,
+            Some("stream deposit".to_string()),
+        );
+
+        let initial_storage = env::storage_usage();
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 1;
+        let mut stream = Stream {
+            stream_id: stream_id.into(),
+            sender_id,
+            receiver_id,
+            amount: amount.into(),
+            start: start.into(),
+            end,
+            withdrawn: U128(0),
+            storage_deposit: U128(0),
+        };
+        self.streams.insert(&stream_id, &stream);
+        self.streams_per_account.insert(&stream.sender_id, stream_id);
+        self.streams_per_account.insert(&stream.receiver_id, stream_id);
+        stream.storage_deposit = charge_attached_storage(initial_storage).into();
+        self.streams.insert(&stream_id, &stream);
+        StreamCreate {
+            stream_id: stream.stream_id,
+            sender_id: &stream.sender_id,
+            receiver_id: &stream.receiver_id,
+            amount: &stream.amount,
+            start: stream.start,
+            end: stream.end,
+        }
+        .emit();
+        stream.stream_id
+    }
+
+    /// Transfers everything accrued so far to the receiver and returns the withdrawn amount.
+    /// Only the receiver can withdraw. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn withdraw_from_stream(&mut self, stream_id: U64) -> U128 {
+        assert_one_yocto();
+        let mut stream = self.internal_unwrap_stream(stream_id);
+        require!(
+            env::predecessor_account_id() == stream.receiver_id,
+            "Only the receiver can withdraw from the stream"
+        );
+        let amount = self.internal_stream_payout(&mut stream);
+        if stream.withdrawn == stream.amount {
+            self.internal_remove_stream(&stream);
+        } else {
+            self.streams.insert(&stream_id.0, &stream);
+        }
+        StreamWithdraw { stream_id, receiver_id: &stream.receiver_id, amount: &U128(amount) }
+            .emit();
+        amount.into()
+    }
+
+    /// Cancels the stream: the accrued amount goes to the receiver and the rest is refunded to
+    /// the sender. Can be called by either party. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn cancel_stream(&mut self, stream_id: U64) {
+        assert_one_yocto();
+        let mut stream = self.internal_unwrap_stream(stream_id);
+        let caller_id = env::predecessor_account_id();
+        require!(
+            caller_id == stream.sender_id || caller_id == stream.receiver_id,
+            "Only the sender or the receiver can cancel the stream"
+        );
+        let paid_out = self.internal_stream_payout(&mut stream);
+        let refund = stream.amount.0 - stream.withdrawn.0;
+        if refund > 0 {
+            self.internal_transfer(
+                &env::current_account_id(),
+                &stream.sender_id,
+                refund,
+                Some(format!("stream {} refund", stream_id.0)),
+            );
+        }
+        self.internal_remove_stream(&stream);
+        StreamCancel {
+            stream_id,
+            cancelled_by: &caller_id,
+            paid_out: &U128(paid_out),
+            refunded: &U128(refund),
+        }
+        .emit();
+    }
+
+    pub fn stream(&self, stream_id: U64) -> Option<Stream> {
+        self.streams.get(&stream_id.0)
+    }
+
+    /// Amount the receiver can withdraw from the stream right now.
+    pub fn stream_withdrawable(&self, stream_id: U64) -> U128 {
+        self.internal_unwrap_stream(stream_id).withdrawable_at(env::block_timestamp()).into()
+    }
+
+    /// Lists active streams in which `account_id` is the sender or the receiver.
+    pub fn streams_by_account(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Stream> {
+        self.streams_per_account
+            .page(&account_id, from_index, limit)
+            .into_iter()
+            .filter_map(|stream_id| self.streams.get(&stream_id))
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, near_sent_to, STORAGE_DEPOSIT, TOTAL_SUPPLY};
+
+    const START: u64 = 10 * NANOS_PER_SECOND;
+    const END: u64 = 110 * NANOS_PER_SECOND;
+
+    /// accounts(1) streams 10 tokens per second to accounts(2) for 100 seconds.
+    fn setup() -> (VMContextBuilder, Contract, U64) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(2));
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        let stream_id =
+            contract.create_stream(accounts(2), None, Some(U128(10)), Some(U64(START)), U64(END));
+        let storage_deposit = contract.stream(stream_id).unwrap().storage_deposit.0;
+        assert_eq!(near_sent_to(&accounts(1)), STORAGE_DEPOSIT - storage_deposit);
+        testing_env!(context.attached_deposit(1).build());
+        (context, contract, stream_id)
+    }
+
+    #[test]
+    fn test_create_stream() {
+        let (_context, contract, stream_id) = setup();
+        let stream = contract.stream(stream_id).unwrap();
+        assert_eq!(stream.amount.0, 1_000);
+        assert!(stream.storage_deposit.0 > 0);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 1_000);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY - 1_000);
+        assert_eq!(contract.stream_withdrawable(stream_id).0, 0);
+        assert_eq!(contract.streams_by_account(accounts(2), None, None), vec![stream]);
+    }
+
+    #[test]
+    fn test_withdraw_over_time() {
+        let (mut context, mut contract, stream_id) = setup();
+        testing_env!(context.block_timestamp(START + 25 * NANOS_PER_SECOND).build());
+        assert_eq!(contract.stream_withdrawable(stream_id).0, 250);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        assert_eq!(contract.withdraw_from_stream(stream_id).0, 250);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 250);
+        assert_eq!(contract.stream_withdrawable(stream_id).0, 0);
+
+        testing_env!(context.block_timestamp(START + NANOS_PER_SECOND / 2 * 51).build());
+        assert_eq!(contract.withdraw_from_stream(stream_id).0, 5);
+
+        testing_env!(context.block_timestamp(END + 1).build());
+        assert_eq!(contract.withdraw_from_stream(stream_id).0, 745);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 1_000);
+        assert!(contract.stream(stream_id).is_none());
+    }
+
+    #[test]
+    fn test_cancel_pays_accrued_and_refunds_rest() {
+        let (mut context, mut contract, stream_id) = setup();
+        testing_env!(context.block_timestamp(START + 40 * NANOS_PER_SECOND).build());
+        let storage_deposit = contract.stream(stream_id).unwrap().storage_deposit;
+        contract.cancel_stream(stream_id);
+        assert_eq!(near_sent_to(&accounts(1)), storage_deposit.0);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 400);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY - 400);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 0);
+        assert!(contract.streams_by_account(accounts(1), None, None).is_empty());
+    }
+
+    #[test]
+    fn test_accrual_does_not_overflow() {
+        let stream = Stream {
+            stream_id: U64(0),
+            sender_id: accounts(1),
+            receiver_id: accounts(2),
+            amount: U128(u128::MAX),
+            start: U64(0),
+            end: U64(u64::MAX),
+            withdrawn: U128(0),
+            storage_deposit: U128(0),
+        };
+        assert_eq!(
+            stream.accrued_at(u64::MAX / 2),
+            (u64::MAX as u128 + 2) * (u64::MAX / 2) as u128
+        );
+        assert_eq!(stream.accrued_at(u64::MAX), u128::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "to cover the storage")]
+    fn test_create_stream_without_storage_deposit() {
+        // One yoctoNEAR is attached after the setup.
+        let (_context, mut contract, _) = setup();
+        contract.create_stream(accounts(2), Some(U128(1_000)), None, None, U64(END));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the receiver can withdraw from the stream")]
+    fn test_sender_cannot_withdraw() {
+        let (mut context, mut contract, stream_id) = setup();
+        testing_env!(context.block_timestamp(END).build());
+        contract.withdraw_from_stream(stream_id);
+    }
+}
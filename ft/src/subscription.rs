@@ -0,0 +1,350 @@
+/*!
+Recurring pull payments, e.g. monthly estate rent.
+
+A payer authorizes a merchant to pull `amount` tokens once every `period`, optionally for at most
+`max_cycles` cycles. Nothing is locked up front: the merchant calls `charge_subscription` and the
+tokens move straight from the payer's balance, one cycle per call, once the cycle is due.
+
+Subscriptions priced in USD charge every cycle the ESTATES the amount is worth at the latest
+oracle price (see [`crate::oracle`]), so a cycle can't be charged while that price is stale.
+
+The payer pays for the storage of the subscription with the deposit attached when creating it,
+and gets it back when the subscription ends or is cancelled.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId};
+
+use crate::events::{SubscriptionCancel, SubscriptionCharge, SubscriptionCreate};
+use crate::record_storage::{charge_attached_storage, refund_record_storage};
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Subscription {
+    pub subscription_id: U64,
+    pub payer_id: AccountId,
+    pub merchant_id: AccountId,
+    /// Amount charged every cycle.
+    pub amount: U128,
+    /// Whether `amount` is in USD, converted to ESTATES on every charge.
+    pub amount_in_usd: bool,
+    /// Cycle length in nanoseconds.
+    pub period: U64,
+    pub max_cycles: Option<u32>,
+    pub cycles_charged: u32,
+    /// Timestamp in nanoseconds from which the next cycle can be charged.
+    pub next_charge_at: U64,
+    /// NEAR the payer paid for the storage of the subscription.
+    pub storage_deposit: U128,
+}
+
+impl Contract {
+    fn internal_unwrap_subscription(&self, subscription_id: U64) -> Subscription {
+        self.subscriptions
+            .get(&subscription_id.0)
+            .unwrap_or_else(|| env::panic_str("Subscription not found"))
+    }
+
+    fn internal_create_subscription(
+        &mut self,
+        merchant_id: AccountId,
+        amount: U128,
+        amount_in_usd: bool,
+        period: U64,
+        max_cycles: Option<u32>,
+    ) -> U64 {
+        let payer_id = env::predecessor_account_id();
+        require!(payer_id != merchant_id, "Payer and merchant should be different");
+        require!(amount.0 > 0, "The amount should be a positive number");
+        require!(period.0 > 0, "The period should be a positive number");
+        require!(max_cycles != Some(0), "Max cycles should be a positive number");
+        self.token.internal_unwrap_balance_of(&payer_id);
+        self.token.internal_unwrap_balance_of(&merchant_id);
+
+        let initial_storage = env::storage_usage();
+        let subscription_id = self.next_subscription_id;
+        self.next_subscription_id += 1;
+        let mut subscription = Subscription {
+            subscription_id: subscription_id.into(),
+            payer_id,
+            merchant_id,
+            amount,
+            amount_in_usd,
+            period,
+            max_cycles,
+            cycles_charged: 0,
+            next_charge_at: env::block_timestamp().into(),
+            storage_deposit: U128(0),
+        };
+        self.subscriptions.insert(&subscription_id, &subscription);
+        self.subscriptions_per_payer.insert(&subscription.payer_id, subscription_id);
+        self.subscriptions_per_merchant.insert(&subscription.merchant_id, subscription_id);
+        subscription.storage_deposit = charge_attached_storage(initial_storage).into();
+        self.subscriptions.insert(&subscription_id, &subscription);
+        SubscriptionCreate {
+            subscription_id: subscription.subscription_id,
+            payer_id: &subscription.payer_id,
+            merchant_id: &subscription.merchant_id,
+            amount: &subscription.amount,
+            amount_in_usd,
+            period: subscription.period,
+            max_cycles: subscription.max_cycles,
+        }
+        .emit();
+        subscription.subscription_id
+    }
+
+    fn internal_remove_subscription(&mut self, subscription: &Subscription) {
+        let subscription_id = subscription.subscription_id.0;
+        self.subscriptions.remove(&subscription_id);
+        self.subscriptions_per_payer.remove(&subscription.payer_id, subscription_id);
+        self.subscriptions_per_merchant.remove(&subscription.merchant_id, subscription_id);
+        refund_record_storage(&subscription.payer_id, subscription.storage_deposit);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Authorizes `merchant_id` to charge the predecessor `amount` tokens every `period`
+    /// nanoseconds. The first cycle can be charged right away. Requires a deposit that covers
+    /// the storage of the subscription, the excess is refunded.
+    #[payable]
+    pub fn create_subscription(
+        &mut self,
+        merchant_id: AccountId,
+        amount: U128,
+        period: U64,
+        max_cycles: Option<u32>,
+    ) -> U64 {
+        self.internal_create_subscription(merchant_id, amount, false, period, max_cycles)
+    }
+
+    /// Same as `create_subscription`, with `amount_usd` in USD. The oracle has to be configured.
+    #[payable]
+    pub fn create_subscription_in_usd(
+        &mut self,
+        merchant_id: AccountId,
+        amount_usd: U128,
+        period: U64,
+        max_cycles: Option<u32>,
+    ) -> U64 {
+        require!(self.oracle_config.is_some(), "No oracle is configured");
+        self.internal_create_subscription(merchant_id, amount_usd, true, period, max_cycles)
+    }
+
+    /// Charges one due cycle of the subscription. Only the merchant can charge. Panics without
+    /// changing anything if no cycle is due or the payer's balance is too low.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn charge_subscription(&mut self, subscription_id: U64) {
+        assert_one_yocto();
+        let mut subscription = self.internal_unwrap_subscription(subscription_id);
+        require!(
+            env::predecessor_account_id() == subscription.merchant_id,
+            "Only the merchant can charge the subscription"
+        );
+        require!(
+            env::block_timestamp() >= subscription.next_charge_at.0,
+            "The next cycle is not due yet"
+        );
+        let amount = if subscription.amount_in_usd {
+            self.internal_usd_to_estates(subscription.amount.0)
+        } else {
+            subscription.amount.0
+        };
+        require!(
+            self.token.ft_balance_of(subscription.payer_id.clone()).0 >= amount,
+            "The payer doesn't have enough balance for this cycle"
+        );
+
+        self.internal_transfer(
+            &subscription.payer_id,
+            &subscription.merchant_id,
+            amount,
+            Some(format!("subscription {}", subscription_id.0)),
+        );
+        subscription.cycles_charged += 1;
+        subscription.next_charge_at =
+            (subscription.next_charge_at.0 + subscription.period.0).into();
+        SubscriptionCharge {
+            subscription_id,
+            payer_id: &subscription.payer_id,
+            merchant_id: &subscription.merchant_id,
+            amount: &U128(amount),
+            cycle: subscription.cycles_charged,
+        }
+        .emit();
+        if Some(subscription.cycles_charged) == subscription.max_cycles {
+            self.internal_remove_subscription(&subscription);
+        } else {
+            self.subscriptions.insert(&subscription_id.0, &subscription);
+        }
+    }
+
+    /// Cancels the subscription. Only the payer can cancel. Requires exactly 1 yoctoNEAR
+    /// attached.
+    #[payable]
+    pub fn cancel_subscription(&mut self, subscription_id: U64) {
+        assert_one_yocto();
+        let subscription = self.internal_unwrap_subscription(subscription_id);
+        require!(
+            env::predecessor_account_id() == subscription.payer_id,
+            "Only the payer can cancel the subscription"
+        );
+        self.internal_remove_subscription(&subscription);
+        SubscriptionCancel {
+            subscription_id,
+            payer_id: &subscription.payer_id,
+            merchant_id: &subscription.merchant_id,
+            cycles_charged: subscription.cycles_charged,
+        }
+        .emit();
+    }
+
+    pub fn subscription(&self, subscription_id: U64) -> Option<Subscription> {
+        self.subscriptions.get(&subscription_id.0)
+    }
+
+    pub fn subscriptions_by_payer(
+        &self,
+        payer_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Subscription> {
+        self.subscriptions_per_payer
+            .page(&payer_id, from_index, limit)
+            .into_iter()
+            .filter_map(|subscription_id| self.subscriptions.get(&subscription_id))
+            .collect()
+    }
+
+    pub fn subscriptions_by_merchant(
+        &self,
+        merchant_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Subscription> {
+        self.subscriptions_per_merchant
+            .page(&merchant_id, from_index, limit)
+            .into_iter()
+            .filter_map(|subscription_id| self.subscriptions.get(&subscription_id))
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::oracle::{OracleConfig, PriceData};
+    use crate::tests::{get_context, near_sent_to, STORAGE_DEPOSIT};
+
+    const PERIOD: u64 = 30 * 24 * 3600 * 1_000_000_000;
+    const RENT: u128 = 100;
+
+    /// accounts(1) rents from accounts(2) for at most 3 months and holds 250 tokens.
+    fn setup() -> (VMContextBuilder, Contract, U64) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), U128(250));
+        contract.token.internal_register_account(&accounts(2));
+        testing_env!(context.attached_deposit(STORAGE_DEPOSIT).build());
+        let subscription_id =
+            contract.create_subscription(accounts(2), U128(RENT), U64(PERIOD), Some(3));
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+        (context, contract, subscription_id)
+    }
+
+    #[test]
+    fn test_charge_once_per_period() {
+        let (mut context, mut contract, subscription_id) = setup();
+        contract.charge_subscription(subscription_id);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, RENT);
+
+        testing_env!(context.block_timestamp(PERIOD).build());
+        contract.charge_subscription(subscription_id);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, 50);
+        let subscription = contract.subscription(subscription_id).unwrap();
+        assert_eq!(subscription.cycles_charged, 2);
+        assert_eq!(subscription.next_charge_at.0, 2 * PERIOD);
+        assert_eq!(contract.subscriptions_by_payer(accounts(1), None, None), vec![subscription]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The next cycle is not due yet")]
+    fn test_cannot_charge_before_period_elapsed() {
+        let (mut context, mut contract, subscription_id) = setup();
+        contract.charge_subscription(subscription_id);
+        testing_env!(context.block_timestamp(PERIOD - 1).build());
+        contract.charge_subscription(subscription_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "The payer doesn't have enough balance for this cycle")]
+    fn test_charge_fails_when_balance_is_short() {
+        let (mut context, mut contract, subscription_id) = setup();
+        contract.charge_subscription(subscription_id);
+        testing_env!(context.block_timestamp(PERIOD).build());
+        contract.charge_subscription(subscription_id);
+        testing_env!(context.block_timestamp(2 * PERIOD).build());
+        contract.charge_subscription(subscription_id);
+    }
+
+    /// Sets a price of 10^22 USD per whole token, so 1 USD is 100 of the smallest unit, and
+    /// opens a subscription of 1.5 USD per period.
+    fn setup_in_usd() -> (VMContextBuilder, Contract, U64) {
+        let (mut context, mut contract, _) = setup();
+        contract.oracle_config = Some(OracleConfig {
+            oracle_id: accounts(4),
+            asset_id: "estates".into(),
+            max_age: U64(PERIOD),
+            max_confidence_bps: 100,
+        });
+        contract.latest_price = Some(PriceData {
+            price: U128(10_000_000_000_000_000_000_000),
+            confidence: U128(0),
+            decimals: 0,
+            timestamp: U64(0),
+        });
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(STORAGE_DEPOSIT)
+            .build());
+        let subscription_id =
+            contract.create_subscription_in_usd(accounts(2), U128(1_500_000), U64(PERIOD), None);
+        testing_env!(context.predecessor_account_id(accounts(2)).attached_deposit(1).build());
+        (context, contract, subscription_id)
+    }
+
+    #[test]
+    fn test_charge_in_usd() {
+        let (_, mut contract, subscription_id) = setup_in_usd();
+        contract.charge_subscription(subscription_id);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 150);
+    }
+
+    #[test]
+    #[should_panic(expected = "The oracle price is stale")]
+    fn test_charge_in_usd_with_stale_price() {
+        let (mut context, mut contract, subscription_id) = setup_in_usd();
+        testing_env!(context.block_timestamp(PERIOD + 1).build());
+        contract.charge_subscription(subscription_id);
+    }
+
+    #[test]
+    fn test_payer_cancel() {
+        let (mut context, mut contract, subscription_id) = setup();
+        contract.charge_subscription(subscription_id);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let storage_deposit = contract.subscription(subscription_id).unwrap().storage_deposit;
+        contract.cancel_subscription(subscription_id);
+        assert!(storage_deposit.0 > 0);
+        assert_eq!(near_sent_to(&accounts(1)), storage_deposit.0);
+        assert!(contract.subscription(subscription_id).is_none());
+        assert!(contract.subscriptions_by_merchant(accounts(2), None, None).is_empty());
+    }
+}
@@ -0,0 +1,475 @@
+/*!
+Treasury sub-ledger for the project funds, split into spending categories.
+
+The treasury tokens are held on the contract account and booked per category. Anyone funds a
+category by sending tokens to this contract with `ft_transfer_call` and a `fund_treasury` message
+(see [`TokenReceiverMessage`](crate::receiver::TokenReceiverMessage)).
+
+Tokens only leave the treasury through spending proposals. The owner or a member of the council
+proposes to pay an amount of a category to a payee, for a reason:
+  - the owner's approval executes the proposal right away,
+  - otherwise it is executed once enough council members approved it.
+
+The owner or the proposer can reject a pending proposal. Every executed spending is appended to
+the spend log, which is never rewritten.
+
+The owner also decides what happens to the balance of an account closed with
+`storage_unregister(force=true)`: it's burned (the default), booked under a treasury category,
+or the forced closing of an account with a positive balance is refused. Refunds of
+`ft_transfer_call` that are burned because their sender closed its account follow the same
+policy, except that they can't be refused anymore and are burned instead.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, require, AccountId, Balance};
+
+use crate::events::{SpendExecute, SpendPropose, SpendReject, TreasuryFund};
+use crate::*;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TreasuryCategory {
+    Marketing,
+    Development,
+    Liquidity,
+}
+
+impl TreasuryCategory {
+    pub const ALL: [TreasuryCategory; 3] =
+        [TreasuryCategory::Marketing, TreasuryCategory::Development, TreasuryCategory::Liquidity];
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SpendProposal {
+    pub proposal_id: U64,
+    pub category: TreasuryCategory,
+    pub payee_id: AccountId,
+    pub amount: U128,
+    pub reason: String,
+    pub proposed_by: AccountId,
+    /// Council members who approved the proposal so far.
+    pub approvals: Vec<AccountId>,
+}
+
+/// Entry of the spend log.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SpendRecord {
+    #[serde(flatten)]
+    pub proposal: SpendProposal,
+    /// The owner, or the council member whose approval reached the threshold.
+    pub executed_by: AccountId,
+    /// Timestamp in nanoseconds.
+    pub executed_at: U64,
+}
+
+/// What happens to the tokens of closed accounts.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ClosedBalancePolicy {
+    Burn,
+    Treasury { category: TreasuryCategory },
+    Forbid,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TreasuryBalance {
+    pub category: TreasuryCategory,
+    pub balance: U128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TreasuryCouncil {
+    pub members: Vec<AccountId>,
+    /// Council approvals that execute a proposal.
+    pub threshold: u32,
+}
+
+impl Contract {
+    fn internal_treasury_balance(&self, category: TreasuryCategory) -> Balance {
+        self.treasury_balances.get(&category).unwrap_or(0)
+    }
+
+    /// Books `amount` tokens that were already moved to the contract account under `category`.
+    pub(crate) fn internal_fund_treasury(
+        &mut self,
+        sender_id: &AccountId,
+        amount: Balance,
+        category: TreasuryCategory,
+    ) {
+        let balance = self.internal_treasury_balance(category) + amount;
+        self.treasury_balances.insert(&category, &balance);
+        TreasuryFund { category, sender_id, amount: &U128(amount) }.emit();
+    }
+
+    /// Books `amount` tokens burned from the closed `account_id` under the treasury category of
+    /// the policy, if any, by minting them again on the contract account.
+    pub(crate) fn internal_collect_closed_balance(
+        &mut self,
+        account_id: &AccountId,
+        amount: Balance,
+    ) {
+        if let ClosedBalancePolicy::Treasury { category } = self.closed_balance_policy {
+            self.internal_mint(&env::current_account_id(), amount, "closed account to treasury");
+            self.internal_fund_treasury(account_id, amount, category);
+        }
+    }
+
+    fn internal_unwrap_spend_proposal(&self, proposal_id: U64) -> SpendProposal {
+        self.spend_proposals
+            .get(&proposal_id.0)
+            .unwrap_or_else(|| env::panic_str("Spend proposal not found"))
+    }
+
+    fn internal_execute_spend(&mut self, proposal: SpendProposal, executed_by: AccountId) {
+        let balance = self.internal_treasury_balance(proposal.category);
+        require!(proposal.amount.0 <= balance, "The amount exceeds the category balance");
+        self.treasury_balances.insert(&proposal.category, &(balance - proposal.amount.0));
+        self.spend_proposals.remove(&proposal.proposal_id.0);
+        self.internal_transfer(
+            &env::current_account_id(),
+            &proposal.payee_id,
+            proposal.amount.0,
+            Some(format!("treasury spend {}", proposal.proposal_id.0)),
+        );
+        let record =
+            SpendRecord { proposal, executed_by, executed_at: env::block_timestamp().into() };
+        SpendExecute { record: &record }.emit();
+        self.spend_log.push(&record);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Replaces the treasury council. `threshold` approvals of its members execute a proposal.
+    /// Only the owner can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_treasury_council(&mut self, members: Vec<AccountId>, threshold: u32) {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(threshold > 0, "The threshold should be a positive number");
+        self.treasury_council.clear();
+        for member in members {
+            self.treasury_council.insert(&member);
+        }
+        require!(
+            threshold as u64 <= self.treasury_council.len(),
+            "The threshold exceeds the council size"
+        );
+        self.treasury_council_threshold = threshold;
+    }
+
+    /// Proposes to pay `amount` of the `category` funds to `payee_id` and returns the proposal
+    /// id. Only the owner or a council member can call this method. Requires exactly 1 yoctoNEAR
+    /// attached.
+    #[payable]
+    pub fn propose_spend(
+        &mut self,
+        category: TreasuryCategory,
+        payee_id: AccountId,
+        amount: U128,
+        reason: String,
+    ) -> U64 {
+        assert_one_yocto();
+        let proposed_by = env::predecessor_account_id();
+        require!(
+            proposed_by == self.owner_id || self.treasury_council.contains(&proposed_by),
+            "Only the owner or a council member can propose a spend"
+        );
+        require!(amount.0 > 0, "The amount should be a positive number");
+        require!(
+            amount.0 <= self.internal_treasury_balance(category),
+            "The amount exceeds the category balance"
+        );
+        require!(!reason.is_empty(), "The reason should not be empty");
+        require!(self.token.accounts.contains_key(&payee_id), "The payee is not registered");
+
+        let proposal_id = self.next_spend_proposal_id;
+        self.next_spend_proposal_id += 1;
+        let proposal = SpendProposal {
+            proposal_id: proposal_id.into(),
+            category,
+            payee_id,
+            amount,
+            reason,
+            proposed_by,
+            approvals: vec![],
+        };
+        self.spend_proposals.insert(&proposal_id, &proposal);
+        SpendPropose { proposal: &proposal }.emit();
+        proposal.proposal_id
+    }
+
+    /// Approves the proposal. The owner's approval executes it, a council member's approval
+    /// executes it once the council threshold is reached. Returns whether it was executed.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn approve_spend(&mut self, proposal_id: U64) -> bool {
+        assert_one_yocto();
+        let approver_id = env::predecessor_account_id();
+        let mut proposal = self.internal_unwrap_spend_proposal(proposal_id);
+        if approver_id == self.owner_id {
+            self.internal_execute_spend(proposal, approver_id);
+            return true;
+        }
+        require!(
+            self.treasury_council.contains(&approver_id),
+            "Only the owner or a council member can approve a spend"
+        );
+        require!(!proposal.approvals.contains(&approver_id), "Already approved");
+        proposal.approvals.push(approver_id.clone());
+        // Members removed from the council since their approval don't count anymore.
+        let approvals = proposal
+            .approvals
+            .iter()
+            .filter(|member| self.treasury_council.contains(member))
+            .count();
+        if approvals >= self.treasury_council_threshold as usize {
+            self.internal_execute_spend(proposal, approver_id);
+            true
+        } else {
+            self.spend_proposals.insert(&proposal_id.0, &proposal);
+            false
+        }
+    }
+
+    /// Drops a pending proposal. Only the owner or the proposer can call this method.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn reject_spend(&mut self, proposal_id: U64) {
+        assert_one_yocto();
+        let proposal = self.internal_unwrap_spend_proposal(proposal_id);
+        let rejected_by = env::predecessor_account_id();
+        require!(
+            rejected_by == self.owner_id || rejected_by == proposal.proposed_by,
+            "Only the owner or the proposer can reject a spend"
+        );
+        self.spend_proposals.remove(&proposal_id.0);
+        SpendReject { proposal_id, rejected_by: &rejected_by }.emit();
+    }
+
+    /// Sets what happens to the balance of closed accounts. Only the owner can call this method.
+    /// Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn set_closed_balance_policy(&mut self, policy: ClosedBalancePolicy) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.closed_balance_policy = policy;
+    }
+
+    pub fn closed_balance_policy(&self) -> ClosedBalancePolicy {
+        self.closed_balance_policy
+    }
+
+    pub fn treasury_balances(&self) -> Vec<TreasuryBalance> {
+        TreasuryCategory::ALL
+            .iter()
+            .map(|&category| TreasuryBalance {
+                category,
+                balance: self.internal_treasury_balance(category).into(),
+            })
+            .collect()
+    }
+
+    pub fn treasury_council(&self) -> TreasuryCouncil {
+        TreasuryCouncil {
+            members: self.treasury_council.to_vec(),
+            threshold: self.treasury_council_threshold,
+        }
+    }
+
+    pub fn spend_proposal(&self, proposal_id: U64) -> Option<SpendProposal> {
+        self.spend_proposals.get(&proposal_id.0)
+    }
+
+    /// Lists the pending proposals.
+    pub fn spend_proposals(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<SpendProposal> {
+        self.spend_proposals
+            .values()
+            .skip(from_index.map(|index| index.0 as usize).unwrap_or(0))
+            .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    /// Lists the executed spendings, oldest first.
+    pub fn spend_log(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<SpendRecord> {
+        self.spend_log
+            .iter()
+            .skip(from_index.map(|index| index.0 as usize).unwrap_or(0))
+            .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    pub fn spend_log_length(&self) -> U64 {
+        self.spend_log.len().into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, TOTAL_SUPPLY};
+
+    const FUNDS: Balance = 1_000;
+
+    /// accounts(1) owns the contract and funds the development category, accounts(2) and
+    /// accounts(3) sit on a council of threshold 2, accounts(4) is the payee.
+    fn setup() -> (VMContextBuilder, Contract) {
+        let mut context = get_context(accounts(1));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(1), TOTAL_SUPPLY.into());
+        contract.token.internal_register_account(&accounts(4));
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.set_treasury_council(vec![accounts(2), accounts(3)], 2);
+        let msg = r#"{"fund_treasury": {"category": "development"}}"#.to_string();
+        let _ = contract.ft_transfer_call(accounts(0), FUNDS.into(), None, msg.clone());
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(0).build());
+        let unused = contract.ft_on_transfer(accounts(1), FUNDS.into(), msg);
+        assert!(matches!(unused, PromiseOrValue::Value(U128(0))));
+        (context, contract)
+    }
+
+    fn propose(context: &mut VMContextBuilder, contract: &mut Contract, proposer: usize) -> U64 {
+        testing_env!(context
+            .predecessor_account_id(accounts(proposer))
+            .attached_deposit(1)
+            .build());
+        contract.propose_spend(
+            TreasuryCategory::Development,
+            accounts(4),
+            U128(400),
+            "Estate builder invoice".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_fund_treasury() {
+        let (_context, contract) = setup();
+        let balances = contract.treasury_balances();
+        assert_eq!(balances[1].category, TreasuryCategory::Development);
+        assert_eq!(balances[1].balance.0, FUNDS);
+        assert_eq!(balances[0].balance.0, 0);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, FUNDS);
+    }
+
+    #[test]
+    fn test_owner_approval_executes() {
+        let (mut context, mut contract) = setup();
+        let proposal_id = propose(&mut context, &mut contract, 2);
+        testing_env!(context.predecessor_account_id(accounts(1)).block_timestamp(42).build());
+        assert!(contract.approve_spend(proposal_id));
+
+        assert_eq!(contract.ft_balance_of(accounts(4)).0, 400);
+        assert_eq!(contract.treasury_balances()[1].balance.0, FUNDS - 400);
+        assert!(contract.spend_proposal(proposal_id).is_none());
+        let log = contract.spend_log(None, None);
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].proposal.proposed_by, accounts(2));
+        assert_eq!(log[0].executed_by, accounts(1));
+        assert_eq!(log[0].executed_at.0, 42);
+    }
+
+    #[test]
+    fn test_council_threshold() {
+        let (mut context, mut contract) = setup();
+        let proposal_id = propose(&mut context, &mut contract, 1);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        assert!(!contract.approve_spend(proposal_id));
+        assert_eq!(contract.spend_proposal(proposal_id).unwrap().approvals, vec![accounts(2)]);
+        assert_eq!(contract.ft_balance_of(accounts(4)).0, 0);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        assert!(contract.approve_spend(proposal_id));
+        assert_eq!(contract.ft_balance_of(accounts(4)).0, 400);
+        assert_eq!(contract.spend_log_length().0, 1);
+    }
+
+    #[test]
+    fn test_reject_spend() {
+        let (mut context, mut contract) = setup();
+        let proposal_id = propose(&mut context, &mut contract, 2);
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.reject_spend(proposal_id);
+        assert!(contract.spend_proposals(None, None).is_empty());
+        assert!(contract.spend_log(None, None).is_empty());
+        assert_eq!(contract.treasury_balances()[1].balance.0, FUNDS);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or a council member can propose a spend")]
+    fn test_outsider_cannot_propose() {
+        let (mut context, mut contract) = setup();
+        propose(&mut context, &mut contract, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount exceeds the category balance")]
+    fn test_spend_overdrawn_category() {
+        let (mut context, mut contract) = setup();
+        let first = propose(&mut context, &mut contract, 1);
+        let second = propose(&mut context, &mut contract, 1);
+        let third = propose(&mut context, &mut contract, 1);
+        for proposal_id in [first, second, third] {
+            contract.approve_spend(proposal_id);
+        }
+    }
+
+    /// accounts(4) closes its account with 300 tokens under `policy`.
+    fn close_account(policy: ClosedBalancePolicy) -> Contract {
+        let (mut context, mut contract) = setup();
+        testing_env!(context.predecessor_account_id(accounts(1)).attached_deposit(1).build());
+        contract.set_closed_balance_policy(policy);
+        contract.ft_transfer(accounts(4), U128(300), None);
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        assert!(contract.storage_unregister(Some(true)));
+        contract
+    }
+
+    #[test]
+    fn test_closed_balance_is_burned() {
+        let contract = close_account(ClosedBalancePolicy::Burn);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY - 300);
+        let logs = get_logs();
+        assert!(logs[1].contains(r#""event":"ft_burn""#));
+        assert!(logs[1].contains(r#""memo":"account closed""#));
+    }
+
+    #[test]
+    fn test_closed_balance_to_treasury() {
+        let category = TreasuryCategory::Marketing;
+        let contract = close_account(ClosedBalancePolicy::Treasury { category });
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+        assert_eq!(contract.treasury_balances()[0].balance.0, 300);
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, FUNDS + 300);
+        let logs = get_logs();
+        assert!(logs[1].contains(r#""event":"ft_burn""#));
+        assert!(logs[2].contains(r#""event":"ft_mint""#));
+        assert!(logs[3].contains(r#""event":"treasury_fund""#));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't force unregister an account with a positive balance")]
+    fn test_forbid_force_unregister() {
+        close_account(ClosedBalancePolicy::Forbid);
+    }
+}
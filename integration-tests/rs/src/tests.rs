@@ -1,4 +1,4 @@
-use near_sdk::json_types::U128;
+use near_sdk::json_types::{U128, U64};
 use near_units::{parse_gas, parse_near};
 use serde_json::json;
 use workspaces::prelude::*;
@@ -7,6 +7,9 @@ use workspaces::{network::Sandbox, Account, Contract, Worker};
 
 const DEFI_WASM_FILEPATH: &str = "../../res/defi.wasm";
 const FT_WASM_FILEPATH: &str = "../../res/fungible_token.wasm";
+const MARKETPLACE_WASM_FILEPATH: &str = "../../res/marketplace.wasm";
+const ORACLE_WASM_FILEPATH: &str = "../../res/oracle.wasm";
+const DEX_WASM_FILEPATH: &str = "../../res/dex.wasm";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -18,6 +21,12 @@ async fn main() -> anyhow::Result<()> {
     let defi_contract = worker.dev_deploy(&defi_wasm).await?;
     let ft_wasm = std::fs::read(FT_WASM_FILEPATH)?;
     let ft_contract = worker.dev_deploy(&ft_wasm).await?;
+    let marketplace_wasm = std::fs::read(MARKETPLACE_WASM_FILEPATH)?;
+    let marketplace_contract = worker.dev_deploy(&marketplace_wasm).await?;
+    let oracle_wasm = std::fs::read(ORACLE_WASM_FILEPATH)?;
+    let oracle_contract = worker.dev_deploy(&oracle_wasm).await?;
+    let dex_wasm = std::fs::read(DEX_WASM_FILEPATH)?;
+    let dex_contract = worker.dev_deploy(&dex_wasm).await?;
 
     // create accounts
     let owner = worker.root_account().unwrap();
@@ -45,6 +54,18 @@ async fn main() -> anyhow::Result<()> {
         .transact()
         .await?
         .into_result()?;
+    let erin = owner
+        .create_subaccount(&worker, "erin")
+        .initial_balance(parse_near!("30 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    let frank = owner
+        .create_subaccount(&worker, "frank")
+        .initial_balance(parse_near!("30 N"))
+        .transact()
+        .await?
+        .into_result()?;
 
     // Initialize contracts
     ft_contract
@@ -71,13 +92,55 @@ async fn main() -> anyhow::Result<()> {
         .deposit(parse_near!("0.008 N"))
         .transact()
         .await?;
+    marketplace_contract
+        .call(&worker, "new")
+        .args_json(serde_json::json!({
+            "fungible_token_account_id": ft_contract.id()
+        }))?
+        .transact()
+        .await?;
+    marketplace_contract
+        .as_account()
+        .call(&worker, ft_contract.id(), "storage_deposit")
+        .args_json(serde_json::json!({
+            "account_id": marketplace_contract.id()
+        }))?
+        .deposit(parse_near!("0.008 N"))
+        .transact()
+        .await?;
+    oracle_contract
+        .call(&worker, "new")
+        .args_json(serde_json::json!({
+            "owner_id": owner.id()
+        }))?
+        .transact()
+        .await?;
+    // the DEX sells 2 ESTATES per NEAR
+    dex_contract
+        .call(&worker, "new")
+        .args_json(serde_json::json!({
+            "owner_id": owner.id(),
+            "fungible_token_account_id": ft_contract.id(),
+            "rate": "2",
+        }))?
+        .transact()
+        .await?;
+    dex_contract
+        .as_account()
+        .call(&worker, ft_contract.id(), "storage_deposit")
+        .args_json(serde_json::json!({
+            "account_id": dex_contract.id()
+        }))?
+        .deposit(parse_near!("0.008 N"))
+        .transact()
+        .await?;
 
     // begin tests
     test_total_supply(&owner, &ft_contract, &worker).await?;
     test_simple_transfer(&owner, &alice, &ft_contract, &worker).await?;
     test_can_close_empty_balance_account(&bob, &ft_contract, &worker).await?;
     test_close_account_non_empty_balance(&alice, &ft_contract, &worker).await?;
-    test_close_account_force_non_empty_balance(&alice, &ft_contract, &worker).await?;
+    test_close_account_force_non_empty_balance(&owner, &alice, &bob, &ft_contract, &worker).await?;
     test_transfer_call_with_burned_amount(&owner, &charlie, &ft_contract, &defi_contract, &worker)
         .await?;
     test_simulate_transfer_call_with_immediate_return_and_no_refund(
@@ -96,6 +159,30 @@ async fn main() -> anyhow::Result<()> {
     .await?;
     test_transfer_call_promise_panics_for_a_full_refund(&owner, &alice, &ft_contract, &worker)
         .await?;
+    test_mt_transfer_call_with_refund(&owner, &ft_contract, &defi_contract, &worker).await?;
+    test_mt_transfer_call_promise_panics_for_a_full_refund(
+        &owner,
+        &ft_contract,
+        &defi_contract,
+        &worker,
+    )
+    .await?;
+    test_marketplace_buy(&owner, &erin, &ft_contract, &marketplace_contract, &worker).await?;
+    test_marketplace_outbid_refund(
+        &owner,
+        &erin,
+        &frank,
+        &ft_contract,
+        &marketplace_contract,
+        &worker,
+    )
+    .await?;
+    test_marketplace_seller_withdrawal(&owner, &ft_contract, &marketplace_contract, &worker)
+        .await?;
+    test_oracle_usd_conversion(&owner, &ft_contract, &oracle_contract, &worker).await?;
+    test_oracle_stale_price_rejected(&owner, &ft_contract, &oracle_contract, &worker).await?;
+    test_buyback_burns_tokens(&owner, &ft_contract, &dex_contract, &worker).await?;
+    test_buyback_slippage_rolls_back(&owner, &ft_contract, &worker).await?;
     Ok(())
 }
 
@@ -225,10 +312,14 @@ async fn test_close_account_non_empty_balance(
 }
 
 async fn test_close_account_force_non_empty_balance(
+    owner: &Account,
     user_with_funds: &Account,
+    other_user: &Account,
     contract: &Contract,
     worker: &Worker<Sandbox>,
 ) -> anyhow::Result<()> {
+    // by default the balance is burned
+    let supply_before = ft_total_supply(contract, worker).await?;
     let result: CallExecutionDetails = user_with_funds
         .call(&worker, contract.id(), "storage_unregister")
         .args_json(serde_json::json!({"force": true }))?
@@ -245,10 +336,104 @@ async fn test_close_account_force_non_empty_balance(
             parse_near!("1,000 N") // alice balance from above transfer_amount
         )
     );
+    assert!(result.logs()[1].contains(r#""event":"ft_burn""#));
+    assert_eq!(
+        ft_total_supply(contract, worker).await?,
+        U128::from(supply_before.0 - parse_near!("1,000 N"))
+    );
+
+    // with the treasury policy the balance is booked under the treasury category instead
+    set_closed_balance_policy(
+        owner,
+        contract,
+        json!({"treasury": {"category": "marketing"}}),
+        worker,
+    )
+    .await?;
+    fund_account(owner, other_user, contract, worker).await?;
+    let supply_before = ft_total_supply(contract, worker).await?;
+    let result: CallExecutionDetails = other_user
+        .call(&worker, contract.id(), "storage_unregister")
+        .args_json(serde_json::json!({"force": true }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    assert!(result.is_success());
+    assert!(result.logs()[2].contains(r#""event":"ft_mint""#));
+    assert_eq!(ft_total_supply(contract, worker).await?, supply_before);
+    let treasury: serde_json::Value = contract
+        .call(&worker, "treasury_balances")
+        .args_json(json!({}))?
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(treasury[0]["balance"], parse_near!("1,000 N").to_string());
+
+    // with the forbid policy a funded account can't be closed
+    set_closed_balance_policy(owner, contract, json!("forbid"), worker).await?;
+    fund_account(owner, other_user, contract, worker).await?;
+    match other_user
+        .call(&worker, contract.id(), "storage_unregister")
+        .args_json(serde_json::json!({"force": true }))?
+        .deposit(1)
+        .transact()
+        .await
+    {
+        Ok(_result) => panic!("storage_unregister worked despite the forbid policy"),
+        Err(e) => assert!(e
+            .to_string()
+            .contains("Can't force unregister an account with a positive balance")),
+    }
+    assert_eq!(
+        ft_balance_of(contract, other_user, worker).await?,
+        U128::from(parse_near!("1,000 N"))
+    );
+
+    set_closed_balance_policy(owner, contract, json!("burn"), worker).await?;
     println!("      Passed ✅ test_close_account_force_non_empty_balance");
     Ok(())
 }
 
+async fn set_closed_balance_policy(
+    owner: &Account,
+    contract: &Contract,
+    policy: serde_json::Value,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    owner
+        .call(&worker, contract.id(), "set_closed_balance_policy")
+        .args_json(json!({ "policy": policy }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    Ok(())
+}
+
+/// Registers `user` and sends them 1,000 tokens.
+async fn fund_account(
+    owner: &Account,
+    user: &Account,
+    contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    owner
+        .call(&worker, contract.id(), "storage_deposit")
+        .args_json(json!({ "account_id": user.id() }))?
+        .deposit(parse_near!("0.008 N"))
+        .transact()
+        .await?;
+    owner
+        .call(&worker, contract.id(), "ft_transfer")
+        .args_json(json!({
+            "receiver_id": user.id(),
+            "amount": parse_near!("1,000 N").to_string()
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    Ok(())
+}
+
 async fn test_transfer_call_with_burned_amount(
     owner: &Account,
     user: &Account,
@@ -505,3 +690,560 @@ async fn test_transfer_call_promise_panics_for_a_full_refund(
     }
     Ok(())
 }
+
+async fn test_mt_transfer_call_with_refund(
+    owner: &Account,
+    ft_contract: &Contract,
+    defi_contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    // fractionalize a parcel and mint its shares to the owner
+    owner
+        .call(&worker, ft_contract.id(), "register_parcel")
+        .args_json(serde_json::json!({
+            "owner_id": owner.id(),
+            "metadata_uri": "ipfs://estate-0",
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .transact()
+        .await?;
+    let token_id: String = owner
+        .call(&worker, ft_contract.id(), "create_estate_shares")
+        .args_json(serde_json::json!({
+            "parcel_id": "0",
+            "metadata": { "title": "Estate 0" },
+        }))?
+        .deposit(1)
+        .transact()
+        .await?
+        .json()?;
+    owner
+        .call(&worker, ft_contract.id(), "mt_mint")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "owner_id": owner.id(),
+            "amount": "1000",
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+
+    owner
+        .call(&worker, ft_contract.id(), "mt_transfer_call")
+        .args_json(serde_json::json!({
+            "receiver_id": defi_contract.id(),
+            "token_id": token_id,
+            "amount": "100",
+            "msg": "30",
+        }))?
+        .deposit(1)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+
+    let owner_balance: U128 = ft_contract
+        .call(&worker, "mt_balance_of")
+        .args_json(json!({"account_id": owner.id(), "token_id": token_id}))?
+        .transact()
+        .await?
+        .json()?;
+    let defi_balance: U128 = ft_contract
+        .call(&worker, "mt_balance_of")
+        .args_json(json!({"account_id": defi_contract.id(), "token_id": token_id}))?
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(owner_balance, U128::from(930));
+    assert_eq!(defi_balance, U128::from(70));
+    println!("      Passed ✅ test_mt_transfer_call_with_refund");
+    Ok(())
+}
+
+async fn test_mt_transfer_call_promise_panics_for_a_full_refund(
+    owner: &Account,
+    ft_contract: &Contract,
+    defi_contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    let owner_before_balance: U128 = ft_contract
+        .call(&worker, "mt_balance_of")
+        .args_json(json!({"account_id": owner.id(), "token_id": "0"}))?
+        .transact()
+        .await?
+        .json()?;
+
+    owner
+        .call(&worker, ft_contract.id(), "mt_transfer_call")
+        .args_json(serde_json::json!({
+            "receiver_id": defi_contract.id(),
+            "token_id": "0",
+            "amount": "100",
+            "msg": "no parsey as integer big panic oh no",
+        }))?
+        .deposit(1)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+
+    let owner_after_balance: U128 = ft_contract
+        .call(&worker, "mt_balance_of")
+        .args_json(json!({"account_id": owner.id(), "token_id": "0"}))?
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(owner_before_balance, owner_after_balance);
+    println!("      Passed ✅ test_mt_transfer_call_promise_panics_for_a_full_refund");
+    Ok(())
+}
+
+async fn ft_balance_of(
+    ft_contract: &Contract,
+    account: &Account,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<U128> {
+    Ok(ft_contract
+        .call(&worker, "ft_balance_of")
+        .args_json(json!({"account_id": account.id()}))?
+        .transact()
+        .await?
+        .json()?)
+}
+
+async fn marketplace_balance_of(
+    marketplace_contract: &Contract,
+    account: &Account,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<U128> {
+    Ok(marketplace_contract
+        .call(&worker, "balance_of")
+        .args_json(json!({"account_id": account.id()}))?
+        .transact()
+        .await?
+        .json()?)
+}
+
+/// Lists the parcel at 100 ESTATES on the marketplace, which checks its owner with the registry.
+async fn list_parcel(
+    seller: &Account,
+    parcel_id: &str,
+    marketplace_contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    let listing_id: U64 = seller
+        .call(&worker, marketplace_contract.id(), "list")
+        .args_json(serde_json::json!({
+            "parcel_id": parcel_id,
+            "price": parse_near!("100 N").to_string(),
+        }))?
+        .gas(parse_gas!("50 Tgas") as u64)
+        .transact()
+        .await?
+        .json()?;
+    let listing: serde_json::Value = marketplace_contract
+        .call(&worker, "listing")
+        .args_json(json!({"listing_id": listing_id}))?
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(listing["parcel_id"], json!(parcel_id));
+    Ok(())
+}
+
+async fn test_marketplace_buy(
+    owner: &Account,
+    buyer: &Account,
+    ft_contract: &Contract,
+    marketplace_contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    // register and fund the buyer
+    owner
+        .call(&worker, ft_contract.id(), "storage_deposit")
+        .args_json(serde_json::json!({
+            "account_id": buyer.id()
+        }))?
+        .deposit(parse_near!("0.008 N"))
+        .transact()
+        .await?;
+    owner
+        .call(&worker, ft_contract.id(), "ft_transfer")
+        .args_json(serde_json::json!({
+            "receiver_id": buyer.id(),
+            "amount": parse_near!("1,000 N").to_string()
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+
+    // the owner of parcel 0 lists it and hands it over to the marketplace
+    list_parcel(owner, "0", marketplace_contract, worker).await?;
+    owner
+        .call(&worker, ft_contract.id(), "transfer_parcel")
+        .args_json(serde_json::json!({
+            "parcel_id": "0",
+            "receiver_id": marketplace_contract.id(),
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+
+    // paying above the price refunds the excess
+    buyer
+        .call(&worker, ft_contract.id(), "ft_transfer_call")
+        .args_json(serde_json::json!({
+            "receiver_id": marketplace_contract.id(),
+            "amount": parse_near!("120 N").to_string(),
+            "msg": json!({"buy": {"listing_id": "0"}}).to_string(),
+        }))?
+        .deposit(1)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+
+    assert_eq!(ft_balance_of(ft_contract, buyer, worker).await?, U128::from(parse_near!("900 N")));
+    assert_eq!(
+        marketplace_balance_of(marketplace_contract, owner, worker).await?,
+        U128::from(parse_near!("100 N"))
+    );
+    let listing: Option<serde_json::Value> = marketplace_contract
+        .call(&worker, "listing")
+        .args_json(json!({"listing_id": "0"}))?
+        .transact()
+        .await?
+        .json()?;
+    assert!(listing.is_none());
+    let parcel: serde_json::Value = ft_contract
+        .call(&worker, "parcel")
+        .args_json(json!({"parcel_id": "0"}))?
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(parcel["owner_id"], json!(buyer.id()));
+    println!("      Passed ✅ test_marketplace_buy");
+    Ok(())
+}
+
+async fn test_marketplace_outbid_refund(
+    owner: &Account,
+    bidder: &Account,
+    other_bidder: &Account,
+    ft_contract: &Contract,
+    marketplace_contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    // register and fund the other bidder
+    owner
+        .call(&worker, ft_contract.id(), "storage_deposit")
+        .args_json(serde_json::json!({
+            "account_id": other_bidder.id()
+        }))?
+        .deposit(parse_near!("0.008 N"))
+        .transact()
+        .await?;
+    owner
+        .call(&worker, ft_contract.id(), "ft_transfer")
+        .args_json(serde_json::json!({
+            "receiver_id": other_bidder.id(),
+            "amount": parse_near!("1,000 N").to_string()
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    owner
+        .call(&worker, ft_contract.id(), "register_parcel")
+        .args_json(serde_json::json!({
+            "owner_id": owner.id(),
+            "metadata_uri": "ipfs://estate-1",
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .transact()
+        .await?;
+    list_parcel(owner, "1", marketplace_contract, worker).await?;
+
+    let bidder_before_balance = ft_balance_of(ft_contract, bidder, worker).await?;
+    for (account, amount) in [(bidder, parse_near!("40 N")), (other_bidder, parse_near!("50 N"))] {
+        account
+            .call(&worker, ft_contract.id(), "ft_transfer_call")
+            .args_json(serde_json::json!({
+                "receiver_id": marketplace_contract.id(),
+                "amount": amount.to_string(),
+                "msg": json!({"bid": {"listing_id": "1"}}).to_string(),
+            }))?
+            .deposit(1)
+            .gas(parse_gas!("200 Tgas") as u64)
+            .transact()
+            .await?;
+    }
+
+    // the outbid bid is credited back and can be withdrawn
+    assert_eq!(
+        marketplace_balance_of(marketplace_contract, bidder, worker).await?,
+        U128::from(parse_near!("40 N"))
+    );
+    bidder
+        .call(&worker, marketplace_contract.id(), "withdraw")
+        .deposit(1)
+        .gas(parse_gas!("50 Tgas") as u64)
+        .transact()
+        .await?;
+    assert_eq!(ft_balance_of(ft_contract, bidder, worker).await?, bidder_before_balance);
+    assert_eq!(marketplace_balance_of(marketplace_contract, bidder, worker).await?, U128::from(0));
+    println!("      Passed ✅ test_marketplace_outbid_refund");
+    Ok(())
+}
+
+async fn test_marketplace_seller_withdrawal(
+    owner: &Account,
+    ft_contract: &Contract,
+    marketplace_contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    let owner_before_balance = ft_balance_of(ft_contract, owner, worker).await?;
+    let proceeds = marketplace_balance_of(marketplace_contract, owner, worker).await?;
+    assert_eq!(proceeds, U128::from(parse_near!("100 N")));
+
+    owner
+        .call(&worker, marketplace_contract.id(), "withdraw")
+        .deposit(1)
+        .gas(parse_gas!("50 Tgas") as u64)
+        .transact()
+        .await?;
+
+    assert_eq!(
+        ft_balance_of(ft_contract, owner, worker).await?.0,
+        owner_before_balance.0 + proceeds.0
+    );
+    assert_eq!(marketplace_balance_of(marketplace_contract, owner, worker).await?, U128::from(0));
+    println!("      Passed ✅ test_marketplace_seller_withdrawal");
+    Ok(())
+}
+
+async fn set_oracle_config(
+    owner: &Account,
+    ft_contract: &Contract,
+    oracle_contract: &Contract,
+    max_age: u64,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    owner
+        .call(&worker, ft_contract.id(), "set_oracle_config")
+        .args_json(json!({
+            "config": {
+                "oracle_id": oracle_contract.id(),
+                "asset_id": "estates",
+                "max_age": max_age.to_string(),
+                "max_confidence_bps": 100,
+            }
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    Ok(())
+}
+
+async fn set_oracle_price(
+    owner: &Account,
+    oracle_contract: &Contract,
+    confidence: &str,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    // ESTATES at 2.5 USD, with 8 decimals
+    owner
+        .call(&worker, oracle_contract.id(), "set_price")
+        .args_json(json!({
+            "asset_id": "estates",
+            "price": "250000000",
+            "confidence": confidence,
+            "decimals": 8,
+        }))?
+        .transact()
+        .await?;
+    Ok(())
+}
+
+async fn test_oracle_usd_conversion(
+    owner: &Account,
+    ft_contract: &Contract,
+    oracle_contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    // an hour is plenty for the sandbox blocks
+    set_oracle_config(owner, ft_contract, oracle_contract, 3_600_000_000_000, worker).await?;
+    set_oracle_price(owner, oracle_contract, "1000000", worker).await?;
+    let result: CallExecutionDetails = owner
+        .call(&worker, ft_contract.id(), "update_price")
+        .args_json(json!({}))?
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    assert!(result.is_success());
+
+    let estates: U128 = ft_contract
+        .call(&worker, "usd_to_estates")
+        .args_json(json!({"amount_usd": "1000000"}))?
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(estates, U128::from(parse_near!("0.4 N")));
+
+    // an answer with a confidence interval of more than 1% is stored but can't be used
+    set_oracle_price(owner, oracle_contract, "2500001", worker).await?;
+    owner
+        .call(&worker, ft_contract.id(), "update_price")
+        .args_json(json!({}))?
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    match ft_contract
+        .call(&worker, "usd_to_estates")
+        .args_json(json!({"amount_usd": "1000000"}))?
+        .transact()
+        .await
+    {
+        Ok(_result) => panic!("usd_to_estates worked despite the low confidence"),
+        Err(e) => assert!(e.to_string().contains("The oracle price confidence is too low")),
+    }
+    println!("      Passed ✅ test_oracle_usd_conversion");
+    Ok(())
+}
+
+async fn test_oracle_stale_price_rejected(
+    owner: &Account,
+    ft_contract: &Contract,
+    oracle_contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    // a price is stale one nanosecond after its observation, i.e. by the next block
+    set_oracle_config(owner, ft_contract, oracle_contract, 1, worker).await?;
+    set_oracle_price(owner, oracle_contract, "1000000", worker).await?;
+    owner
+        .call(&worker, ft_contract.id(), "update_price")
+        .args_json(json!({}))?
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    let price: Option<serde_json::Value> =
+        ft_contract.call(&worker, "latest_price").args_json(json!({}))?.transact().await?.json()?;
+    assert!(price.is_some());
+    match ft_contract
+        .call(&worker, "usd_to_estates")
+        .args_json(json!({"amount_usd": "1000000"}))?
+        .transact()
+        .await
+    {
+        Ok(_result) => panic!("usd_to_estates worked despite the stale price"),
+        Err(e) => assert!(e.to_string().contains("The oracle price is stale")),
+    }
+    println!("      Passed ✅ test_oracle_stale_price_rejected");
+    Ok(())
+}
+
+async fn ft_total_supply(ft_contract: &Contract, worker: &Worker<Sandbox>) -> anyhow::Result<U128> {
+    Ok(ft_contract
+        .call(&worker, "ft_total_supply")
+        .args_json(json!({}))?
+        .transact()
+        .await?
+        .json()?)
+}
+
+async fn sale_proceeds(ft_contract: &Contract, worker: &Worker<Sandbox>) -> anyhow::Result<U128> {
+    Ok(ft_contract.call(&worker, "sale_proceeds").args_json(json!({}))?.transact().await?.json()?)
+}
+
+async fn test_buyback_burns_tokens(
+    owner: &Account,
+    ft_contract: &Contract,
+    dex_contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    // the DEX holds ESTATES to sell
+    owner
+        .call(&worker, ft_contract.id(), "ft_transfer")
+        .args_json(json!({
+            "receiver_id": dex_contract.id(),
+            "amount": parse_near!("100 N").to_string()
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+
+    // the owner raises 5 NEAR of sale proceeds, at 1 NEAR per ESTATES
+    owner
+        .call(&worker, ft_contract.id(), "create_sale_round")
+        .args_json(json!({
+            "price": parse_near!("1 N").to_string(),
+            "start_at": "0",
+            "end_at": u64::MAX.to_string(),
+            "hard_cap": parse_near!("1,000 N").to_string(),
+            "whitelist_only": false,
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    owner
+        .call(&worker, ft_contract.id(), "buy_tokens")
+        .args_json(json!({"round_id": "0"}))?
+        .deposit(parse_near!("5 N"))
+        .transact()
+        .await?;
+    assert_eq!(sale_proceeds(ft_contract, worker).await?, U128::from(parse_near!("5 N")));
+
+    owner
+        .call(&worker, ft_contract.id(), "set_buyback_dex")
+        .args_json(json!({"dex_id": dex_contract.id()}))?
+        .deposit(1)
+        .transact()
+        .await?;
+    let supply_before = ft_total_supply(ft_contract, worker).await?;
+    let result: CallExecutionDetails = owner
+        .call(&worker, ft_contract.id(), "buyback")
+        .args_json(json!({
+            "amount_in": parse_near!("2 N").to_string(),
+            "min_out": parse_near!("4 N").to_string(),
+        }))?
+        .deposit(1)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    assert_eq!(result.json::<U128>()?, U128::from(parse_near!("4 N")));
+
+    assert_eq!(
+        ft_total_supply(ft_contract, worker).await?,
+        U128::from(supply_before.0 - parse_near!("4 N"))
+    );
+    assert_eq!(sale_proceeds(ft_contract, worker).await?, U128::from(parse_near!("3 N")));
+    assert_eq!(
+        ft_balance_of(ft_contract, dex_contract.as_account(), worker).await?,
+        U128::from(parse_near!("96 N"))
+    );
+    assert!(result.logs().iter().any(|log| log.contains(r#""event":"ft_burn""#)));
+    println!("      Passed ✅ test_buyback_burns_tokens");
+    Ok(())
+}
+
+async fn test_buyback_slippage_rolls_back(
+    owner: &Account,
+    ft_contract: &Contract,
+    worker: &Worker<Sandbox>,
+) -> anyhow::Result<()> {
+    // 1 NEAR buys 2 ESTATES, asking for 3 makes the swap fail
+    let supply_before = ft_total_supply(ft_contract, worker).await?;
+    let result: CallExecutionDetails = owner
+        .call(&worker, ft_contract.id(), "buyback")
+        .args_json(json!({
+            "amount_in": parse_near!("1 N").to_string(),
+            "min_out": parse_near!("3 N").to_string(),
+        }))?
+        .deposit(1)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    assert_eq!(result.json::<U128>()?, U128::from(0));
+
+    assert_eq!(ft_total_supply(ft_contract, worker).await?, supply_before);
+    assert_eq!(sale_proceeds(ft_contract, worker).await?, U128::from(parse_near!("3 N")));
+    assert!(result.logs().iter().any(|log| log.contains(r#""event":"buyback_fail""#)));
+    println!("      Passed ✅ test_buyback_slippage_rolls_back");
+    Ok(())
+}
@@ -16,7 +16,7 @@
 import { Worker, NearAccount, captureError, NEAR, BN } from 'near-workspaces';
 import anyTest, { TestFn } from 'ava';
 
-const STORAGE_BYTE_COST = '1.5 mN';
+const STORAGE_BYTE_COST = '8 mN';
 const INITIAL_SUPPLY = "10000";
 
 async function registerUser(ft: NearAccount, user: NearAccount) {
@@ -2,6 +2,7 @@
 
 title FT build
 cd ..
+if not exist res mkdir res
 cargo build --all --target wasm32-unknown-unknown --release
 xcopy %CD%\target\wasm32-unknown-unknown\release\*.wasm %CD%\res /Y
 pause
\ No newline at end of file
@@ -1,6 +1,12 @@
 #!/bin/bash
 set -e
-cd "`dirname $0`"/../ft
+cd "`dirname $0`"/..
+mkdir -p res
+cd ft
 cargo build --all --target wasm32-unknown-unknown --release
 cd ..
 cp ft/target/wasm32-unknown-unknown/release/*.wasm ./res/
+for contract in test-contract-defi test-contract-marketplace test-contract-oracle test-contract-dex; do
+  (cd $contract && cargo build --target wasm32-unknown-unknown --release)
+  cp $contract/target/wasm32-unknown-unknown/release/*.wasm ./res/
+done
@@ -18,6 +18,7 @@ pub struct DeFi {
 #[ext_contract(ext_self)]
 pub trait ValueReturnTrait {
     fn value_please(&self, amount_to_return: String) -> PromiseOrValue<U128>;
+    fn values_please(&self, amount_to_return: String, count: u64) -> PromiseOrValue<Vec<U128>>;
 }
 
 #[near_bindgen]
@@ -58,6 +59,42 @@ impl FungibleTokenReceiver for DeFi {
     }
 }
 
+#[near_bindgen]
+impl DeFi {
+    /// Same as `ft_on_transfer`, for the estate shares (NEP-245) of the fungible token contract.
+    /// If given `msg: "take-my-money", immediately keeps everything.
+    /// Otherwise, makes a cross-contract call to own `values_please` function, which returns
+    /// `msg` parsed as an integer as the unused amount of every token
+    pub fn mt_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_ids: Vec<AccountId>,
+        token_ids: Vec<String>,
+        amounts: Vec<U128>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>> {
+        assert_eq!(
+            &env::predecessor_account_id(),
+            &self.fungible_token_account_id,
+            "Only supports the one fungible token contract"
+        );
+        log!(
+            "in {:?} of {:?} from @{} ({:?}) mt_on_transfer, msg = {}",
+            amounts,
+            token_ids,
+            sender_id.as_ref(),
+            previous_owner_ids,
+            msg
+        );
+        match msg.as_str() {
+            "take-my-money" => PromiseOrValue::Value(vec![U128::from(0); token_ids.len()]),
+            _ => Self::ext(env::current_account_id())
+                .values_please(msg, token_ids.len() as u64)
+                .into(),
+        }
+    }
+}
+
 #[near_bindgen]
 impl ValueReturnTrait for DeFi {
     fn value_please(&self, amount_to_return: String) -> PromiseOrValue<U128> {
@@ -65,4 +102,10 @@ impl ValueReturnTrait for DeFi {
         let amount: Balance = amount_to_return.parse().expect("Not an integer");
         PromiseOrValue::Value(amount.into())
     }
+
+    fn values_please(&self, amount_to_return: String, count: u64) -> PromiseOrValue<Vec<U128>> {
+        log!("in values_please, amount_to_return = {}", amount_to_return);
+        let amount: Balance = amount_to_return.parse().expect("Not an integer");
+        PromiseOrValue::Value(vec![amount.into(); count as usize])
+    }
 }
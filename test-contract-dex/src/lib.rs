@@ -0,0 +1,99 @@
+/*!
+Minimal DEX stand-in for the sandbox tests of the ESTATES buyback.
+
+It sells the ESTATES it holds for NEAR at a fixed rate set by its owner: `swap_near` sends
+`rate` of the smallest unit of ESTATES per attached yoctoNEAR to the predecessor with
+`ft_transfer_call` and the given `msg`, and returns the amount the predecessor kept. Swaps below
+`min_out` panic, so the attached NEAR is refunded by the runtime. If the predecessor keeps
+nothing, the NEAR is sent back and the swap returns 0.
+*/
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::{
+    env, ext_contract, log, near_bindgen, require, AccountId, Balance, Gas, PanicOnDefault,
+    Promise, PromiseOrValue, PromiseResult,
+};
+
+const GAS_FOR_FT_TRANSFER_CALL: Gas = Gas(50_000_000_000_000);
+const GAS_FOR_ON_SWAP: Gas = Gas(5_000_000_000_000);
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Dex {
+    owner_id: AccountId,
+    fungible_token_account_id: AccountId,
+    /// Smallest units of ESTATES per yoctoNEAR.
+    rate: Balance,
+}
+
+#[ext_contract(ext_self)]
+pub trait SwapCallback {
+    fn on_swap(&mut self, trader_id: AccountId, amount_in: U128) -> U128;
+}
+
+#[near_bindgen]
+impl Dex {
+    #[init]
+    pub fn new(owner_id: AccountId, fungible_token_account_id: AccountId, rate: U128) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        Self { owner_id, fungible_token_account_id, rate: rate.0 }
+    }
+
+    pub fn set_rate(&mut self, rate: U128) {
+        require!(env::predecessor_account_id() == self.owner_id, "Only the owner can set the rate");
+        self.rate = rate.0;
+    }
+
+    /// Swaps the attached NEAR for ESTATES sent to the predecessor.
+    #[payable]
+    pub fn swap_near(
+        &mut self,
+        token_out: AccountId,
+        min_out: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        require!(token_out == self.fungible_token_account_id, "Unknown token");
+        let trader_id = env::predecessor_account_id();
+        let amount_in = env::attached_deposit();
+        let amount_out = amount_in
+            .checked_mul(self.rate)
+            .unwrap_or_else(|| env::panic_str("The amount overflows"));
+        require!(amount_out > 0, "Nothing to swap");
+        require!(amount_out >= min_out.0, "Slippage exceeded");
+        ext_ft_core::ext(self.fungible_token_account_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(GAS_FOR_FT_TRANSFER_CALL)
+            .ft_transfer_call(trader_id.clone(), amount_out.into(), Some("swap".into()), msg)
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_SWAP)
+                    .on_swap(trader_id, amount_in.into()),
+            )
+            .into()
+    }
+
+    pub fn rate(&self) -> U128 {
+        self.rate.into()
+    }
+}
+
+#[near_bindgen]
+impl SwapCallback for Dex {
+    /// Returns the amount the trader kept, as answered by `ft_transfer_call`.
+    #[private]
+    fn on_swap(&mut self, trader_id: AccountId, amount_in: U128) -> U128 {
+        let used = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<U128>(&value).map(|used| used.0).unwrap_or(0)
+            }
+            _ => 0,
+        };
+        if used > 0 {
+            return used.into();
+        }
+        log!("Swap of {} by @{} failed", amount_in.0, trader_id);
+        Promise::new(trader_id).transfer(amount_in.0);
+        U128(0)
+    }
+}
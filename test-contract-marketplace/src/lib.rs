@@ -0,0 +1,407 @@
+/*!
+Parcel marketplace that settles ESTATES-priced deals through `ft_transfer_call`.
+
+Parcels come from the registry of the ESTATES contract. A seller lists a parcel they own
+with `list`, then hands it over to this contract with the registry's `transfer_parcel`, so the
+marketplace can deliver it to the buyer. Until then the listing can't be sold.
+
+Buying and bidding are `ft_transfer_call`s to this contract with one of these messages:
+  - `{"buy": {"listing_id": "0"}}` buys the listing at its price. Anything above the price is
+    refunded, and so is the whole amount if the parcel can't be delivered.
+  - `{"bid": {"listing_id": "0"}}` bids the whole amount. It has to beat the highest bid and stay
+    below the price. The bid it beats is credited back to its bidder.
+  - `{"cancel_bid": {"listing_id": "0"}}` withdraws the sender's highest bid. The whole amount is
+    refunded and the bid is credited back.
+
+A message that can't be settled (unknown listing, bid too low, ...) refunds the whole amount
+instead of failing. Sellers are paid and outbid bidders are refunded on their balance in this
+contract, which they withdraw with `withdraw`.
+*/
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedMap};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, is_promise_success, log, near_bindgen, require, AccountId,
+    Balance, BorshStorageKey, Gas, PanicOnDefault, Promise, PromiseOrValue, PromiseResult,
+};
+
+const GAS_FOR_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_ON_WITHDRAW: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_PARCEL_VIEW: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_ON_LIST: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_PARCEL_TRANSFER: Gas = Gas(15_000_000_000_000);
+const GAS_FOR_ON_PARCEL_DELIVERY: Gas = Gas(10_000_000_000_000);
+
+#[derive(BorshStorageKey, BorshSerialize)]
+enum StorageKey {
+    Listings,
+    Balances,
+    ListedParcels,
+}
+
+/// The part of a parcel of the registry the marketplace needs.
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ParcelView {
+    pub owner_id: AccountId,
+}
+
+/// Parcel registry of the ESTATES contract.
+#[ext_contract(ext_parcels)]
+pub trait ParcelRegistry {
+    fn parcel(&self, parcel_id: U64) -> Option<ParcelView>;
+    fn transfer_parcel(&mut self, parcel_id: U64, receiver_id: AccountId);
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bid {
+    pub bidder_id: AccountId,
+    pub amount: U128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Listing {
+    pub listing_id: U64,
+    pub seller_id: AccountId,
+    pub parcel_id: U64,
+    pub price: U128,
+    pub highest_bid: Option<Bid>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum MarketMessage {
+    Buy { listing_id: U64 },
+    Bid { listing_id: U64 },
+    CancelBid { listing_id: U64 },
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Marketplace {
+    fungible_token_account_id: AccountId,
+    listings: UnorderedMap<u64, Listing>,
+    next_listing_id: u64,
+    /// Parcels with a listing, so a parcel is listed once.
+    listed_parcels: LookupSet<u64>,
+    /// ESTATES that sellers and outbid bidders can withdraw.
+    balances: LookupMap<AccountId, Balance>,
+}
+
+#[ext_contract(ext_self)]
+pub trait MarketplaceCallbacks {
+    fn on_withdraw(&mut self, account_id: AccountId, amount: U128);
+    fn on_list(&mut self, seller_id: AccountId, parcel_id: U64, price: U128) -> U64;
+    fn on_parcel_delivery(
+        &mut self,
+        listing_id: U64,
+        seller_id: AccountId,
+        buyer_id: AccountId,
+        price: U128,
+        paid: U128,
+        from_bid: bool,
+    ) -> U128;
+}
+
+#[near_bindgen]
+impl Marketplace {
+    #[init]
+    pub fn new(fungible_token_account_id: AccountId) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        Self {
+            fungible_token_account_id,
+            listings: UnorderedMap::new(StorageKey::Listings),
+            next_listing_id: 0,
+            listed_parcels: LookupSet::new(StorageKey::ListedParcels),
+            balances: LookupMap::new(StorageKey::Balances),
+        }
+    }
+
+    /// Lists `parcel_id` at `price` ESTATES once the registry confirms the predecessor owns it.
+    /// Returns the listing id. The parcel should then be transferred to this contract.
+    pub fn list(&mut self, parcel_id: U64, price: U128) -> Promise {
+        require!(price.0 > 0, "The price should be a positive number");
+        require!(!self.listed_parcels.contains(&parcel_id.0), "The parcel is already listed");
+        ext_parcels::ext(self.fungible_token_account_id.clone())
+            .with_static_gas(GAS_FOR_PARCEL_VIEW)
+            .parcel(parcel_id)
+            .then(
+                ext_self::ext(env::current_account_id()).with_static_gas(GAS_FOR_ON_LIST).on_list(
+                    env::predecessor_account_id(),
+                    parcel_id,
+                    price,
+                ),
+            )
+    }
+
+    /// Sends the whole balance of the predecessor to their ESTATES account.
+    /// Requires exactly 1 yoctoNEAR attached, which pays for the transfer.
+    #[payable]
+    pub fn withdraw(&mut self) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let amount = self.balances.remove(&account_id).unwrap_or(0);
+        require!(amount > 0, "Nothing to withdraw");
+        ext_ft_core::ext(self.fungible_token_account_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .ft_transfer(account_id.clone(), amount.into(), Some("marketplace withdrawal".into()))
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_WITHDRAW)
+                    .on_withdraw(account_id, amount.into()),
+            )
+    }
+
+    /// Sells the listing to its highest bidder, whose bid is credited back if the parcel can't
+    /// be delivered. Only the seller can call this method. Requires exactly 1 yoctoNEAR attached.
+    #[payable]
+    pub fn accept_bid(&mut self, listing_id: U64) -> Promise {
+        assert_one_yocto();
+        let mut listing = self.unwrap_own_listing(listing_id);
+        let bid = listing.highest_bid.take().unwrap_or_else(|| env::panic_str("No bids"));
+        self.listings.insert(&listing_id.0, &listing);
+        self.deliver(listing, bid.bidder_id, bid.amount.0, true)
+    }
+
+    /// Removes the listing, credits its highest bid back and returns the parcel to the seller if
+    /// it was handed over. Only the seller can call this method. Requires exactly 1 yoctoNEAR
+    /// attached.
+    #[payable]
+    pub fn cancel_listing(&mut self, listing_id: U64) {
+        assert_one_yocto();
+        let listing = self.unwrap_own_listing(listing_id);
+        self.close_listing(&listing);
+        // Fails without consequences if the parcel was never handed over.
+        ext_parcels::ext(self.fungible_token_account_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(GAS_FOR_PARCEL_TRANSFER)
+            .transfer_parcel(listing.parcel_id, listing.seller_id);
+    }
+
+    pub fn listing(&self, listing_id: U64) -> Option<Listing> {
+        self.listings.get(&listing_id.0)
+    }
+
+    pub fn listings(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Listing> {
+        self.listings
+            .values()
+            .skip(from_index.map(|index| index.0 as usize).unwrap_or(0))
+            .take(limit.map(|limit| limit as usize).unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    /// ESTATES that `account_id` can withdraw.
+    pub fn balance_of(&self, account_id: AccountId) -> U128 {
+        self.balances.get(&account_id).unwrap_or(0).into()
+    }
+}
+
+#[near_bindgen]
+impl MarketplaceCallbacks for Marketplace {
+    /// Credits the amount back if the transfer failed, e.g. the account isn't registered.
+    #[private]
+    fn on_withdraw(&mut self, account_id: AccountId, amount: U128) {
+        if !is_promise_success() {
+            log!("Withdrawal of {} by @{} failed", amount.0, account_id);
+            self.credit(&account_id, amount.0);
+        }
+    }
+
+    /// Creates the listing if the registry says `seller_id` owns the parcel.
+    #[private]
+    fn on_list(&mut self, seller_id: AccountId, parcel_id: U64, price: U128) -> U64 {
+        let parcel = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<Option<ParcelView>>(&value).ok().flatten()
+            }
+            _ => None,
+        };
+        require!(
+            parcel.map(|parcel| parcel.owner_id) == Some(seller_id.clone()),
+            "Only the owner of the parcel can list it"
+        );
+        require!(self.listed_parcels.insert(&parcel_id.0), "The parcel is already listed");
+        let listing_id = self.next_listing_id;
+        self.next_listing_id += 1;
+        let listing = Listing {
+            listing_id: listing_id.into(),
+            seller_id,
+            parcel_id,
+            price,
+            highest_bid: None,
+        };
+        self.listings.insert(&listing_id, &listing);
+        log!("listed parcel {} as listing {}", parcel_id.0, listing_id);
+        listing.listing_id
+    }
+
+    /// Pays the seller and closes the listing if the parcel reached the buyer. Returns the
+    /// amount of `paid` to refund: the excess over the price, or everything if the delivery
+    /// failed. An accepted bid is credited back instead.
+    #[private]
+    fn on_parcel_delivery(
+        &mut self,
+        listing_id: U64,
+        seller_id: AccountId,
+        buyer_id: AccountId,
+        price: U128,
+        paid: U128,
+        from_bid: bool,
+    ) -> U128 {
+        if !is_promise_success() {
+            log!("refunding {}: the parcel of listing {} can't be delivered", paid.0, listing_id.0);
+            if from_bid {
+                self.credit(&buyer_id, paid.0);
+                return U128(0);
+            }
+            return paid;
+        }
+        self.credit(&seller_id, price.0);
+        // The seller may have cancelled the listing in the meantime.
+        if let Some(listing) = self.listings.get(&listing_id.0) {
+            self.close_listing(&listing);
+        }
+        log!("sold listing {} of @{} to @{} for {}", listing_id.0, seller_id, buyer_id, price.0);
+        U128(paid.0 - price.0)
+    }
+}
+
+impl Marketplace {
+    fn credit(&mut self, account_id: &AccountId, amount: Balance) {
+        let balance = self.balances.get(account_id).unwrap_or(0);
+        self.balances.insert(account_id, &(balance + amount));
+    }
+
+    fn unwrap_own_listing(&self, listing_id: U64) -> Listing {
+        let listing =
+            self.listings.get(&listing_id.0).unwrap_or_else(|| env::panic_str("No such listing"));
+        require!(
+            listing.seller_id == env::predecessor_account_id(),
+            "Only the seller can call this method"
+        );
+        listing
+    }
+
+    /// Credits the highest bid back and removes the listing.
+    fn close_listing(&mut self, listing: &Listing) {
+        if let Some(bid) = listing.highest_bid.as_ref() {
+            self.credit(&bid.bidder_id, bid.amount.0);
+        }
+        self.listings.remove(&listing.listing_id.0);
+        self.listed_parcels.remove(&listing.parcel_id.0);
+    }
+
+    /// Transfers the parcel of the listing, which this contract should own by now, to
+    /// `buyer_id`. The sale is settled by `on_parcel_delivery`.
+    fn deliver(
+        &self,
+        listing: Listing,
+        buyer_id: AccountId,
+        paid: Balance,
+        from_bid: bool,
+    ) -> Promise {
+        let price = if from_bid { paid } else { listing.price.0 };
+        ext_parcels::ext(self.fungible_token_account_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(GAS_FOR_PARCEL_TRANSFER)
+            .transfer_parcel(listing.parcel_id, buyer_id.clone())
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_PARCEL_DELIVERY)
+                    .on_parcel_delivery(
+                        listing.listing_id,
+                        listing.seller_id,
+                        buyer_id,
+                        price.into(),
+                        paid.into(),
+                        from_bid,
+                    ),
+            )
+    }
+
+    /// Settles `message` with the `amount` ESTATES `sender_id` sent. Returns the unused amount,
+    /// or the reason why nothing could be done.
+    fn settle(
+        &mut self,
+        sender_id: AccountId,
+        amount: Balance,
+        message: MarketMessage,
+    ) -> Result<PromiseOrValue<U128>, &'static str> {
+        match message {
+            MarketMessage::Buy { listing_id } => {
+                let listing = self.listings.get(&listing_id.0).ok_or("no such listing")?;
+                if listing.seller_id == sender_id {
+                    return Err("the seller can't buy their own listing");
+                }
+                if amount < listing.price.0 {
+                    return Err("the amount is less than the price");
+                }
+                Ok(self.deliver(listing, sender_id, amount, false).into())
+            }
+            MarketMessage::Bid { listing_id } => {
+                let mut listing = self.listings.get(&listing_id.0).ok_or("no such listing")?;
+                if listing.seller_id == sender_id {
+                    return Err("the seller can't bid on their own listing");
+                }
+                if amount >= listing.price.0 {
+                    return Err("the bid reaches the price, buy instead");
+                }
+                if let Some(bid) = listing.highest_bid.take() {
+                    if amount <= bid.amount.0 {
+                        return Err("the bid doesn't beat the highest bid");
+                    }
+                    self.credit(&bid.bidder_id, bid.amount.0);
+                }
+                listing.highest_bid = Some(Bid { bidder_id: sender_id, amount: amount.into() });
+                self.listings.insert(&listing_id.0, &listing);
+                Ok(PromiseOrValue::Value(U128(0)))
+            }
+            MarketMessage::CancelBid { listing_id } => {
+                let mut listing = self.listings.get(&listing_id.0).ok_or("no such listing")?;
+                match listing.highest_bid.take() {
+                    Some(bid) if bid.bidder_id == sender_id => {
+                        self.credit(&bid.bidder_id, bid.amount.0);
+                        self.listings.insert(&listing_id.0, &listing);
+                        Ok(PromiseOrValue::Value(U128(amount)))
+                    }
+                    _ => Err("the sender doesn't hold the highest bid"),
+                }
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Marketplace {
+    /// Settles the `msg` action and returns the exact amount to refund to `sender_id`, once the
+    /// parcel is delivered for a purchase.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        // Verifying that we were called by fungible token contract that we expect.
+        assert_eq!(
+            &env::predecessor_account_id(),
+            &self.fungible_token_account_id,
+            "Only supports the one fungible token contract"
+        );
+        let message: MarketMessage = near_sdk::serde_json::from_str(&msg)
+            .unwrap_or_else(|_| env::panic_str("Invalid transfer message"));
+        match self.settle(sender_id, amount.0, message) {
+            Ok(unused_amount) => unused_amount,
+            Err(reason) => {
+                log!("refunding {}: {}", amount.0, reason);
+                PromiseOrValue::Value(amount)
+            }
+        }
+    }
+}
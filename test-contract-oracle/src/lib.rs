@@ -0,0 +1,59 @@
+/*!
+Mock price-feed oracle for the sandbox tests of the USD prices of the fungible token.
+
+Its owner sets the price of an asset with `set_price`, and `get_price` answers it the way the
+token expects from a real oracle. The observation time defaults to the current block, and can be
+set in the past to test stale prices.
+*/
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, require, AccountId, PanicOnDefault};
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceData {
+    /// USD per whole token, times `10^decimals`.
+    pub price: U128,
+    /// Half width of the confidence interval around the price, in the same unit.
+    pub confidence: U128,
+    pub decimals: u8,
+    /// Timestamp in nanoseconds of the observation.
+    pub timestamp: U64,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Oracle {
+    owner_id: AccountId,
+    prices: UnorderedMap<String, PriceData>,
+}
+
+#[near_bindgen]
+impl Oracle {
+    #[init]
+    pub fn new(owner_id: AccountId) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        Self { owner_id, prices: UnorderedMap::new(b"p") }
+    }
+
+    /// Sets the price of `asset_id`, observed at `timestamp` or now. Only the owner can call
+    /// this method.
+    pub fn set_price(
+        &mut self,
+        asset_id: String,
+        price: U128,
+        confidence: U128,
+        decimals: u8,
+        timestamp: Option<U64>,
+    ) {
+        require!(env::predecessor_account_id() == self.owner_id, "Only the owner can set prices");
+        let timestamp = timestamp.unwrap_or_else(|| env::block_timestamp().into());
+        self.prices.insert(&asset_id, &PriceData { price, confidence, decimals, timestamp });
+    }
+
+    pub fn get_price(&self, asset_id: String) -> Option<PriceData> {
+        self.prices.get(&asset_id)
+    }
+}